@@ -2,5 +2,16 @@
 MEMORY
 {
   FLASH : ORIGIN = 0x08000000, LENGTH = 64K
-  RAM : ORIGIN = 0x20000000, LENGTH = 20K
+  RAM : ORIGIN = 0x20000000, LENGTH = 20K - 512
+  /* the end of RAM is kept for the crash log, see src/crash.rs */
+  CRASHLOG : ORIGIN = 0x20004E00, LENGTH = 512
 }
+
+SECTIONS
+{
+  /* nothing initialises this, so it survives a reset */
+  .uninit.crashlog (NOLOAD) : ALIGN(4)
+  {
+    KEEP(*(.uninit.crashlog));
+  } > CRASHLOG
+} INSERT AFTER .bss;
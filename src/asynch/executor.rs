@@ -0,0 +1,207 @@
+//! An executor for a fixed set of tasks, which sleeps while none are ready
+//!
+//! There is one set of wake flags, so only one executor can run at a time.
+
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use core::task::{Context, RawWaker, RawWakerVTable, Waker};
+
+use super::time;
+
+/// The most tasks an executor can run, one bit each
+pub const MAX_TASKS: usize = 32;
+
+/// The tasks to poll, by bit
+static READY: AtomicU32 = AtomicU32::new(0);
+/// The task which is being polled, for the timers
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+
+static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, wake, wake, drop);
+
+fn clone(data: *const ()) -> RawWaker {
+    RawWaker::new(data, &VTABLE)
+}
+
+fn wake(data: *const ()) {
+    wake_task(data as usize);
+}
+
+fn drop(_: *const ()) {}
+
+/// Mark the task to be polled again
+pub(crate) fn wake_task(index: usize) {
+    READY.fetch_or(1 << index, Ordering::SeqCst);
+}
+
+/// The index of the task which is being polled
+pub(crate) fn current() -> usize {
+    CURRENT.load(Ordering::SeqCst)
+}
+
+/// A task, which is pinned by the caller
+///
+/// ```ignore
+/// let task = pin!(async { ... });
+/// ```
+pub type Task<'a> = Pin<&'a mut dyn Future<Output = ()>>;
+
+/// Runs `N` tasks until they are all done
+pub struct Executor<'a, const N: usize> {
+    tasks: [Option<Task<'a>>; N],
+}
+
+impl<'a, const N: usize> Executor<'a, N> {
+    /// Take the tasks, which are all polled first thing
+    pub fn new(tasks: [Task<'a>; N]) -> Self {
+        assert!(N <= MAX_TASKS, "too many tasks");
+        READY.store(((1u64 << N) - 1) as u32, Ordering::SeqCst);
+
+        Self {
+            tasks: tasks.map(Some),
+        }
+    }
+
+    /// Poll every task which was woken, and tell if any are left
+    ///
+    /// On the host, alternate this with [`time::tick`] instead of calling
+    /// [`Executor::run`].
+    pub fn poll(&mut self) -> bool {
+        let ready = READY.swap(0, Ordering::SeqCst);
+        for (index, slot) in self.tasks.iter_mut().enumerate() {
+            let task = match slot {
+                Some(task) if ready & (1 << index) != 0 => task,
+                _ => continue,
+            };
+
+            CURRENT.store(index, Ordering::SeqCst);
+            time::forget(index);
+
+            // SAFETY: the data is a plain index, which the vtable functions never dereference
+            let waker = unsafe { Waker::from_raw(RawWaker::new(index as *const (), &VTABLE)) };
+            if task
+                .as_mut()
+                .poll(&mut Context::from_waker(&waker))
+                .is_ready()
+            {
+                *slot = None;
+            }
+        }
+
+        self.tasks.iter().any(Option::is_some)
+    }
+
+    /// Run until all tasks are done, sleeping while none are ready
+    pub fn run(mut self) {
+        while self.poll() {
+            sleep();
+        }
+    }
+}
+
+/// Wait for an interrupt, unless a task was woken in the meantime
+fn sleep() {
+    // an interrupt which comes in between still ends the wfi
+    #[cfg(target_arch = "arm")]
+    cortex_m::interrupt::free(|_| {
+        if READY.load(Ordering::SeqCst) == 0 {
+            cortex_m::asm::wfi();
+        }
+    });
+
+    #[cfg(not(target_arch = "arm"))]
+    core::hint::spin_loop();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::{Cell, RefCell};
+    use core::pin::pin;
+    use core::task::Poll;
+    use std::vec::Vec;
+
+    /// Pending once, after waking itself or not
+    fn pending_once(wake: bool) -> impl Future<Output = ()> {
+        let mut polled = false;
+        core::future::poll_fn(move |cx| {
+            if polled {
+                return Poll::Ready(());
+            }
+            polled = true;
+            if wake {
+                cx.waker().wake_by_ref();
+            }
+            Poll::Pending
+        })
+    }
+
+    #[test]
+    fn polls_every_task_first_and_then_the_woken_ones() {
+        let _host = super::super::host();
+        let log = RefCell::new(Vec::new());
+        let a = pin!(async {
+            log.borrow_mut().push("a");
+            pending_once(true).await;
+            log.borrow_mut().push("a again");
+        });
+        let b = pin!(async {
+            log.borrow_mut().push("b");
+            pending_once(false).await;
+            log.borrow_mut().push("b again");
+        });
+        let mut executor = Executor::new([a as Task, b]);
+
+        assert!(executor.poll());
+        assert_eq!(*log.borrow(), ["a", "b"]);
+        // only a woke itself, so b is left pending
+        assert!(executor.poll());
+        assert!(executor.poll());
+        assert_eq!(*log.borrow(), ["a", "b", "a again"]);
+    }
+
+    #[test]
+    fn a_waker_from_elsewhere_wakes_its_task() {
+        let _host = super::super::host();
+        let waker = RefCell::new(None);
+        let polls = Cell::new(0);
+        let task = pin!(core::future::poll_fn(|cx| {
+            polls.set(polls.get() + 1);
+            if polls.get() == 1 {
+                *waker.borrow_mut() = Some(cx.waker().clone());
+                Poll::Pending
+            } else {
+                Poll::Ready(())
+            }
+        }));
+        let idle = pin!(core::future::pending::<()>());
+        let mut executor = Executor::new([idle as Task, task]);
+
+        assert!(executor.poll());
+        assert!(executor.poll());
+        assert_eq!(polls.get(), 1);
+
+        waker.borrow_mut().take().unwrap().wake();
+        assert!(executor.poll());
+        assert_eq!(polls.get(), 2);
+    }
+
+    #[test]
+    fn finished_tasks_are_dropped() {
+        let _host = super::super::host();
+        let done = Cell::new(0);
+        let a = pin!(async { done.set(done.get() + 1) });
+        let b = pin!(async {
+            pending_once(true).await;
+            done.set(done.get() + 1);
+        });
+        let mut executor = Executor::new([a as Task, b]);
+
+        assert!(executor.poll());
+        assert!(!executor.poll());
+        // waking a finished task does nothing
+        wake_task(0);
+        assert!(!executor.poll());
+        assert_eq!(done.get(), 2);
+    }
+}
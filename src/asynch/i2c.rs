@@ -0,0 +1,76 @@
+//! A blocking I2C bus behind the async trait
+//!
+//! The HAL has no async I2C, so every transfer still blocks. At 100 kHz a
+//! transfer of a few bytes takes well under a millisecond.
+
+use core::fmt::Debug;
+use embedded_hal::blocking::i2c::{Read, Write, WriteRead};
+use embedded_hal_async::i2c::{self, ErrorKind, ErrorType, I2c, Operation};
+
+use crate::error::Error;
+
+/// An error of the wrapped bus
+#[derive(Debug)]
+pub struct BusError<E>(pub E);
+
+impl<E: Debug> i2c::Error for BusError<E> {
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::Other
+    }
+}
+
+impl<E: Into<Error>> From<BusError<E>> for Error {
+    fn from(item: BusError<E>) -> Self {
+        item.0.into()
+    }
+}
+
+/// An embedded-hal 0.2 bus, as an [`I2c`]
+pub struct Blocking<I>(pub I);
+
+impl<I, E> ErrorType for Blocking<I>
+where
+    I: Read<Error = E> + Write<Error = E> + WriteRead<Error = E>,
+    E: Debug,
+{
+    type Error = BusError<E>;
+}
+
+impl<I, E> I2c for Blocking<I>
+where
+    I: Read<Error = E> + Write<Error = E> + WriteRead<Error = E>,
+    E: Debug,
+{
+    async fn read(&mut self, address: u8, read: &mut [u8]) -> Result<(), Self::Error> {
+        self.0.read(address, read).map_err(BusError)
+    }
+
+    async fn write(&mut self, address: u8, write: &[u8]) -> Result<(), Self::Error> {
+        self.0.write(address, write).map_err(BusError)
+    }
+
+    async fn write_read(
+        &mut self,
+        address: u8,
+        write: &[u8],
+        read: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        self.0.write_read(address, write, read).map_err(BusError)
+    }
+
+    /// Run the operations one by one, with a stop in between
+    async fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        for operation in operations {
+            match operation {
+                Operation::Read(read) => self.0.read(address, read),
+                Operation::Write(write) => self.0.write(address, write),
+            }
+            .map_err(BusError)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,35 @@
+//! The character LCD, see [`crate::lcd`]
+
+use embedded_hal_async::delay::DelayNs;
+use embedded_hal_async::i2c::I2c;
+
+use crate::debug;
+use crate::lcd::{ADDRESS, DISPLAY_ON, FUNCTION_SET, SETUP};
+
+/// Wake up and clear the display, awaiting the second this takes
+pub async fn init<I: I2c, D: DelayNs>(bus: &mut I, delay: &mut D) -> Result<(), I::Error> {
+    for _ in 0..=3 {
+        debug!("function set");
+        bus.write(ADDRESS, &[FUNCTION_SET]).await?;
+        delay.delay_ms(100).await;
+    }
+
+    debug!("busy flag");
+    let mut buffer = [0u8];
+    bus.write_read(ADDRESS, &[], &mut buffer).await?;
+    debug!("ret: {:08b}", buffer[0]);
+
+    for (name, command) in SETUP {
+        debug!("{}", name);
+        bus.write(ADDRESS, &[command]).await?;
+        delay.delay_ms(100).await;
+    }
+
+    Ok(())
+}
+
+/// Write a character
+pub async fn write_char<I: I2c>(bus: &mut I, character: u8) -> Result<(), I::Error> {
+    bus.write(ADDRESS, &[character]).await?;
+    bus.write(ADDRESS, &[DISPLAY_ON]).await
+}
@@ -0,0 +1,76 @@
+//! Scrolling text over the matrix
+
+use embedded_hal_async::delay::DelayNs;
+use max7219::{connectors::Connector, DataError, MAX7219};
+
+use crate::patterns;
+
+/// The frame of scrolling text, `position` rows in
+///
+/// Characters without a glyph are left blank. The text wraps around.
+pub fn frame(text: &[u8], position: usize) -> [u8; 8] {
+    let mut frame = [0; 8];
+    let length = text.len() * 4;
+    if length == 0 {
+        return frame;
+    }
+
+    // the text runs from the bottom row up, starting with the first two characters, like `main`
+    for (i, row) in frame.iter_mut().enumerate() {
+        let index = (i + length * 8 - 8 - position % length) % length;
+        let character = text[text.len() - 1 - index / 4];
+        *row = patterns::glyph(character).map_or(0, |glyph| glyph[index % 4]);
+    }
+    frame
+}
+
+/// Scroll the text over the matrix once, a row every `row_ms`
+pub async fn scroll<C, D>(
+    matrix: &mut MAX7219<C>,
+    text: &[u8],
+    row_ms: u32,
+    delay: &mut D,
+) -> Result<(), DataError>
+where
+    C: Connector,
+    D: DelayNs,
+{
+    for position in 0..text.len() * 4 {
+        matrix.write_raw(0, &frame(text, position))?;
+        delay.delay_ms(row_ms).await;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn starts_with_the_first_two_characters_from_the_bottom() {
+        let [a, b] = [
+            patterns::glyph(b'a').unwrap(),
+            patterns::glyph(b'b').unwrap(),
+        ];
+        let frame = frame(b"ab", 0);
+        assert_eq!(frame[..4], b[..]);
+        assert_eq!(frame[4..], a[..]);
+    }
+
+    #[test]
+    fn scrolls_a_row_at_a_time_and_wraps_around() {
+        let text = b"hello ";
+        for position in 0..text.len() * 4 {
+            let now = frame(text, position);
+            let next = frame(text, position + 1);
+            assert_eq!(next[1..], now[..7], "{}", position);
+            assert_eq!(frame(text, position + text.len() * 4), now);
+        }
+    }
+
+    #[test]
+    fn unknown_characters_and_no_text_are_blank() {
+        assert_eq!(frame(b"\x01\x02", 3), [0; 8]);
+        assert_eq!(frame(b"", 5), [0; 8]);
+    }
+}
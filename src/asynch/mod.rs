@@ -0,0 +1,28 @@
+//! Async versions of the drivers, for awaiting several devices at once
+//!
+//! The [`executor::Executor`] polls the tasks which were woken and sleeps in
+//! between. Its timers are driven by TIM4, see [`time::start`]. Bit-banged
+//! protocols like 1-Wire still block, only the waits in between are awaited.
+//!
+//! ```ignore
+//! time::start(tim4, &clocks, &mut apb1);
+//! let keys = pin!(async { ... });
+//! let sampler = pin!(async { ... });
+//! Executor::new([keys as Task, sampler]).run();
+//! ```
+
+pub mod executor;
+pub mod i2c;
+pub mod lcd;
+pub mod matrix;
+pub mod numpad;
+pub mod probe;
+pub mod time;
+
+/// The executor and the timers keep their state in statics, so the tests
+/// which use them take turns
+#[cfg(test)]
+pub(crate) fn host() -> std::sync::MutexGuard<'static, ()> {
+    static HOST: std::sync::Mutex<()> = std::sync::Mutex::new(());
+    HOST.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
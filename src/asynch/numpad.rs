@@ -0,0 +1,37 @@
+//! Waiting for the numpad, which is polled in between timers
+
+use core::convert::Infallible;
+use embedded_hal_async::delay::DelayNs;
+
+use crate::numpad::{Button, Buttons, Numpad};
+
+/// Wait until a button is pressed, and return all which are
+pub async fn pressed<D, E>(
+    numpad: &mut Numpad,
+    delay: &mut D,
+    interval_ms: u32,
+) -> Result<Button, E>
+where
+    D: DelayNs,
+    E: From<Infallible>,
+{
+    loop {
+        let buttons = numpad.read::<E>()?;
+        if buttons != Buttons::None {
+            return Ok(buttons);
+        }
+        delay.delay_ms(interval_ms).await;
+    }
+}
+
+/// Wait until all buttons are released
+pub async fn released<D, E>(numpad: &mut Numpad, delay: &mut D, interval_ms: u32) -> Result<(), E>
+where
+    D: DelayNs,
+    E: From<Infallible>,
+{
+    while numpad.read::<E>()? != Buttons::None {
+        delay.delay_ms(interval_ms).await;
+    }
+    Ok(())
+}
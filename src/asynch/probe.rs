@@ -0,0 +1,32 @@
+//! Sampling the DS18B20 without blocking during the conversion
+
+use ds18b20::{Ds18b20, Resolution};
+use embedded_hal::blocking::delay::DelayUs;
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+use embedded_hal_async::delay::DelayNs;
+use one_wire_bus::{OneWire, OneWireError};
+
+use crate::probe::read_temperature;
+use crate::temperature::Temperature;
+
+/// Start a measurement, await the conversion and read the result
+///
+/// The 1-Wire transfers themselves use the blocking `bit_delay`, since they
+/// need microsecond timing.
+pub async fn measure<T, U, D, P>(
+    probe: &Ds18b20,
+    owb: &mut OneWire<T>,
+    bit_delay: &mut U,
+    delay: &mut D,
+) -> Result<Temperature, OneWireError<P>>
+where
+    T: InputPin<Error = P> + OutputPin<Error = P>,
+    U: DelayUs<u16>,
+    D: DelayNs,
+{
+    probe.start_temp_measurement(owb, bit_delay)?;
+    delay
+        .delay_ms(Resolution::Bits12.max_measurement_time_millis().into())
+        .await;
+    read_temperature(probe, owb, bit_delay)
+}
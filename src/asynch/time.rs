@@ -0,0 +1,228 @@
+//! Millisecond timers, ticked by TIM4 or by hand on the host
+
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU32, Ordering};
+use core::task::{Context, Poll};
+use embedded_hal_async::delay::DelayNs;
+use stm32f1xx_hal::pac;
+use stm32f1xx_hal::prelude::*;
+use stm32f1xx_hal::rcc::{Clocks, APB1};
+use stm32f1xx_hal::timer::Event;
+
+use super::executor::{self, MAX_TASKS};
+use crate::scheduler::reached;
+
+/// Milliseconds since [`start`], wrapping around
+static NOW: AtomicU32 = AtomicU32::new(0);
+/// The tasks waiting for a timer, by bit
+static WAITING: AtomicU32 = AtomicU32::new(0);
+/// The earliest deadline of each waiting task
+static DEADLINES: [AtomicU32; MAX_TASKS] = [const { AtomicU32::new(0) }; MAX_TASKS];
+
+/// Tick every millisecond from TIM4
+///
+/// The binary handles the interrupt by calling [`on_tim4`]:
+///
+/// ```ignore
+/// #[interrupt]
+/// fn TIM4() {
+///     time::on_tim4();
+/// }
+/// ```
+pub fn start(tim4: pac::TIM4, clocks: &Clocks, apb1: &mut APB1) {
+    let mut timer = stm32f1xx_hal::timer::Timer::tim4(tim4, clocks, apb1).start_count_down(1.khz());
+    timer.listen(Event::Update);
+
+    // SAFETY: the handler only clears the flag and touches atomics
+    unsafe { cortex_m::peripheral::NVIC::unmask(pac::Interrupt::TIM4) };
+}
+
+/// Acknowledge the interrupt of TIM4, and tick a millisecond
+pub fn on_tim4() {
+    // SAFETY: only the update flag is cleared, which nothing else uses
+    unsafe { &*pac::TIM4::ptr() }
+        .sr
+        .modify(|_, w| w.uif().clear_bit());
+    tick(1);
+}
+
+pub fn now() -> u32 {
+    NOW.load(Ordering::SeqCst)
+}
+
+/// Advance the time, and wake the tasks whose timers expired
+pub fn tick(ms: u32) {
+    let now = NOW.fetch_add(ms, Ordering::SeqCst).wrapping_add(ms);
+
+    let waiting = WAITING.load(Ordering::SeqCst);
+    for (index, deadline) in DEADLINES.iter().enumerate() {
+        let bit = 1 << index;
+        if waiting & bit != 0 && reached(now, deadline.load(Ordering::SeqCst)) {
+            WAITING.fetch_and(!bit, Ordering::SeqCst);
+            executor::wake_task(index);
+        }
+    }
+}
+
+/// Wake the task being polled at the deadline, or earlier
+fn register(deadline: u32) {
+    let index = executor::current();
+    let bit = 1 << index;
+
+    // keep the earliest deadline of the timers the task awaits
+    let stored = DEADLINES[index].load(Ordering::SeqCst);
+    if WAITING.load(Ordering::SeqCst) & bit == 0 || !reached(deadline, stored) {
+        DEADLINES[index].store(deadline, Ordering::SeqCst);
+    }
+    WAITING.fetch_or(bit, Ordering::SeqCst);
+}
+
+/// Stop waking the task for its timers, as it is polled anyway
+pub(crate) fn forget(index: usize) {
+    WAITING.fetch_and(!(1 << index), Ordering::SeqCst);
+}
+
+/// A future which is ready at a deadline
+///
+/// It does not use the waker of the context, so it only works on the
+/// [`executor::Executor`].
+#[derive(Debug, Clone, Copy)]
+pub struct Timer {
+    deadline: u32,
+}
+
+impl Timer {
+    pub fn at(deadline: u32) -> Self {
+        Self { deadline }
+    }
+
+    /// Wait at least `ms`
+    ///
+    /// The current millisecond is already partly over, so one is added.
+    pub fn after_ms(ms: u32) -> Self {
+        Self::at(now().wrapping_add(ms).wrapping_add(1))
+    }
+}
+
+impl Future for Timer {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<()> {
+        if reached(now(), self.deadline) {
+            return Poll::Ready(());
+        }
+
+        // the tick might have passed before registering
+        register(self.deadline);
+        if reached(now(), self.deadline) {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/// An async delay with millisecond resolution, rounding up
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Delay;
+
+impl DelayNs for Delay {
+    async fn delay_ns(&mut self, ns: u32) {
+        Timer::after_ms(ns.div_ceil(1_000_000)).await;
+    }
+
+    async fn delay_us(&mut self, us: u32) {
+        Timer::after_ms(us.div_ceil(1000)).await;
+    }
+
+    async fn delay_ms(&mut self, ms: u32) {
+        Timer::after_ms(ms).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asynch::executor::{Executor, Task};
+    use core::cell::Cell;
+    use core::pin::pin;
+
+    /// Poll and tick a millisecond at a time, and tell how long it took
+    fn run<const N: usize>(mut executor: Executor<N>, limit: u32) -> u32 {
+        let start = now();
+        while executor.poll() {
+            assert!(now().wrapping_sub(start) < limit, "still running");
+            tick(1);
+        }
+        now().wrapping_sub(start)
+    }
+
+    /// The time when the future is done
+    async fn at_end(future: impl Future, end: &Cell<u32>) {
+        future.await;
+        end.set(now());
+    }
+
+    #[test]
+    fn timers_wait_at_least_their_time() {
+        let _host = crate::asynch::host();
+        let start = now();
+        let (short, long) = (Cell::new(0), Cell::new(0));
+        let a = pin!(at_end(Timer::after_ms(5), &short));
+        let b = pin!(at_end(Timer::after_ms(30), &long));
+        run(Executor::new([a as Task, b]), 100);
+
+        assert_eq!(short.get().wrapping_sub(start), 6);
+        assert_eq!(long.get().wrapping_sub(start), 31);
+    }
+
+    #[test]
+    fn the_earlier_of_two_timers_wakes_the_task() {
+        let _host = crate::asynch::host();
+        let start = now();
+        let end = Cell::new(0);
+        // like a timeout next to a delay, the later one registers first
+        let mut timeout = Timer::after_ms(50);
+        let mut delay = Timer::after_ms(10);
+        let first = core::future::poll_fn(|cx| {
+            let timeout = Pin::new(&mut timeout).poll(cx);
+            let delay = Pin::new(&mut delay).poll(cx);
+            if timeout.is_ready() || delay.is_ready() {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        });
+        let task = pin!(at_end(first, &end));
+        run(Executor::new([task as Task]), 100);
+
+        assert_eq!(end.get().wrapping_sub(start), 11);
+    }
+
+    #[test]
+    fn deadlines_across_the_wraparound() {
+        let _host = crate::asynch::host();
+        tick((u32::MAX - 5).wrapping_sub(now()));
+        let start = now();
+        let end = Cell::new(0);
+        let task = pin!(at_end(Timer::after_ms(10), &end));
+        run(Executor::new([task as Task]), 100);
+
+        assert_eq!(end.get(), start.wrapping_add(11));
+        assert!(end.get() < start);
+    }
+
+    #[test]
+    fn the_delay_rounds_up_to_milliseconds() {
+        let _host = crate::asynch::host();
+        let mut a = Delay;
+        let mut b = Delay;
+        let task = pin!(async {
+            a.delay_us(1500).await;
+            b.delay_ns(1).await;
+        });
+        // 2 ms and 1 ms, each with the millisecond which already started
+        assert_eq!(run(Executor::new([task as Task]), 100), 5);
+    }
+}
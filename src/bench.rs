@@ -0,0 +1,305 @@
+//! The commands of the pin tester, which make it a GPIO multimeter
+//!
+//! Every line is a command and the pin it acts on, in any case:
+//!
+//! | command                         | what it does                          |
+//! |---------------------------------|---------------------------------------|
+//! | `in PA5 [up\|down\|floating]`   | an input, pulled down unless given    |
+//! | `out PA5 high\|low [od]`        | a push-pull or open-drain output      |
+//! | `square PA5 1000`               | a square wave at the frequency in Hz  |
+//! | `read PA5`                      | the level of the pin                  |
+//! | `adc PA5`                       | the voltage, on PA0-PA7, PB0 and PB1  |
+//!
+//! ```ignore
+//! let mut line = Line::new();
+//! if let Some(text) = line.push(byte) {
+//!     match Command::parse(text) {}
+//! }
+//! ```
+
+use core::fmt;
+
+use crate::pins::{Pin, Pull};
+
+/// The highest frequency of a square wave, which the pin tester toggles
+pub const MAX_SQUARE_HZ: u32 = 10_000;
+
+/// How an output drives its pin
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Drive {
+    PushPull,
+    /// Only drives low, and lets go of the line when high
+    OpenDrain,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Input(Pin, Pull),
+    Output { pin: Pin, drive: Drive, high: bool },
+    Square { pin: Pin, hz: u32 },
+    Read(Pin),
+    Analog(Pin),
+}
+
+/// Why a line is not a command
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseError {
+    Command,
+    Pin,
+    Argument,
+    /// The pin has no ADC channel
+    NotAnalog,
+    /// The frequency is 0 or above [`MAX_SQUARE_HZ`]
+    Frequency,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str(match self {
+            Self::Command => "unknown command, try in, out, square, read or adc",
+            Self::Pin => "expected a pin like PA5",
+            Self::Argument => "unexpected argument",
+            Self::NotAnalog => "only PA0-PA7, PB0 and PB1 have an ADC channel",
+            Self::Frequency => "the frequency is out of range",
+        })
+    }
+}
+
+impl Command {
+    /// Parse a line, without its line ending
+    pub fn parse(line: &str) -> Result<Self, ParseError> {
+        let mut words = line.split_ascii_whitespace();
+        let command = words.next().ok_or(ParseError::Command)?;
+        let named = words.next().and_then(Pin::parse);
+        let pin = || named.ok_or(ParseError::Pin);
+        let is =
+            |word: &str, options: &[&str]| options.iter().any(|o| word.eq_ignore_ascii_case(o));
+
+        let parsed = if is(command, &["in", "input"]) {
+            let pull = match words.next() {
+                None => Pull::Down,
+                Some(word) if is(word, &["up"]) => Pull::Up,
+                Some(word) if is(word, &["down"]) => Pull::Down,
+                Some(word) if is(word, &["floating", "float"]) => Pull::Floating,
+                Some(_) => return Err(ParseError::Argument),
+            };
+            Self::Input(pin()?, pull)
+        } else if is(command, &["out", "output"]) {
+            let pin = pin()?;
+            let high = match words.next() {
+                Some(word) if is(word, &["high", "1"]) => true,
+                Some(word) if is(word, &["low", "0"]) => false,
+                _ => return Err(ParseError::Argument),
+            };
+            let drive = match words.next() {
+                None => Drive::PushPull,
+                Some(word) if is(word, &["pp", "push-pull"]) => Drive::PushPull,
+                Some(word) if is(word, &["od", "open-drain"]) => Drive::OpenDrain,
+                Some(_) => return Err(ParseError::Argument),
+            };
+            Self::Output { pin, drive, high }
+        } else if is(command, &["square", "sq"]) {
+            let pin = pin()?;
+            let hz: u32 = words
+                .next()
+                .and_then(|word| word.parse().ok())
+                .ok_or(ParseError::Argument)?;
+            if hz == 0 || hz > MAX_SQUARE_HZ {
+                return Err(ParseError::Frequency);
+            }
+            Self::Square { pin, hz }
+        } else if is(command, &["read", "r"]) {
+            Self::Read(pin()?)
+        } else if is(command, &["adc", "analog"]) {
+            let pin = pin()?;
+            pin.adc_channel().ok_or(ParseError::NotAnalog)?;
+            Self::Analog(pin)
+        } else {
+            return Err(ParseError::Command);
+        };
+
+        match words.next() {
+            Some(_) => Err(ParseError::Argument),
+            None => Ok(parsed),
+        }
+    }
+
+    /// The pin which the command acts on
+    pub fn pin(&self) -> Pin {
+        match *self {
+            Self::Input(pin, _)
+            | Self::Output { pin, .. }
+            | Self::Square { pin, .. }
+            | Self::Read(pin)
+            | Self::Analog(pin) => pin,
+        }
+    }
+}
+
+/// Collects bytes into lines, ended by CR, LF or both
+#[derive(Debug)]
+pub struct Line {
+    bytes: [u8; 32],
+    len: usize,
+    /// Whether the line is longer than fits, and is dropped at its end
+    overflow: bool,
+}
+
+impl Default for Line {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Line {
+    pub const fn new() -> Self {
+        Self {
+            bytes: [0; 32],
+            len: 0,
+            overflow: false,
+        }
+    }
+
+    /// Add a byte, which may end a line that is not empty
+    pub fn push(&mut self, byte: u8) -> Option<&str> {
+        if byte != b'\r' && byte != b'\n' {
+            if self.len < self.bytes.len() {
+                self.bytes[self.len] = byte;
+                self.len += 1;
+            } else {
+                self.overflow = true;
+            }
+            return None;
+        }
+
+        let len = core::mem::take(&mut self.len);
+        if core::mem::take(&mut self.overflow) || len == 0 {
+            return None;
+        }
+        // a line which is not text is no command either
+        Some(core::str::from_utf8(&self.bytes[..len]).unwrap_or(""))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pins::Port;
+    use std::string::String;
+    use std::vec::Vec;
+
+    const PA5: Pin = Pin::new(Port::A, 5);
+
+    #[test]
+    fn commands_parse_in_any_case() {
+        assert_eq!(
+            Command::parse("in PA5"),
+            Ok(Command::Input(PA5, Pull::Down))
+        );
+        assert_eq!(
+            Command::parse("IN pa5 Up"),
+            Ok(Command::Input(PA5, Pull::Up))
+        );
+        assert_eq!(
+            Command::parse("input PA5 float"),
+            Ok(Command::Input(PA5, Pull::Floating))
+        );
+        assert_eq!(
+            Command::parse("out PA5 high"),
+            Ok(Command::Output {
+                pin: PA5,
+                drive: Drive::PushPull,
+                high: true
+            })
+        );
+        assert_eq!(
+            Command::parse("  out  PA5 0 OD "),
+            Ok(Command::Output {
+                pin: PA5,
+                drive: Drive::OpenDrain,
+                high: false
+            })
+        );
+        assert_eq!(
+            Command::parse("sq PA5 1000"),
+            Ok(Command::Square { pin: PA5, hz: 1000 })
+        );
+        assert_eq!(Command::parse("r PA5"), Ok(Command::Read(PA5)));
+        assert_eq!(
+            Command::parse("adc PB1"),
+            Ok(Command::Analog(Pin::new(Port::B, 1)))
+        );
+    }
+
+    #[test]
+    fn errors_name_what_is_wrong() {
+        assert_eq!(Command::parse(""), Err(ParseError::Command));
+        assert_eq!(Command::parse("blink PA5"), Err(ParseError::Command));
+        assert_eq!(Command::parse("read"), Err(ParseError::Pin));
+        assert_eq!(Command::parse("read PA+5"), Err(ParseError::Pin));
+        assert_eq!(Command::parse("read PA16"), Err(ParseError::Pin));
+        assert_eq!(Command::parse("read PA5 now"), Err(ParseError::Argument));
+        assert_eq!(Command::parse("in PA5 sideways"), Err(ParseError::Argument));
+        assert_eq!(Command::parse("out PA5"), Err(ParseError::Argument));
+        assert_eq!(
+            Command::parse("out PA5 high pp od"),
+            Err(ParseError::Argument)
+        );
+        assert_eq!(Command::parse("square PA5"), Err(ParseError::Argument));
+        assert_eq!(Command::parse("square PA5 fast"), Err(ParseError::Argument));
+        assert_eq!(Command::parse("adc PA8"), Err(ParseError::NotAnalog));
+    }
+
+    #[test]
+    fn square_waves_stay_in_range() {
+        assert_eq!(Command::parse("square PA5 0"), Err(ParseError::Frequency));
+        assert_eq!(
+            Command::parse("square PA5 10000"),
+            Ok(Command::Square {
+                pin: PA5,
+                hz: MAX_SQUARE_HZ
+            })
+        );
+        assert_eq!(
+            Command::parse("square PA5 10001"),
+            Err(ParseError::Frequency)
+        );
+    }
+
+    fn lines(line: &mut Line, bytes: &[u8]) -> Vec<String> {
+        bytes
+            .iter()
+            .filter_map(|&byte| line.push(byte).map(String::from))
+            .collect()
+    }
+
+    #[test]
+    fn lines_end_at_cr_lf_or_both() {
+        let mut line = Line::new();
+        assert_eq!(
+            lines(&mut line, b"read PA5\r\nin PB0\nr PC13\r"),
+            ["read PA5", "in PB0", "r PC13"]
+        );
+        assert_eq!(lines(&mut line, b"\r\n\n"), [] as [&str; 0]);
+    }
+
+    #[test]
+    fn long_lines_are_dropped_whole() {
+        let mut line = Line::new();
+        let mut bytes = vec![b'x'; 40];
+        bytes.extend_from_slice(b"\nread PA5\n");
+        assert_eq!(lines(&mut line, &bytes), ["read PA5"]);
+
+        // exactly full still fits
+        let mut bytes = vec![b'x'; 32];
+        bytes.push(b'\n');
+        assert_eq!(lines(&mut line, &bytes).len(), 1);
+    }
+
+    #[test]
+    fn lines_which_are_not_text_are_empty() {
+        let mut line = Line::new();
+        assert_eq!(lines(&mut line, b"\xff\xfe\n"), [""]);
+        assert_eq!(Command::parse(""), Err(ParseError::Command));
+    }
+}
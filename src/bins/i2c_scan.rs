@@ -0,0 +1,121 @@
+#![deny(unsafe_code)]
+#![no_std]
+#![no_main]
+
+#[cfg(feature = "semi")]
+extern crate panic_semihosting;
+
+use cortex_m_rt::entry;
+use embedded_hal::blocking::i2c::{Read, WriteRead};
+use stm32f1xx_hal::pac;
+
+use embedded_pg::board::Board;
+use embedded_pg::clocks::Profile;
+use embedded_pg::error::{Context, Error};
+use embedded_pg::log::{self, Level};
+use embedded_pg::watchdog::ResetCause;
+use embedded_pg::{crash, i2c_scan, info};
+
+/// Clear the NACK flag and release the bus
+///
+/// `BlockingI2c` leaves the flag set after a missing device, which makes every
+/// following transaction fail as well.
+#[allow(unsafe_code)]
+fn recover_from_nack() {
+    // SAFETY: the blocking driver is idle and expects these flags to be clear
+    let i2c2 = unsafe { &*pac::I2C2::ptr() };
+    i2c2.sr1.modify(|_, w| w.af().clear_bit());
+    i2c2.cr1.modify(|_, w| w.stop().set_bit());
+}
+
+/// The bus, recovering after every failed transaction
+///
+/// Both the probes and the register reads of [`i2c_scan::identify`] can be
+/// refused, and the next address would fail with them.
+struct Recovering<I>(I);
+
+impl<I: Read> Read for Recovering<I> {
+    type Error = I::Error;
+
+    fn read(&mut self, address: u8, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        let result = self.0.read(address, buffer);
+        if result.is_err() {
+            recover_from_nack();
+        }
+        result
+    }
+}
+
+impl<I: WriteRead> WriteRead for Recovering<I> {
+    type Error = I::Error;
+
+    fn write_read(
+        &mut self,
+        address: u8,
+        bytes: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        let result = self.0.write_read(address, bytes, buffer);
+        if result.is_err() {
+            recover_from_nack();
+        }
+        result
+    }
+}
+
+/// Wrapper around main which supports returning errors
+fn _main() -> Result<(), Error> {
+    // get access to all required peripherals
+    let dev_peripherals = pac::Peripherals::take().context("device peripherals")?;
+    let mut bus = Recovering(Board::new(dev_peripherals, Profile::LOW_POWER)?.i2c);
+    crash::report();
+    info!("reset by {}", ResetCause::take());
+
+    // probe every address
+    let mut found = [false; 128];
+    for address in i2c_scan::FIRST..=i2c_scan::LAST {
+        found[address as usize] = i2c_scan::probe(&mut bus, address);
+    }
+
+    // print a table like i2cdetect
+    info!("     0  1  2  3  4  5  6  7  8  9  a  b  c  d  e  f");
+    for row in (0..128).step_by(16) {
+        log::with(Level::Info, |out| {
+            write!(out, "{:02x}:", row)?;
+            for address in row..row + 16 {
+                if !(i2c_scan::FIRST..=i2c_scan::LAST).contains(&address) {
+                    write!(out, "   ")?;
+                } else if found[address as usize] {
+                    write!(out, " {:02x}", address)?;
+                } else {
+                    write!(out, " --")?;
+                }
+            }
+            Ok(())
+        });
+    }
+
+    // guess what the devices are
+    for address in i2c_scan::FIRST..=i2c_scan::LAST {
+        if !found[address as usize] {
+            continue;
+        }
+
+        match i2c_scan::identify(&mut bus, address) {
+            Some(device) => info!("{:#04x}  {}", address, device),
+            None => info!("{:#04x}  unknown", address),
+        }
+    }
+
+    Ok(())
+}
+
+#[entry]
+fn main() -> ! {
+    _main().unwrap();
+
+    // nothing left to do
+    loop {
+        cortex_m::asm::wfi();
+    }
+}
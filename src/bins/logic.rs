@@ -0,0 +1,145 @@
+#![deny(unsafe_code)]
+#![no_std]
+#![no_main]
+
+//! A logic analyzer of PA0-PA7, which PulseView drives over USART1
+//!
+//! Add it in PulseView as an "Openbench Logic Sniffer & SUMP compatibles"
+//! device on the serial port of PA9 (TX) and PA10 (RX), at 115200 baud.
+//! With `log-usart`, the log stops once the host takes over USART1.
+
+#[cfg(feature = "semi")]
+extern crate panic_semihosting;
+
+use cortex_m_rt::entry;
+use embedded_hal::serial::{Read, Write};
+use nb::block;
+use stm32f1xx_hal::serial::{self, Serial};
+use stm32f1xx_hal::{pac, prelude::*};
+
+use embedded_pg::capture::{self, Aborted, Capture};
+use embedded_pg::clocks::Profile;
+use embedded_pg::error::{Context, Error};
+use embedded_pg::log::Level;
+use embedded_pg::pins::Port;
+use embedded_pg::sump::{self, Command, Device, Parser, Settings};
+use embedded_pg::watchdog::ResetCause;
+use embedded_pg::{crash, info, log, warn};
+
+const DEVICE: Device = Device {
+    name: "embedded-playground logic",
+    version: env!("CARGO_PKG_VERSION"),
+    probes: 8,
+    memory: capture::MAX_SAMPLES,
+    max_rate: capture::MAX_RATE,
+};
+
+fn _main() -> Result<(), Error> {
+    // get access to all required peripherals
+    let dev_peripherals = pac::Peripherals::take().context("device peripherals")?;
+    let mut flash = dev_peripherals.FLASH.constrain();
+    let mut radio_clock = dev_peripherals.RCC.constrain();
+    let clocks = Profile::FULL.freeze(radio_clock.cfgr, &mut flash.acr);
+    log::init(&clocks);
+    crash::report();
+    info!("reset by {}", ResetCause::take());
+    let mut afio = dev_peripherals.AFIO.constrain(&mut radio_clock.apb2);
+    let mut gpioa = dev_peripherals.GPIOA.split(&mut radio_clock.apb2);
+
+    // the probes, which are not 5 V tolerant
+    let _probes = (
+        gpioa.pa0.into_floating_input(&mut gpioa.crl),
+        gpioa.pa1.into_floating_input(&mut gpioa.crl),
+        gpioa.pa2.into_floating_input(&mut gpioa.crl),
+        gpioa.pa3.into_floating_input(&mut gpioa.crl),
+        gpioa.pa4.into_floating_input(&mut gpioa.crl),
+        gpioa.pa5.into_floating_input(&mut gpioa.crl),
+        gpioa.pa6.into_floating_input(&mut gpioa.crl),
+        gpioa.pa7.into_floating_input(&mut gpioa.crl),
+    );
+    let dma = dev_peripherals.DMA1.split(&mut radio_clock.ahb);
+    let mut capture = Capture::new(
+        dev_peripherals.TIM2,
+        dma.2,
+        Port::A,
+        &clocks,
+        &mut radio_clock.apb1,
+    )
+    .context("capture")?;
+
+    // the host, which the log makes way for if it is on USART1 as well
+    if cfg!(feature = "log-usart") {
+        info!("the host takes over USART1, so the log stops");
+        log::set_level(Level::Off);
+    }
+    let serial = Serial::usart1(
+        dev_peripherals.USART1,
+        (
+            gpioa.pa9.into_alternate_push_pull(&mut gpioa.crh),
+            gpioa.pa10,
+        ),
+        &mut afio.mapr,
+        serial::Config::default().baudrate(115_200.bps()),
+        clocks,
+        &mut radio_clock.apb2,
+    );
+    let (mut tx, mut rx) = serial.split();
+    let mut send = |byte| block!(tx.write(byte)).ok();
+
+    let mut parser = Parser::new();
+    let mut settings = Settings::default();
+    // the byte which aborted a capture, which may start the next command
+    let mut aborting = None;
+    loop {
+        // a byte which came in garbled is left for the resets of the host
+        let byte = match aborting.take() {
+            Some(byte) => byte,
+            None => match block!(rx.read()) {
+                Ok(byte) => byte,
+                Err(_) => continue,
+            },
+        };
+
+        match parser.push(byte) {
+            None | Some(Command::Reset) => {}
+            Some(Command::Id) => sump::ID.iter().for_each(|&byte| {
+                send(byte);
+            }),
+            Some(Command::Metadata) => DEVICE.metadata(|byte| {
+                send(byte);
+            }),
+            Some(Command::Run) => {
+                info!(
+                    "capturing {} samples at {} Hz",
+                    settings.read,
+                    settings.rate()
+                );
+                // any byte from the host stops waiting for the trigger, and is parsed next
+                let aborted = || {
+                    aborting = rx.read().ok();
+                    aborting.is_some()
+                };
+                match capture.run(&settings, aborted) {
+                    Ok((samples, rate)) => {
+                        info!("sending {} samples at {} Hz", samples.len(), rate);
+                        let groups = settings.groups();
+                        for sample in samples.newest_first() {
+                            sump::sample_bytes(sample, groups, |byte| {
+                                send(byte);
+                            });
+                        }
+                    }
+                    Err(Aborted::Host) => info!("capture aborted"),
+                    Err(Aborted::Overrun) => warn!("capture overrun"),
+                }
+            }
+            Some(command) => settings.apply(command),
+        }
+    }
+}
+
+#[entry]
+fn main() -> ! {
+    _main().unwrap();
+    panic!()
+}
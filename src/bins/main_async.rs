@@ -0,0 +1,122 @@
+//! `main` on async drivers, awaiting the numpad, LCD, matrix and probe at once
+
+#![deny(unsafe_code)]
+#![no_std]
+#![no_main]
+
+#[cfg(feature = "semi")]
+extern crate panic_semihosting;
+
+use core::pin::pin;
+use cortex_m_rt::entry;
+use stm32f1xx_hal::delay::Delay;
+use stm32f1xx_hal::pac::{self, interrupt};
+
+use embedded_pg::asynch::executor::{Executor, Task};
+use embedded_pg::asynch::i2c::Blocking;
+use embedded_pg::asynch::time::{self, Timer};
+use embedded_pg::asynch::{lcd, matrix, numpad, probe};
+use embedded_pg::board::Board;
+use embedded_pg::clocks::Profile;
+use embedded_pg::error::{Context, Error};
+use embedded_pg::watchdog::ResetCause;
+use embedded_pg::{crash, error, info, patterns};
+
+/// The characters of the buttons, by bit
+const CHARACTERS: &[u8; 16] = b"123A456B789C*0#D";
+
+/// Log the error a task ended with
+async fn report(name: &str, task: impl core::future::Future<Output = Result<(), Error>>) {
+    if let Err(e) = task.await {
+        error!("{}: {:?}", name, e);
+    }
+}
+
+/// The tick of the async timers
+#[interrupt]
+fn TIM4() {
+    time::on_tim4();
+}
+
+/// Wrapper around main which supports returning errors
+fn _main() -> Result<(), Error> {
+    // get access to all required peripherals
+    let core_peripherals = cortex_m::Peripherals::take().context("core peripherals")?;
+    let dev_peripherals = pac::Peripherals::take().context("device peripherals")?;
+    let Board {
+        clocks,
+        numpad: mut keypad,
+        matrix: mut display,
+        i2c,
+        one_wire,
+        mut apb1,
+        tim4,
+        ..
+    } = Board::new(dev_peripherals, Profile::FULL)?;
+    let crashed = crash::report().is_some();
+    info!("reset by {}", ResetCause::take());
+
+    time::start(tim4, &clocks, &mut apb1);
+    let mut bus = Blocking(i2c);
+    // only for the microsecond timing of 1-Wire
+    let mut bit_delay = Delay::new(core_peripherals.SYST, clocks);
+    let mut temp_probe =
+        embedded_pg::probe::find_temp_probe::<_, _, _, Error>(one_wire, &mut bit_delay)?;
+
+    // type the pressed buttons on the LCD, '!' first if the previous run crashed
+    let keys = pin!(report("keys", async {
+        let mut delay = time::Delay;
+        lcd::init(&mut bus, &mut delay).await.context("lcd")?;
+        if crashed {
+            lcd::write_char(&mut bus, b'!').await?;
+        }
+
+        loop {
+            let buttons = numpad::pressed::<_, Error>(&mut keypad, &mut delay, 20).await?;
+            info!("buttons {:#06x}", buttons);
+            let character = CHARACTERS[buttons.trailing_zeros() as usize];
+            lcd::write_char(&mut bus, character).await?;
+            numpad::released::<_, Error>(&mut keypad, &mut delay, 20).await?;
+        }
+    }));
+
+    // scroll text over the matrix, after a cross if the previous run crashed
+    let scroller = pin!(report("matrix", async {
+        let mut delay = time::Delay;
+        display.power_on()?;
+        if crashed {
+            display.write_raw(0, &patterns::Cross)?;
+            Timer::after_ms(3000).await;
+        }
+
+        loop {
+            matrix::scroll(&mut display, b"Feroxide! ", 150, &mut delay).await?;
+        }
+    }));
+
+    // measure the temperature every few seconds
+    let sampler = pin!(report("probe", async {
+        let mut delay = time::Delay;
+        let (temp_probe, owb) = match temp_probe.as_mut() {
+            Some((temp_probe, owb)) => (temp_probe, owb),
+            None => return Ok(()),
+        };
+
+        loop {
+            let temp = probe::measure(temp_probe, owb, &mut bit_delay, &mut delay)
+                .await
+                .context("read probe")?;
+            info!("temp {}", temp);
+            Timer::after_ms(2000).await;
+        }
+    }));
+
+    Executor::new([keys as Task, scroller, sampler]).run();
+    Ok(())
+}
+
+#[entry]
+fn main() -> ! {
+    _main().unwrap();
+    panic!("all tasks ended")
+}
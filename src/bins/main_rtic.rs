@@ -0,0 +1,227 @@
+//! `main` on RTIC, where the peripherals drive the tasks
+//!
+//! | task      | trigger             | priority | shared          |
+//! |-----------|---------------------|----------|-----------------|
+//! | `refresh` | TIM2, every 100 ms  | 3        | matrix, pixels  |
+//! | `pressed` | EXTI on the columns | 2        | numpad          |
+//! | `show`    | spawned by pressed  | 2        | all but numpad  |
+//! | `sample`  | TIM3, every second  | 1        | bus             |
+//!
+//! The 1-Wire timing of `sample` does not survive being preempted, in which
+//! case the read fails its CRC and is tried again the next second.
+
+#![deny(unsafe_code)]
+#![no_std]
+#![no_main]
+
+#[cfg(feature = "semi")]
+extern crate panic_semihosting;
+
+#[rtic::app(device = stm32f1xx_hal::pac, peripherals = true, dispatchers = [USART3])]
+mod app {
+    use ds18b20::Ds18b20;
+    use one_wire_bus::OneWire;
+    use stm32f1xx_hal::delay::Delay;
+    use stm32f1xx_hal::pac::{TIM2, TIM3};
+    use stm32f1xx_hal::prelude::*;
+    use stm32f1xx_hal::timer::{CountDownTimer, Event, Timer};
+
+    use embedded_pg::board::{self, Board, OneWirePin};
+    use embedded_pg::clocks::Profile;
+    use embedded_pg::error::{Context, Error};
+    use embedded_pg::numpad::{Button, Buttons, Numpad};
+    use embedded_pg::probe::{find_temp_probe, read_temperature};
+    use embedded_pg::watchdog::ResetCause;
+    use embedded_pg::{crash, error, info, lcd, patterns, warn};
+
+    /// How many refreshes the cross of a crash stays on the matrix
+    const CROSS_TICKS: u32 = 20;
+    /// The characters of the buttons, by bit
+    const CHARACTERS: &[u8; 16] = b"123A456B789C*0#D";
+
+    #[shared]
+    struct Shared {
+        bus: board::I2c,
+        matrix: board::Matrix,
+        pixels: [u8; 8],
+        #[lock_free]
+        numpad: Numpad,
+    }
+
+    #[local]
+    struct Local {
+        refresh_timer: CountDownTimer<TIM2>,
+        sample_timer: CountDownTimer<TIM3>,
+        probe: Option<(Ds18b20, OneWire<OneWirePin>)>,
+        delay: Delay,
+    }
+
+    #[init]
+    fn init(cx: init::Context) -> (Shared, Local, init::Monotonics) {
+        let (shared, local) = setup(cx).unwrap();
+        (shared, local, init::Monotonics())
+    }
+
+    /// Wrapper around init which supports returning errors
+    fn setup(cx: init::Context) -> Result<(Shared, Local), Error> {
+        let Board {
+            clocks,
+            mut numpad,
+            mut matrix,
+            i2c: mut bus,
+            one_wire,
+            mut apb1,
+            mut afio,
+            exti,
+            tim2,
+            tim3,
+            ..
+        } = Board::new(cx.device, Profile::FULL)?;
+        let crashed = crash::report().is_some();
+        info!("reset by {}", ResetCause::take());
+
+        let mut delay = Delay::new(cx.core.SYST, clocks);
+        lcd::init(&mut bus, &mut delay).context("lcd")?;
+        let probe = find_temp_probe::<_, _, _, Error>(one_wire, &mut delay)?;
+
+        matrix.power_on()?;
+        let pixels = if crashed {
+            patterns::Cross
+        } else {
+            patterns::Chess
+        };
+
+        numpad.listen::<Error>(&mut afio, &exti)?;
+
+        let mut refresh_timer = Timer::tim2(tim2, &clocks, &mut apb1).start_count_down(100.ms());
+        refresh_timer.listen(Event::Update);
+        let mut sample_timer = Timer::tim3(tim3, &clocks, &mut apb1).start_count_down(1.hz());
+        sample_timer.listen(Event::Update);
+
+        Ok((
+            Shared {
+                bus,
+                matrix,
+                pixels,
+                numpad,
+            },
+            Local {
+                refresh_timer,
+                sample_timer,
+                probe,
+                delay,
+            },
+        ))
+    }
+
+    /// Write the pixels to the matrix again
+    #[task(binds = TIM2, priority = 3, shared = [matrix, pixels], local = [refresh_timer, ticks: u32 = 0])]
+    fn refresh(cx: refresh::Context) {
+        cx.local.refresh_timer.clear_update_interrupt_flag();
+
+        // the cross of a crash makes way after a while
+        let ticks = cx.local.ticks;
+        let clear_cross = *ticks == CROSS_TICKS;
+        *ticks = ticks.saturating_add(1);
+
+        (cx.shared.matrix, cx.shared.pixels).lock(|matrix, pixels| {
+            if clear_cross && *pixels == patterns::Cross {
+                *pixels = patterns::Chess;
+            }
+            if let Err(e) = matrix.write_raw(0, pixels) {
+                error!("refresh: {:?}", e);
+            }
+        });
+    }
+
+    /// Read the numpad after a column went high
+    fn scan(numpad: &mut Numpad) {
+        let buttons = numpad.read::<Error>();
+        numpad.clear_interrupts();
+
+        match buttons {
+            Ok(Buttons::None) => {}
+            Ok(buttons) => {
+                if show::spawn(buttons).is_err() {
+                    warn!("dropped buttons {:#06x}", buttons);
+                }
+            }
+            Err(e) => error!("numpad: {:?}", e),
+        }
+    }
+
+    #[task(binds = EXTI3, priority = 2, shared = [numpad])]
+    fn pressed(cx: pressed::Context) {
+        scan(cx.shared.numpad);
+    }
+
+    #[task(binds = EXTI4, priority = 2, shared = [numpad])]
+    fn pressed_4(cx: pressed_4::Context) {
+        scan(cx.shared.numpad);
+    }
+
+    #[task(binds = EXTI9_5, priority = 2, shared = [numpad])]
+    fn pressed_9_5(cx: pressed_9_5::Context) {
+        scan(cx.shared.numpad);
+    }
+
+    /// Show the pressed buttons on the matrix and the LCD
+    #[task(priority = 2, capacity = 4, shared = [bus, matrix, pixels])]
+    fn show(cx: show::Context, buttons: Button) {
+        info!("buttons {:#06x}", buttons);
+        let new_pixels = match buttons {
+            Buttons::One => Some(patterns::One),
+            Buttons::Two => Some(patterns::Chess),
+            _ => None,
+        };
+
+        let mut bus = cx.shared.bus;
+        let character = CHARACTERS[buttons.trailing_zeros() as usize];
+        if let Err(e) = bus.lock(|bus| lcd::write_char(bus, character)) {
+            error!("lcd: {:?}", e);
+        }
+
+        // write straight away instead of waiting for the next refresh
+        if let Some(new_pixels) = new_pixels {
+            (cx.shared.matrix, cx.shared.pixels).lock(|matrix, pixels| {
+                *pixels = new_pixels;
+                if let Err(e) = matrix.write_raw(0, pixels) {
+                    error!("show: {:?}", e);
+                }
+            });
+        }
+    }
+
+    /// Log the last measurement and start the next one, which takes 750 ms
+    #[task(binds = TIM3, priority = 1, shared = [bus], local = [sample_timer, probe, delay])]
+    fn sample(mut cx: sample::Context) {
+        cx.local.sample_timer.clear_update_interrupt_flag();
+
+        let (probe, owb) = match cx.local.probe {
+            Some((probe, owb)) => (probe, owb),
+            None => return,
+        };
+        let delay = cx.local.delay;
+
+        match read_temperature(probe, owb, delay) {
+            Ok(temp) => {
+                info!("temp {}", temp);
+
+                // the whole degrees on the LCD
+                let celsius = temp.celsius().rem_euclid(100) as u8;
+                let result = cx.shared.bus.lock(|bus| {
+                    lcd::write_char(bus, b'0' + celsius / 10)?;
+                    lcd::write_char(bus, b'0' + celsius % 10)
+                });
+                if let Err(e) = result {
+                    error!("lcd: {:?}", e);
+                }
+            }
+            Err(e) => warn!("read probe: {:?}", e),
+        }
+
+        if let Err(e) = probe.start_temp_measurement(owb, delay) {
+            warn!("start measurement: {:?}", e);
+        }
+    }
+}
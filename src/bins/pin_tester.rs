@@ -1,137 +1,421 @@
 #![deny(unsafe_code)]
 #![no_std]
 #![no_main]
-#![feature(try_trait)]
 
+#[cfg(feature = "semi")]
 extern crate panic_semihosting;
 
-use core::convert::Infallible;
-use core::fmt::{Debug, Write};
 use cortex_m_rt::entry;
-use cortex_m_semihosting::hio;
-use embedded_hal::digital::v2::InputPin;
 use nb::block;
-use stm32f1xx_hal::{pac, prelude::*, timer::Timer};
+use stm32f1xx_hal::adc::Adc;
+use stm32f1xx_hal::pac::{self, ADC1, TIM2};
+use stm32f1xx_hal::{prelude::*, timer::Timer};
 
-// Combine all possible errors into one single Error
+use embedded_pg::bench::{Command, Drive, Line};
+use embedded_pg::board::{CONNECTED, PULLED_UP};
+use embedded_pg::clocks::Profile;
+use embedded_pg::cycles::{CycleCounter, Dwt};
+use embedded_pg::error::{Context, Error};
+use embedded_pg::log::{self, Level};
+use embedded_pg::pins::{read_analog, Gpio, Mode, Pin, Port, Pull};
+use embedded_pg::scheduler::reached;
+use embedded_pg::watchdog::ResetCause;
+use embedded_pg::{crash, info, warn};
 
-macro_rules! build_error {
-    ( $(($x:ident, $y:ty)),* $(,)? ) => {
+/// Every pin on the headers, and the pull it is watched with
+///
+/// PA13 and PA14 are left to the debugger, and PC13 to the LED. Mapping the
+/// wiring pulls all of them down, except those [`reserved`] for the log.
+const PINS: [(Pin, Pull); 31] = [
+    (Pin::new(Port::A, 0), Pull::Down),
+    (Pin::new(Port::A, 1), Pull::Down),
+    (Pin::new(Port::A, 2), Pull::Down),
+    (Pin::new(Port::A, 3), Pull::Down),
+    (Pin::new(Port::A, 4), Pull::Down),
+    (Pin::new(Port::A, 5), Pull::Down),
+    (Pin::new(Port::A, 6), Pull::Down),
+    (Pin::new(Port::A, 7), Pull::Down),
+    (Pin::new(Port::A, 8), Pull::Down),
+    (Pin::new(Port::A, 9), Pull::Down),
+    (Pin::new(Port::A, 10), Pull::Down),
+    (Pin::new(Port::A, 11), Pull::Down),
+    (Pin::new(Port::A, 12), Pull::Down),
+    (Pin::new(Port::A, 15), Pull::Down),
+    (Pin::new(Port::B, 0), Pull::Down),
+    (Pin::new(Port::B, 1), Pull::Down),
+    (Pin::new(Port::B, 3), Pull::Down),
+    (Pin::new(Port::B, 4), Pull::Down),
+    (Pin::new(Port::B, 5), Pull::Down),
+    (Pin::new(Port::B, 6), Pull::Down),
+    (Pin::new(Port::B, 7), Pull::Down),
+    (Pin::new(Port::B, 8), Pull::Down),
+    (Pin::new(Port::B, 9), Pull::Down),
+    (Pin::new(Port::B, 10), Pull::Down),
+    (Pin::new(Port::B, 11), Pull::Down),
+    (Pin::new(Port::B, 12), Pull::Down),
+    (Pin::new(Port::B, 13), Pull::Down),
+    (Pin::new(Port::B, 14), Pull::Down),
+    (Pin::new(Port::B, 15), Pull::Down),
+    (Pin::new(Port::C, 14), Pull::Down),
+    (Pin::new(Port::C, 15), Pull::Down),
+];
 
-        #[derive(Debug)]
-        enum Error {
-            $(
-                $x($y)
-            ),*
+/// How often the toggle counts are shown, when a pin toggled since
+const SUMMARY_MS: u32 = 10_000;
+
+/// Whether the log takes the pin, which is then left alone
+fn reserved(pin: Pin) -> bool {
+    cfg!(feature = "log-usart") && (pin == Pin::new(Port::A, 9) || pin == Pin::new(Port::A, 10))
+}
+
+/// A square wave, which the main loop toggles
+#[derive(Clone, Copy)]
+struct Square {
+    half_cycles: u32,
+    next: u32,
+    high: bool,
+}
+
+/// The pins as the commands set them, of which the inputs are watched
+struct Bench {
+    gpio: Gpio,
+    adc: Adc<ADC1>,
+    dwt: Dwt,
+    modes: [Mode; PINS.len()],
+    squares: [Option<Square>; PINS.len()],
+    levels: [bool; PINS.len()],
+    toggles: [u32; PINS.len()],
+}
+
+impl Bench {
+    fn new(mut gpio: Gpio, adc: Adc<ADC1>, dwt: Dwt) -> Self {
+        let modes = PINS.map(|(pin, pull)| {
+            if !reserved(pin) {
+                gpio.set_mode(pin, Mode::Input(pull));
+            }
+            Mode::Input(pull)
+        });
+        let levels = PINS.map(|(pin, _)| gpio.is_high(pin));
+
+        Self {
+            gpio,
+            adc,
+            dwt,
+            modes,
+            squares: [None; PINS.len()],
+            levels,
+            toggles: [0; PINS.len()],
         }
+    }
+
+    fn set_mode(&mut self, i: usize, mode: Mode) {
+        self.squares[i] = None;
+        self.modes[i] = mode;
+        self.gpio.set_mode(PINS[i].0, mode);
+        // a pin becomes watched from its level now
+        self.levels[i] = self.gpio.is_high(PINS[i].0);
+    }
+
+    fn execute(&mut self, command: Command) {
+        let pin = command.pin();
+        let i = match index(pin) {
+            Some(i) if !reserved(pin) => i,
+            _ => return warn!("{} is not free for testing", pin),
+        };
+
+        match command {
+            Command::Input(_, pull) => {
+                self.set_mode(i, Mode::Input(pull));
+                info!("{} is an input, {:?}", pin, pull);
+            }
+            Command::Output { drive, high, .. } => {
+                // the level first, so the pin does not glitch
+                self.gpio.set(pin, high);
+                let mode = match drive {
+                    Drive::PushPull => Mode::PushPull,
+                    Drive::OpenDrain => Mode::OpenDrain,
+                };
+                self.set_mode(i, mode);
+                let level = if high { "high" } else { "low" };
+                info!("{} drives {}, {:?}", pin, level, drive);
+            }
+            Command::Square { hz, .. } => {
+                self.set_mode(i, Mode::PushPull);
+                let half_cycles = self.dwt.cycles_per_us() * 500_000 / hz;
+                self.squares[i] = Some(Square {
+                    half_cycles,
+                    next: self.dwt.cycles(),
+                    high: false,
+                });
+                info!("{} toggles at {} Hz", pin, hz);
+            }
+            Command::Read(_) => {
+                let level = if self.gpio.is_high(pin) {
+                    "high"
+                } else {
+                    "low"
+                };
+                info!("{} is {}", pin, level);
+            }
+            Command::Analog(_) => {
+                self.set_mode(i, Mode::Analog);
+                let raw = read_analog(&mut self.adc, pin).unwrap_or(0) as u32;
+                // the reference is 1.2 V, which gives the supply
+                let vref = self.adc.read_vref().max(1) as u32;
+                let mv = raw * 1200 / vref;
+                info!(
+                    "{} is at {}.{:03} V, {} of 4095",
+                    pin,
+                    mv / 1000,
+                    mv % 1000,
+                    raw
+                );
+            }
+        }
+    }
+
+    /// Toggle the square waves which are due
+    fn toggle(&mut self) {
+        let now = self.dwt.cycles();
+        for (i, square) in self.squares.iter_mut().enumerate() {
+            let Some(square) = square else { continue };
+            if !reached(now, square.next) {
+                continue;
+            }
+            square.high = !square.high;
+            self.gpio.set(PINS[i].0, square.high);
+            square.next = square.next.wrapping_add(square.half_cycles);
+            // after a long log line, the wave starts over instead of catching up
+            if reached(now, square.next) {
+                square.next = now.wrapping_add(square.half_cycles);
+            }
+        }
+    }
+
+    /// Log the inputs which changed
+    fn watch(&mut self, now: u32) -> bool {
+        let mut changed = false;
+        for (i, (pin, _)) in PINS.iter().enumerate() {
+            if !matches!(self.modes[i], Mode::Input(_)) || reserved(*pin) {
+                continue;
+            }
+            let high = self.gpio.is_high(*pin);
+            if high == self.levels[i] {
+                continue;
+            }
+            self.levels[i] = high;
+            self.toggles[i] += 1;
+            changed = true;
+
+            let arrow = if high { '↑' } else { '↓' };
+            info!("{} {} at {}.{:03} s", pin, arrow, now / 1000, now % 1000);
+        }
+        changed
+    }
+}
+
+/// Watch the inputs, and take commands which set the pins
+fn bench(gpio: Gpio, adc: Adc<ADC1>, dwt: Dwt) -> Result<(), Error> {
+    let cycles_per_ms = dwt.cycles_per_us() * 1000;
+    let mut bench = Bench::new(gpio, adc, dwt);
+    let mut line = Line::new();
+    let mut summarized = true;
+    let mut next_summary = SUMMARY_MS;
+    let mut now = 0u32;
+    let mut last = bench.dwt.cycles();
+    if log::has_input() {
+        info!("commands: in, out, square, read and adc, like `out PA5 high od`");
+    } else {
+        warn!("the log takes no commands, build with log-usart or log-rtt for them");
+    }
+
+    loop {
+        bench.toggle();
 
-        $(
-            impl From<$y> for Error {
-                fn from(item: $y) -> Self {
-                    Self::$x(item)
+        let mut input = [0; 16];
+        let len = log::read(&mut input);
+        for &byte in &input[..len] {
+            if let Some(text) = line.push(byte) {
+                match Command::parse(text) {
+                    Ok(command) => bench.execute(command),
+                    Err(e) => warn!("{}: {}", text, e),
                 }
             }
-        )*
+        }
+
+        // logging may take longer than a tick, so the time comes from the cycles
+        let ms = bench.dwt.cycles().wrapping_sub(last) / cycles_per_ms;
+        if ms == 0 {
+            continue;
+        }
+        last = last.wrapping_add(ms * cycles_per_ms);
+        now = now.wrapping_add(ms);
+
+        if bench.watch(now) {
+            summarized = false;
+        }
+
+        if reached(now, next_summary) {
+            if !summarized {
+                log::with(Level::Info, |out| {
+                    write!(out, "toggles:")?;
+                    for (i, (pin, _)) in PINS.iter().enumerate() {
+                        if bench.toggles[i] > 0 {
+                            write!(out, " {} {}", pin, bench.toggles[i])?;
+                        }
+                    }
+                    Ok(())
+                });
+                summarized = true;
+            }
+            next_summary = now.wrapping_add(SUMMARY_MS);
+        }
+    }
+}
+
+/// The levels of the pins, by their index in the table, with the reserved
+/// ones low
+fn levels(gpio: &Gpio) -> u32 {
+    PINS.iter()
+        .enumerate()
+        .filter(|(_, (pin, _))| !reserved(*pin))
+        .fold(0, |levels, (i, (pin, _))| {
+            levels | (gpio.is_high(*pin) as u32) << i
+        })
+}
+
+/// Drive every pin in turn, and find the pins which follow it
+///
+/// A pin follows when it is high while the driver is high, and low while it
+/// is low. So a pin which a part pulls up only follows when it is shorted.
+fn connections(gpio: &mut Gpio, settle_cycles: u32) -> [u32; PINS.len()] {
+    let mut follows = [0; PINS.len()];
+    for (i, (driver, _)) in PINS.iter().enumerate() {
+        if reserved(*driver) {
+            continue;
+        }
+        gpio.set_mode(*driver, Mode::PushPull);
+        gpio.set(*driver, true);
+        cortex_m::asm::delay(settle_cycles);
+        let high = levels(gpio);
+        gpio.set(*driver, false);
+        cortex_m::asm::delay(settle_cycles);
+        let low = levels(gpio);
+        gpio.set_mode(*driver, Mode::Input(Pull::Down));
+
+        follows[i] = high & !low & !(1 << i);
     }
+    follows
 }
 
-build_error!(
-    (NoneOption, core::option::NoneError),
-    (Unit, ()),
-    (Void, void::Void),
-    (Fmt, core::fmt::Error),
-    (Infallible, Infallible),
-    (I2c, stm32f1xx_hal::i2c::Error),
-);
-
-struct Bitstring {
-    val: u64,
-    size: usize,
+fn index(pin: Pin) -> Option<usize> {
+    PINS.iter().position(|(other, _)| *other == pin)
 }
 
-impl core::iter::FromIterator<bool> for Bitstring {
-    fn from_iter<I: IntoIterator<Item = bool>>(i: I) -> Self {
-        let mut val = 0;
-        let mut size = 0;
+/// Log the connections and the pulled up pins, against the board
+fn report(follows: &[u32; PINS.len()], pulled_up: u32) {
+    let connected = |a: usize, b: usize| follows[a] & (1 << b) != 0 || follows[b] & (1 << a) != 0;
+    let expected = |a: Pin, b: Pin| {
+        CONNECTED
+            .iter()
+            .any(|&(x, y)| (x, y) == (a, b) || (x, y) == (b, a))
+    };
 
-        for x in i {
-            val <<= 1;
-            val |= x as u64;
-            size += 1;
+    info!("wiring:");
+    for (a, (pin_a, _)) in PINS.iter().enumerate() {
+        for (b, (pin_b, _)) in PINS.iter().enumerate().skip(a + 1) {
+            if !connected(a, b) {
+                continue;
+            } else if expected(*pin_a, *pin_b) {
+                info!("{} - {}", pin_a, pin_b);
+            } else {
+                warn!("{} and {} are shorted", pin_a, pin_b);
+            }
+        }
+    }
+    for (a, b) in CONNECTED {
+        if reserved(a) || reserved(b) {
+            continue;
         }
+        match (index(a), index(b)) {
+            (Some(i), Some(j)) if connected(i, j) => {}
+            _ => warn!("{} and {} are not connected", a, b),
+        }
+    }
 
-        Self { val, size }
+    for (i, (pin, _)) in PINS.iter().enumerate() {
+        if pulled_up & (1 << i) == 0 {
+            continue;
+        }
+        match PULLED_UP.iter().find(|(other, _)| other == pin) {
+            Some((_, part)) => info!("{} is pulled up by the {}", pin, part),
+            None => info!("{} is pulled up", pin),
+        }
+    }
+    for (pin, part) in PULLED_UP {
+        if reserved(pin) {
+            continue;
+        }
+        if index(pin).is_none_or(|i| pulled_up & (1 << i) == 0) {
+            warn!("{} is not pulled up, is the {} connected?", pin, part);
+        }
     }
 }
 
-impl Debug for Bitstring {
-    fn fmt(&self, fmt: &mut core::fmt::Formatter) -> core::fmt::Result {
-        fmt.write_fmt(format_args!("{:b}", 1 << self.size | self.val))
+/// Map which pins are connected, and log it whenever it changes
+fn map_wiring(mut gpio: Gpio, dwt: Dwt, timer: Timer<TIM2>) -> Result<(), Error> {
+    let mut main_countdown = timer.start_count_down(1.hz());
+    // a pull-down charges the pins in well under a microsecond
+    let settle_cycles = dwt.cycles_per_us() * 10;
+
+    for (pin, _) in PINS.into_iter().filter(|(pin, _)| !reserved(*pin)) {
+        gpio.set_mode(pin, Mode::Input(Pull::Down));
+    }
+    let mut last = None;
+
+    loop {
+        let pulled_up = levels(&gpio);
+        let follows = connections(&mut gpio, settle_cycles);
+        if last != Some((follows, pulled_up)) {
+            report(&follows, pulled_up);
+            last = Some((follows, pulled_up));
+        }
+
+        block!(main_countdown.wait())?;
     }
 }
 
 /// Wrapper around main which supports returning errors
 fn _main() -> Result<(), Error> {
     // get access to all required peripherals
-    let mut stdout = hio::hstdout()?;
-
-    let dev_peripherals = pac::Peripherals::take()?;
+    let mut core_peripherals = cortex_m::Peripherals::take().context("core peripherals")?;
+    let dev_peripherals = pac::Peripherals::take().context("device peripherals")?;
     let mut flash = dev_peripherals.FLASH.constrain();
     let mut radio_clock = dev_peripherals.RCC.constrain();
-    let clocks = radio_clock.cfgr.freeze(&mut flash.acr);
+    let clocks = Profile::LOW_POWER.freeze(radio_clock.cfgr, &mut flash.acr);
+    log::init(&clocks);
+    crash::report();
+    info!("reset by {}", ResetCause::take());
     let mut afio = dev_peripherals.AFIO.constrain(&mut radio_clock.apb2);
-    let mut gpioa = dev_peripherals.GPIOA.split(&mut radio_clock.apb2);
-    let mut gpiob = dev_peripherals.GPIOB.split(&mut radio_clock.apb2);
-    let mut gpioc = dev_peripherals.GPIOC.split(&mut radio_clock.apb2);
+    let gpio = Gpio::new(
+        dev_peripherals.GPIOA.split(&mut radio_clock.apb2),
+        dev_peripherals.GPIOB.split(&mut radio_clock.apb2),
+        dev_peripherals.GPIOC.split(&mut radio_clock.apb2),
+        &mut afio.mapr,
+    );
     let tim2 = Timer::tim2(dev_peripherals.TIM2, &clocks, &mut radio_clock.apb1);
-    let mut main_countdown = tim2.start_count_down(100.ms());
-
-    let (pa15, pb3, pb4) = afio.mapr.disable_jtag(gpioa.pa15, gpiob.pb3, gpiob.pb4);
-
-    let pins = [
-        gpiob.pb11.into_pull_down_input(&mut gpiob.crh).downgrade(),
-        gpiob.pb10.into_pull_down_input(&mut gpiob.crh).downgrade(),
-        gpiob.pb1.into_pull_down_input(&mut gpiob.crl).downgrade(),
-        gpiob.pb0.into_pull_down_input(&mut gpiob.crl).downgrade(),
-        gpioa.pa7.into_pull_down_input(&mut gpioa.crl).downgrade(),
-        gpioa.pa6.into_pull_down_input(&mut gpioa.crl).downgrade(),
-        gpioa.pa5.into_pull_down_input(&mut gpioa.crl).downgrade(),
-        gpioa.pa4.into_pull_down_input(&mut gpioa.crl).downgrade(),
-        gpioa.pa3.into_pull_down_input(&mut gpioa.crl).downgrade(),
-        gpioa.pa2.into_pull_down_input(&mut gpioa.crl).downgrade(),
-        gpioa.pa1.into_pull_down_input(&mut gpioa.crl).downgrade(),
-        gpioa.pa0.into_pull_down_input(&mut gpioa.crl).downgrade(),
-        gpioc.pc15.into_pull_down_input(&mut gpioc.crh).downgrade(),
-        gpioc.pc14.into_pull_down_input(&mut gpioc.crh).downgrade(),
-        // gpioc.pc13.into_pull_down_input(&mut gpioc.crh).downgrade(),
-        gpiob.pb12.into_pull_down_input(&mut gpiob.crh).downgrade(),
-        gpiob.pb13.into_pull_down_input(&mut gpiob.crh).downgrade(),
-        gpiob.pb14.into_pull_down_input(&mut gpiob.crh).downgrade(),
-        gpiob.pb15.into_pull_down_input(&mut gpiob.crh).downgrade(),
-        gpioa.pa8.into_pull_down_input(&mut gpioa.crh).downgrade(),
-        gpioa.pa9.into_pull_down_input(&mut gpioa.crh).downgrade(),
-        gpioa.pa10.into_pull_down_input(&mut gpioa.crh).downgrade(),
-        gpioa.pa11.into_pull_down_input(&mut gpioa.crh).downgrade(),
-        gpioa.pa12.into_pull_down_input(&mut gpioa.crh).downgrade(),
-        pa15.into_pull_down_input(&mut gpioa.crh).downgrade(),
-        pb3.into_pull_down_input(&mut gpiob.crl).downgrade(),
-        pb4.into_pull_down_input(&mut gpiob.crl).downgrade(),
-        gpiob.pb5.into_pull_down_input(&mut gpiob.crl).downgrade(),
-        gpiob.pb6.into_pull_down_input(&mut gpiob.crl).downgrade(),
-        gpiob.pb7.into_pull_down_input(&mut gpiob.crl).downgrade(),
-        gpiob.pb8.into_pull_down_input(&mut gpiob.crh).downgrade(),
-        gpiob.pb9.into_pull_down_input(&mut gpiob.crh).downgrade(),
-    ];
+    let dwt = Dwt::new(
+        &mut core_peripherals.DCB,
+        &mut core_peripherals.DWT,
+        &clocks,
+    );
 
-    loop {
-        writeln!(
-            stdout,
-            "{:?}",
-            pins.iter()
-                .map(|x| x.is_high().unwrap())
-                .collect::<Bitstring>()
-        )?;
-        block!(main_countdown.wait())?;
+    let adc = Adc::adc1(dev_peripherals.ADC1, &mut radio_clock.apb2, clocks);
+
+    if cfg!(feature = "continuity") {
+        map_wiring(gpio, dwt, tim2)
+    } else {
+        bench(gpio, adc, dwt)
     }
 }
 
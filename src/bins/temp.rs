@@ -1,141 +1,93 @@
 #![deny(unsafe_code)]
 #![no_std]
 #![no_main]
-#![feature(try_trait)]
 
+#[cfg(feature = "semi")]
 extern crate panic_semihosting;
 
-use core::convert::Infallible;
-use core::fmt::{Debug, Write};
+use core::cell::RefCell;
 use cortex_m_rt::entry;
-use cortex_m_semihosting::hio;
-use ds18b20::Ds18b20;
-use embedded_hal::blocking::delay::{DelayMs, DelayUs};
-use embedded_hal::digital::v2::{InputPin, OutputPin};
-use nb::block;
-use one_wire_bus::OneWire;
-use stm32f1xx_hal::{delay::Delay, pac, prelude::*, timer::Timer};
-
-// Combine all possible errors into one single Error
-
-macro_rules! build_error {
-    ( $(($x:ident, $y:ty)),* $(,)? ) => {
-
-        #[derive(Debug)]
-        enum Error {
-            $(
-                $x($y)
-            ),*
-        }
-
-        $(
-            impl From<$y> for Error {
-                fn from(item: $y) -> Self {
-                    Self::$x(item)
-                }
-            }
-        )*
-    }
-}
-
-build_error!(
-    (NoneOption, core::option::NoneError),
-    (Unit, ()),
-    (Void, void::Void),
-    (Fmt, core::fmt::Error),
-    (Infallible, Infallible),
-    (I2c, stm32f1xx_hal::i2c::Error),
-    (Owb, one_wire_bus::OneWireError<Infallible>),
-);
-
-/// Get the temperature probe connected on the given pin, if any
-fn get_temp_probe<T, U>(
-    pin: T,
-    delay: &mut U,
-    stdout: &mut hio::HStdout,
-) -> Result<Option<(Ds18b20, OneWire<T>)>, Error>
-where
-    T: InputPin<Error = Infallible> + OutputPin<Error = Infallible>,
-    U: DelayMs<u16> + DelayUs<u16>,
-{
-    // initialise the OneWireBus
-    let mut owb = OneWire::new(pin)?;
-
-    // find the device
-    let mut devs = owb.devices(false, delay);
-    let probe: Option<Ds18b20> = loop {
-        match devs.next() {
-            // found a device on the bus
-            Some(Ok(addr)) => {
-                writeln!(stdout, "addr: {:?}", addr)?;
-
-                // check if it's a temperature probe
-                match Ds18b20::new::<()>(addr) {
-                    Ok(x) => break Some(x),
-
-                    Err(e) => writeln!(stdout, "Ds::new     {:?}", e)?,
-                }
-            }
-
-            // found a device but it errored
-            Some(Err(e)) => {
-                writeln!(stdout, "devs.next   {:?}", e)?;
-            }
-
-            // no more devices
-            None => {
-                writeln!(stdout, "out of devices")?;
-                break None;
-            }
-        }
-    };
-
-    // if we found a probe
-    if let Some(probe) = probe {
-        // start measurement
-        probe.start_temp_measurement(&mut owb, delay)?;
-        ds18b20::Resolution::Bits12.delay_for_measurement_time(delay);
-
-        Ok(Some((probe, owb)))
-    } else {
-        // release the bus again
-        owb.release_bus()?;
-
-        Ok(None)
-    }
-}
+use embedded_hal::watchdog::WatchdogEnable;
+use embedded_pg::board::Board;
+use embedded_pg::clocks::Profile;
+use embedded_pg::cycles::Dwt;
+use embedded_pg::error::{Context, Error};
+use embedded_pg::probe::{find_temp_probe, read_temperature};
+use embedded_pg::scheduler::{CycleClock, Scheduler};
+use embedded_pg::watchdog::{ResetCause, Supervisor};
+use embedded_pg::{crash, info, warn};
+use stm32f1xx_hal::{delay::Delay, pac, prelude::*, watchdog::IndependentWatchdog};
 
 /// Wrapper around main which supports returning errors
 fn _main() -> Result<(), Error> {
     // get access to all required peripherals
-    let mut stdout = hio::hstdout()?;
-    let core_peripherals = cortex_m::Peripherals::take()?;
-    let dev_peripherals = pac::Peripherals::take()?;
-    let mut flash = dev_peripherals.FLASH.constrain();
-    let mut radio_clock = dev_peripherals.RCC.constrain();
-    let clocks = radio_clock.cfgr.freeze(&mut flash.acr);
-    let mut gpiob = dev_peripherals.GPIOB.split(&mut radio_clock.apb2);
-    let tim2 = Timer::tim2(dev_peripherals.TIM2, &clocks, &mut radio_clock.apb1);
-    let mut main_countdown = tim2.start_count_down(100.ms());
+    let mut core_peripherals = cortex_m::Peripherals::take().context("core peripherals")?;
+    let dev_peripherals = pac::Peripherals::take().context("device peripherals")?;
+    let Board {
+        clocks,
+        one_wire,
+        iwdg,
+        dbgmcu,
+        ..
+    } = Board::new(dev_peripherals, Profile::FULL)?;
+    crash::report();
+    info!("reset by {}", ResetCause::take());
+    let dwt = Dwt::new(
+        &mut core_peripherals.DCB,
+        &mut core_peripherals.DWT,
+        &clocks,
+    );
+    let mut scheduler = Scheduler::<_, Error, 2>::new(CycleClock::new(dwt));
     let mut delay = Delay::new(core_peripherals.SYST, clocks);
 
     // temp probe
-    let pb12 = gpiob.pb12.into_open_drain_output(&mut gpiob.crh);
-    let mut temp_probe = get_temp_probe(pb12, &mut delay, &mut stdout)?;
+    let mut temp_probe =
+        find_temp_probe::<_, _, _, Error>(one_wire, &mut delay).context("find probe")?;
+    if temp_probe.is_none() {
+        warn!("no probe found, connect one and reset");
+    }
 
-    loop {
-        // read the temperature sensor
-        if let Some((ref probe, ref mut owb)) = temp_probe {
-            let temp_sensor = probe.read_data(owb, &mut delay)?;
-            write!(
-                stdout,
-                "temp {} mC",
-                (temp_sensor.temperature * 1000.) as i32
-            )?;
+    // reset when the sampler gets stuck
+    let mut dog = IndependentWatchdog::new(iwdg);
+    dog.stop_on_debug(&dbgmcu, true);
+    dog.start(2000.ms());
+    let now = scheduler.now();
+    let supervisor = RefCell::new(Supervisor::<_, 1>::new(dog));
+    // without a probe there is nothing to supervise, but the watchdog is fed
+    let sampler = match temp_probe {
+        Some(_) => Some(
+            supervisor
+                .borrow_mut()
+                .register("sampler", 1000, now)
+                .context("watchdog")?,
+        ),
+        None => None,
+    };
+
+    // read the temperature sensor
+    let mut sample = |now| {
+        if let (Some((ref probe, ref mut owb)), Some(sampler)) = (&mut temp_probe, sampler) {
+            let temp = read_temperature(probe, owb, &mut delay).context("read probe")?;
+            info!("temp {}", temp);
+            supervisor.borrow_mut().check_in(sampler, now);
         }
+        Ok(())
+    };
 
-        block!(main_countdown.wait())?;
-    }
+    // the supervisor logs which task is late
+    let mut feed = |now| {
+        supervisor.borrow_mut().feed(now).ok();
+        Ok(())
+    };
+
+    scheduler
+        .every("sampler", 100, &mut sample)
+        .context("scheduler")?;
+    scheduler
+        .every("watchdog", 100, &mut feed)
+        .context("scheduler")?;
+
+    scheduler.run()
 }
 
 #[entry]
@@ -0,0 +1,400 @@
+//! A sequence of bits of a fixed capacity, like the levels of some pins
+//!
+//! The bits keep the order in which they were pushed, and print in binary,
+//! first bit first. [`Bitstring::format`] prints them in hex, in groups, or
+//! with the first bit as the least significant instead. The alternate flag
+//! adds a `0b` or `0x` prefix.
+//!
+//! ```ignore
+//! let levels: Bitstring = pins.iter().map(|pin| gpio.is_high(*pin)).collect();
+//! info!("{:#}", levels.format().hex().group(2));
+//! for (index, high) in last.changes(&levels) {
+//!     info!("{} is now {}", pins[index], high);
+//! }
+//! ```
+
+use core::fmt;
+use core::iter::FromIterator;
+use core::ops::BitXor;
+
+/// Which bit a value or the output starts with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Order {
+    /// The first bit is the most significant, and prints first
+    MsbFirst,
+    /// The first bit is the least significant, and prints last
+    LsbFirst,
+}
+
+/// The bitstring is at its capacity
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Full;
+
+/// Up to `BYTES * 8` bits, 64 by default
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Bitstring<const BYTES: usize = 8> {
+    /// The bits from the LSB of the first byte on, with the unused ones clear
+    bytes: [u8; BYTES],
+    len: usize,
+}
+
+impl<const BYTES: usize> Default for Bitstring<BYTES> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const BYTES: usize> Bitstring<BYTES> {
+    pub const CAPACITY: usize = BYTES * 8;
+
+    pub const fn new() -> Self {
+        Self {
+            bytes: [0; BYTES],
+            len: 0,
+        }
+    }
+
+    /// The low `len` bits of the value, taken in the order
+    pub fn from_value(value: u64, len: usize, order: Order) -> Self {
+        let len = len.min(64);
+        (0..len)
+            .map(|i| match order {
+                Order::MsbFirst => value & (1 << (len - 1 - i)) != 0,
+                Order::LsbFirst => value & (1 << i) != 0,
+            })
+            .collect()
+    }
+
+    /// The first 64 bits as a value, in the order
+    pub fn value(&self, order: Order) -> u64 {
+        let len = self.len.min(64);
+        self.iter()
+            .take(len)
+            .enumerate()
+            .fold(0, |value, (i, bit)| {
+                let shift = match order {
+                    Order::MsbFirst => len - 1 - i,
+                    Order::LsbFirst => i,
+                };
+                value | (bit as u64) << shift
+            })
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == Self::CAPACITY
+    }
+
+    /// Add a bit at the end
+    pub fn push(&mut self, bit: bool) -> Result<(), Full> {
+        if self.is_full() {
+            return Err(Full);
+        }
+        self.len += 1;
+        self.set(self.len - 1, bit);
+        Ok(())
+    }
+
+    pub fn get(&self, index: usize) -> Option<bool> {
+        (index < self.len).then(|| self.bytes[index / 8] & (1 << (index % 8)) != 0)
+    }
+
+    /// Change a bit, which panics past the end like a slice
+    pub fn set(&mut self, index: usize, bit: bool) {
+        assert!(index < self.len, "bit {} of {}", index, self.len);
+        let mask = 1 << (index % 8);
+        if bit {
+            self.bytes[index / 8] |= mask;
+        } else {
+            self.bytes[index / 8] &= !mask;
+        }
+    }
+
+    /// Drop the bits from the index on
+    pub fn truncate(&mut self, len: usize) {
+        while self.len > len {
+            self.set(self.len - 1, false);
+            self.len -= 1;
+        }
+    }
+
+    pub fn count_ones(&self) -> usize {
+        self.bytes
+            .iter()
+            .map(|byte| byte.count_ones() as usize)
+            .sum()
+    }
+
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = bool> + ExactSizeIterator + '_ {
+        (0..self.len).map(move |i| self.bytes[i / 8] & (1 << (i % 8)) != 0)
+    }
+
+    /// The index and the new bit of every bit which differs in the newer one
+    ///
+    /// A bit past the end of the shorter one counts as clear.
+    pub fn changes<'a>(&'a self, newer: &'a Self) -> impl Iterator<Item = (usize, bool)> + 'a {
+        (0..self.len.max(newer.len)).filter_map(move |i| {
+            let new = newer.get(i).unwrap_or(false);
+            (self.get(i).unwrap_or(false) != new).then_some((i, new))
+        })
+    }
+
+    /// Print in binary, first bit first, until told otherwise
+    pub fn format(&self) -> Format<'_, BYTES> {
+        Format {
+            bits: self,
+            hex: false,
+            order: Order::MsbFirst,
+            group: 0,
+        }
+    }
+}
+
+/// The bits which differ, as long as the longer one
+impl<const BYTES: usize> BitXor for Bitstring<BYTES> {
+    type Output = Self;
+
+    fn bitxor(self, other: Self) -> Self {
+        let mut bytes = [0; BYTES];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = self.bytes[i] ^ other.bytes[i];
+        }
+        Self {
+            bytes,
+            len: self.len.max(other.len),
+        }
+    }
+}
+
+impl<const BYTES: usize> Extend<bool> for Bitstring<BYTES> {
+    /// Panics when the bits do not fit
+    fn extend<I: IntoIterator<Item = bool>>(&mut self, bits: I) {
+        for bit in bits {
+            self.push(bit)
+                .unwrap_or_else(|_| panic!("a bitstring holds {} bits", Self::CAPACITY));
+        }
+    }
+}
+
+impl<const BYTES: usize> FromIterator<bool> for Bitstring<BYTES> {
+    /// Panics when the bits do not fit
+    fn from_iter<I: IntoIterator<Item = bool>>(bits: I) -> Self {
+        let mut bitstring = Self::new();
+        bitstring.extend(bits);
+        bitstring
+    }
+}
+
+impl<const BYTES: usize> fmt::Display for Bitstring<BYTES> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.format(), fmt)
+    }
+}
+
+impl<const BYTES: usize> fmt::Debug for Bitstring<BYTES> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "Bitstring({})", self)
+    }
+}
+
+/// How a bitstring prints, from [`Bitstring::format`]
+#[derive(Debug, Clone, Copy)]
+pub struct Format<'a, const BYTES: usize> {
+    bits: &'a Bitstring<BYTES>,
+    hex: bool,
+    order: Order,
+    group: usize,
+}
+
+impl<const BYTES: usize> Format<'_, BYTES> {
+    /// A digit for every 4 bits, padded with zeros at the most significant end
+    pub fn hex(mut self) -> Self {
+        self.hex = true;
+        self
+    }
+
+    pub fn binary(mut self) -> Self {
+        self.hex = false;
+        self
+    }
+
+    /// Which bit prints first
+    pub fn order(mut self, order: Order) -> Self {
+        self.order = order;
+        self
+    }
+
+    /// Separate every so many digits with `_`, counted from the least
+    /// significant end like the digits of a number
+    pub fn group(mut self, digits: usize) -> Self {
+        self.group = digits;
+        self
+    }
+}
+
+impl<const BYTES: usize> fmt::Display for Format<'_, BYTES> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let len = self.bits.len();
+        let width = if self.hex { 4 } else { 1 };
+        let digits = len.div_ceil(width);
+        let padding = digits * width - len;
+
+        // the bits as they print, from the most significant
+        let bit = |position: usize| match self.order {
+            Order::MsbFirst => self.bits.get(position),
+            Order::LsbFirst => self.bits.get(len - 1 - position),
+        };
+
+        if fmt.alternate() {
+            fmt.write_str(if self.hex { "0x" } else { "0b" })?;
+        }
+        for digit in 0..digits {
+            if digit > 0 && self.group > 0 && (digits - digit).is_multiple_of(self.group) {
+                fmt.write_str("_")?;
+            }
+            let value = (0..width).fold(0, |value, j| {
+                let high = (digit * width + j)
+                    .checked_sub(padding)
+                    .and_then(bit)
+                    .unwrap_or(false);
+                value << 1 | high as u32
+            });
+            let c = core::char::from_digit(value, 16).unwrap_or('?');
+            fmt.write_fmt(format_args!("{}", c))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec::Vec;
+
+    fn bits(text: &str) -> Bitstring {
+        text.chars().map(|c| c == '1').collect()
+    }
+
+    #[test]
+    fn values_in_both_orders() {
+        let msb = Bitstring::<1>::from_value(0b0110, 5, Order::MsbFirst);
+        assert_eq!(
+            msb.iter().collect::<Vec<_>>(),
+            [false, false, true, true, false]
+        );
+        assert_eq!(msb.value(Order::MsbFirst), 0b0110);
+        assert_eq!(msb.value(Order::LsbFirst), 0b01100);
+
+        let lsb = Bitstring::<1>::from_value(0b0110, 5, Order::LsbFirst);
+        assert_eq!(
+            lsb.iter().collect::<Vec<_>>(),
+            [false, true, true, false, false]
+        );
+        assert_eq!(lsb.value(Order::LsbFirst), 0b0110);
+
+        // only the low bits count, and at most 64 of them
+        assert_eq!(
+            Bitstring::<1>::from_value(0xff, 4, Order::MsbFirst).count_ones(),
+            4
+        );
+        let wide = Bitstring::<9>::from_value(u64::MAX, 72, Order::MsbFirst);
+        assert_eq!(wide.len(), 64);
+        assert_eq!(wide.value(Order::LsbFirst), u64::MAX);
+    }
+
+    #[test]
+    fn binary_and_hex() {
+        let b = bits("101100111");
+        assert_eq!(format!("{}", b), "101100111");
+        assert_eq!(format!("{:?}", b), "Bitstring(101100111)");
+        // the padding goes at the most significant end
+        assert_eq!(format!("{}", b.format().hex()), "167");
+        assert_eq!(
+            format!("{}", b.format().hex().order(Order::LsbFirst)),
+            "1cd"
+        );
+        assert_eq!(
+            format!("{}", b.format().order(Order::LsbFirst)),
+            "111001101"
+        );
+        assert_eq!(format!("{}", bits("11").format().hex()), "3");
+        assert_eq!(format!("{}", Bitstring::<1>::new()), "");
+    }
+
+    #[test]
+    fn groups_from_the_least_significant_end() {
+        let b = bits("1011001110");
+        assert_eq!(format!("{}", b.format().group(4)), "10_1100_1110");
+        assert_eq!(format!("{}", b.format().group(5)), "10110_01110");
+        assert_eq!(format!("{}", b.format().group(10)), "1011001110");
+        assert_eq!(format!("{}", b.format().group(1).hex()), "2_c_e");
+        assert_eq!(format!("{}", b.format().group(0)), "1011001110");
+    }
+
+    #[test]
+    fn the_alternate_flag_adds_a_prefix() {
+        let b = bits("100101");
+        assert_eq!(format!("{:#}", b), "0b100101");
+        assert_eq!(format!("{:#}", b.format().hex()), "0x25");
+        assert_eq!(
+            format!("{:#}", b.format().hex().binary().group(3)),
+            "0b100_101"
+        );
+    }
+
+    #[test]
+    fn changes_between_lengths() {
+        let old = bits("1100");
+        let new = bits("1010011");
+        let changes: Vec<_> = old.changes(&new).collect();
+        assert_eq!(changes, [(1, false), (2, true), (5, true), (6, true)]);
+        // the other way round, the missing bits turn clear
+        let changes: Vec<_> = new.changes(&old).collect();
+        assert_eq!(changes, [(1, true), (2, false), (5, false), (6, false)]);
+        assert_eq!(old.changes(&old).count(), 0);
+    }
+
+    #[test]
+    fn xor_marks_the_changes() {
+        let xor = bits("1100") ^ bits("1010011");
+        assert_eq!(xor, bits("0110011"));
+        assert_eq!(
+            xor.iter().enumerate().filter(|&(_, bit)| bit).count(),
+            bits("1100").changes(&bits("1010011")).count()
+        );
+    }
+
+    #[test]
+    fn truncate_clears_the_dropped_bits() {
+        let mut b = bits("10111");
+        b.truncate(2);
+        assert_eq!(b, bits("10"));
+        assert_eq!(b.count_ones(), 1);
+        assert_eq!(b.get(2), None);
+        b.truncate(5);
+        assert_eq!(b.len(), 2);
+        b.push(false).unwrap();
+        assert_eq!(b, bits("100"));
+    }
+
+    #[test]
+    fn push_stops_at_the_capacity() {
+        let mut b: Bitstring<1> = core::iter::repeat_n(true, 8).collect();
+        assert!(b.is_full());
+        assert_eq!(b.push(false), Err(Full));
+        assert_eq!(b.len(), Bitstring::<1>::CAPACITY);
+    }
+
+    #[test]
+    #[should_panic(expected = "a bitstring holds 8 bits")]
+    fn collecting_too_many_bits_panics() {
+        let _: Bitstring<1> = core::iter::repeat_n(false, 9).collect();
+    }
+}
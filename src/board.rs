@@ -0,0 +1,170 @@
+//! The wiring of the breadboard, as ready-to-use drivers
+//!
+//! By default the pins are the ones in the README. The `wiring-main`
+//! feature selects the wiring `main.rs` used to have instead:
+//!
+//! | part              | README        | `wiring-main` |
+//! |-------------------|---------------|---------------|
+//! | numpad row 0      | PA15          | PA15          |
+//! | numpad columns    | PB3, PB4, PB5 | PB3, -, PB5   |
+//! | matrix DIN/CS/CLK | PB7, PB8, PB6 | PB9, PB4, PB8 |
+//! | I2C2 SCL/SDA      | PB10, PB11    | PB10, PB11    |
+//! | 1-Wire            | PB12          | PB12          |
+//! | LED               | PC13          | PC13          |
+
+use max7219::{connectors::PinConnector, MAX7219};
+use one_wire_bus::OneWire;
+use stm32f1xx_hal::gpio::gpiob::{PB10, PB11};
+use stm32f1xx_hal::gpio::gpioc::PC13;
+use stm32f1xx_hal::gpio::{Alternate, OpenDrain, Output, PushPull, Pxx};
+use stm32f1xx_hal::rcc::{Clocks, APB1};
+use stm32f1xx_hal::{afio, i2c, pac, prelude::*};
+
+use crate::clocks::Profile;
+use crate::error::{Context, Error};
+use crate::log;
+use crate::numpad::Numpad;
+use crate::pins::{self, Port};
+
+type Pin = Pxx<Output<PushPull>>;
+
+pub type Matrix = MAX7219<PinConnector<Pin, Pin, Pin>>;
+pub type I2c =
+    i2c::BlockingI2c<pac::I2C2, (PB10<Alternate<OpenDrain>>, PB11<Alternate<OpenDrain>>)>;
+pub type OneWirePin = Pxx<Output<OpenDrain>>;
+/// The on-board LED, which is lit while the pin is low
+pub type Led = PC13<Output<PushPull>>;
+
+/// The pins which the parts pull up, which `pin_tester` checks
+pub const PULLED_UP: [(pins::Pin, &str); 3] = [
+    (pins::Pin::new(Port::B, 10), "I2C SCL"),
+    (pins::Pin::new(Port::B, 11), "I2C SDA"),
+    (pins::Pin::new(Port::B, 12), "1-Wire"),
+];
+
+/// The pins which the breadboard connects to each other, which `pin_tester` checks
+///
+/// None are, as the numpad only connects a row to a column while a key is
+/// pressed.
+pub const CONNECTED: [(pins::Pin, pins::Pin); 0] = [];
+
+/// Everything on the breadboard
+pub struct Board {
+    pub clocks: Clocks,
+    pub numpad: Numpad,
+    pub matrix: Matrix,
+    pub i2c: I2c,
+    pub one_wire: OneWire<OneWirePin>,
+    pub led: Led,
+
+    // what is left for the binaries
+    pub apb1: APB1,
+    pub afio: afio::Parts,
+    pub exti: pac::EXTI,
+    pub tim2: pac::TIM2,
+    pub tim3: pac::TIM3,
+    pub tim4: pac::TIM4,
+    pub iwdg: pac::IWDG,
+    pub dbgmcu: pac::DBGMCU,
+}
+
+impl Board {
+    /// Set up the clocks with the profile, the log and all parts
+    pub fn new(dev_peripherals: pac::Peripherals, profile: Profile) -> Result<Self, Error> {
+        let mut flash = dev_peripherals.FLASH.constrain();
+        let mut radio_clock = dev_peripherals.RCC.constrain();
+        let clocks = profile.freeze(radio_clock.cfgr, &mut flash.acr);
+        log::init(&clocks);
+
+        let mut afio = dev_peripherals.AFIO.constrain(&mut radio_clock.apb2);
+        let mut gpioa = dev_peripherals.GPIOA.split(&mut radio_clock.apb2);
+        let mut gpiob = dev_peripherals.GPIOB.split(&mut radio_clock.apb2);
+        let mut gpioc = dev_peripherals.GPIOC.split(&mut radio_clock.apb2);
+
+        // PA15, PB3 and PB4 are JTAG pins until released
+        let (pa15, pb3, pb4) = afio.mapr.disable_jtag(gpioa.pa15, gpiob.pb3, gpiob.pb4);
+
+        // 4x4 numpad, of which only part is connected
+        let row_0 = pa15.into_push_pull_output(&mut gpioa.crh).downgrade();
+        let col_0 = pb3.into_pull_down_input(&mut gpiob.crl).downgrade();
+        let col_2 = gpiob.pb5.into_pull_down_input(&mut gpiob.crl).downgrade();
+
+        #[cfg(not(feature = "wiring-main"))]
+        let (col_1, matrix) = (
+            Some(pb4.into_pull_down_input(&mut gpiob.crl).downgrade()),
+            MAX7219::from_pins(
+                1,
+                /*data*/ gpiob.pb7.into_push_pull_output(&mut gpiob.crl).downgrade(),
+                /*cs*/ gpiob.pb8.into_push_pull_output(&mut gpiob.crh).downgrade(),
+                /*sck*/ gpiob.pb6.into_push_pull_output(&mut gpiob.crl).downgrade(),
+            ),
+        );
+
+        // the matrix takes PB4 from the numpad
+        #[cfg(feature = "wiring-main")]
+        let (col_1, matrix) = (
+            None,
+            MAX7219::from_pins(
+                1,
+                /*data*/ gpiob.pb9.into_push_pull_output(&mut gpiob.crh).downgrade(),
+                /*cs*/ pb4.into_push_pull_output(&mut gpiob.crl).downgrade(),
+                /*sck*/ gpiob.pb8.into_push_pull_output(&mut gpiob.crh).downgrade(),
+            ),
+        );
+
+        let numpad = Numpad::new::<Error>(
+            [Some(row_0), None, None, None],
+            [Some(col_0), col_1, Some(col_2), None],
+        )
+        .context("numpad")?;
+        let matrix = matrix.context("matrix")?;
+
+        let i2c = i2c::BlockingI2c::i2c2(
+            dev_peripherals.I2C2,
+            (
+                /* sck = clock */ gpiob.pb10.into_alternate_open_drain(&mut gpiob.crh),
+                /* data = sda/sdi */ gpiob.pb11.into_alternate_open_drain(&mut gpiob.crh),
+            ),
+            i2c::Mode::Standard {
+                frequency: 100_000.hz(),
+            },
+            clocks,
+            &mut radio_clock.apb1,
+            /* start_timeout_us */
+            1000,
+            /* start_retries */
+            10,
+            /* addr_timeout_us */
+            1000,
+            /* data_timeout_us */
+            1000,
+        );
+
+        let one_wire = OneWire::new(
+            gpiob
+                .pb12
+                .into_open_drain_output(&mut gpiob.crh)
+                .downgrade(),
+        )
+        .context("1-wire")?;
+
+        let led = gpioc.pc13.into_push_pull_output(&mut gpioc.crh);
+
+        Ok(Self {
+            clocks,
+            numpad,
+            matrix,
+            i2c,
+            one_wire,
+            led,
+            apb1: radio_clock.apb1,
+            afio,
+            exti: dev_peripherals.EXTI,
+            tim2: dev_peripherals.TIM2,
+            tim3: dev_peripherals.TIM3,
+            tim4: dev_peripherals.TIM4,
+            iwdg: dev_peripherals.IWDG,
+            dbgmcu: dev_peripherals.DBGMCU,
+        })
+    }
+}
@@ -0,0 +1,265 @@
+//! Sampling 8 pins of a port into RAM, from TIM2 through DMA
+//!
+//! Every update of TIM2 asks DMA1 channel 2 to copy the input register of the
+//! port into a ring buffer, so sampling takes no time of the core. The core
+//! only follows the buffer to find the trigger, and stops the DMA when enough
+//! samples came after it.
+//!
+//! ```ignore
+//! let mut capture = Capture::new(tim2, dma.2, Port::A, &clocks, &mut apb1).context("capture")?;
+//! let samples = capture.run(&settings, || rx.read().is_ok())?;
+//! ```
+
+use core::ptr;
+use core::sync::atomic::{AtomicBool, Ordering};
+use stm32f1xx_hal::dma::dma1;
+use stm32f1xx_hal::pac;
+use stm32f1xx_hal::rcc::{Clocks, APB1};
+use stm32f1xx_hal::timer::Timer;
+
+use crate::pins::Port;
+use crate::sump::Settings;
+
+/// Samples in the ring buffer, which takes most of the RAM
+pub const SAMPLES: usize = 12 * 1024;
+
+/// The fastest the DMA keeps up with, next to the USART
+pub const MAX_RATE: u32 = 1_000_000;
+
+/// Samples which may still come in after the last is due, before the oldest
+/// one to send is overwritten
+const MARGIN: u32 = 256;
+
+/// The most samples a capture holds
+pub const MAX_SAMPLES: u32 = SAMPLES as u32 - MARGIN;
+
+static mut BUFFER: [u8; SAMPLES] = [0; SAMPLES];
+static TAKEN: AtomicBool = AtomicBool::new(false);
+
+/// The address of the input data register, which comes after CRL and CRH
+fn idr(port: Port) -> u32 {
+    port.block() as u32 + 0x08
+}
+
+/// The samples of a finished capture, oldest first
+pub struct Samples<'a> {
+    buffer: &'a [u8; SAMPLES],
+    /// The count of the first sample
+    start: u64,
+    len: u32,
+}
+
+impl Samples<'_> {
+    pub fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, index: usize) -> u8 {
+        self.buffer[((self.start + index as u64) % SAMPLES as u64) as usize]
+    }
+
+    /// The samples from the last to the first, as SUMP sends them
+    pub fn newest_first(&self) -> impl Iterator<Item = u8> + '_ {
+        (0..self.len()).rev().map(move |index| self.get(index))
+    }
+}
+
+/// Why a capture stopped without samples
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aborted {
+    /// The host asked to stop
+    Host,
+    /// The core fell more than the buffer behind the DMA
+    Overrun,
+}
+
+/// The timer, DMA channel and buffer of the sampler
+pub struct Capture {
+    tim: pac::TIM2,
+    timer_hz: u32,
+    dma: dma1::C2,
+    buffer: &'static mut [u8; SAMPLES],
+    /// Samples written since the start, as far as the core knows
+    count: u64,
+    /// Where the DMA was in the buffer when the count was updated
+    position: usize,
+}
+
+impl Capture {
+    /// Set up the timer and DMA to sample pins 0-7 of the port, which only one capture may
+    pub fn new(
+        tim2: pac::TIM2,
+        mut dma: dma1::C2,
+        port: Port,
+        clocks: &Clocks,
+        apb1: &mut APB1,
+    ) -> Option<Self> {
+        if TAKEN.swap(true, Ordering::SeqCst) {
+            return None;
+        }
+        // SAFETY: the flag hands out the buffer once, and only the DMA shares it
+        let buffer = unsafe { &mut *ptr::addr_of_mut!(BUFFER) };
+
+        // only used to enable and reset the timer
+        let tim = Timer::tim2(tim2, clocks, apb1).release();
+
+        // read the whole register, and keep the low byte
+        dma.set_peripheral_address(idr(port), false);
+        dma.set_memory_address(buffer.as_ptr() as u32, true);
+        dma.ch().cr.modify(|_, w| {
+            w.dir()
+                .from_peripheral()
+                .circ()
+                .enabled()
+                .psize()
+                .bits16()
+                .msize()
+                .bits8()
+                .pl()
+                .very_high()
+        });
+
+        Some(Self {
+            tim,
+            timer_hz: clocks.pclk1_tim().0,
+            dma,
+            buffer,
+            count: 0,
+            position: 0,
+        })
+    }
+
+    /// Start sampling at about the rate, and return the rate it got
+    fn start(&mut self, rate: u32) -> u32 {
+        let ticks = (self.timer_hz / rate.clamp(1, MAX_RATE)).max(1);
+        let prescaler = (ticks - 1) / 0x1_0000;
+        let reload = ticks / (prescaler + 1) - 1;
+
+        self.tim.cr1.modify(|_, w| w.cen().clear_bit());
+        self.tim.psc.write(|w| w.psc().bits(prescaler as u16));
+        self.tim.arr.write(|w| w.arr().bits(reload as u16));
+        // load the prescaler without a request to the DMA
+        self.tim.egr.write(|w| w.ug().set_bit());
+        self.tim.sr.modify(|_, w| w.uif().clear_bit());
+
+        self.dma.set_transfer_length(SAMPLES);
+        self.dma.start();
+        self.count = 0;
+        self.position = 0;
+
+        self.tim.dier.write(|w| w.ude().set_bit());
+        self.tim.cr1.modify(|_, w| w.cen().set_bit());
+        self.timer_hz / ((prescaler + 1) * (reload + 1))
+    }
+
+    fn stop(&mut self) {
+        self.tim.cr1.modify(|_, w| w.cen().clear_bit());
+        self.tim.dier.write(|w| w.ude().clear_bit());
+        self.dma.stop();
+    }
+
+    /// Count what the DMA wrote since the last update
+    ///
+    /// The DMA counts down to the end of the buffer and starts over, so this
+    /// has to be called at least once a buffer.
+    fn update(&mut self) -> u64 {
+        let position = SAMPLES - self.dma.get_ndtr() as usize;
+        let written = (position + SAMPLES - self.position) % SAMPLES;
+        self.position = position;
+        self.count += written as u64;
+        self.count
+    }
+
+    fn sample(&self, count: u64) -> u8 {
+        // SAFETY: a byte of the buffer, which the DMA may write at the same time
+        unsafe { ptr::read_volatile(&self.buffer[(count % SAMPLES as u64) as usize]) }
+    }
+
+    /// Capture as the settings ask, until the host aborts
+    ///
+    /// The samples before the trigger come from the ring buffer, so the
+    /// trigger is only looked for once enough of them are in. Returns the
+    /// rate it sampled at as well.
+    pub fn run(
+        &mut self,
+        settings: &Settings,
+        mut aborted: impl FnMut() -> bool,
+    ) -> Result<(Samples<'_>, u32), Aborted> {
+        let (before, after) = settings.window(MAX_SAMPLES);
+        let (before, after) = (before as u64, after as u64);
+        let trigger = settings.trigger;
+        let rate = self.start(settings.rate());
+
+        let mut scanned = before;
+        let mut fired = None;
+        let result = loop {
+            let count = self.update();
+            if aborted() {
+                break Err(Aborted::Host);
+            }
+
+            if let Some(fired) = fired {
+                if count >= fired + after {
+                    break Ok(fired + after);
+                }
+            } else if count > before {
+                // the oldest sample to look at must still be there
+                if count - scanned > MAX_SAMPLES as u64 {
+                    break Err(Aborted::Overrun);
+                }
+                let mut previous = self.sample(scanned.saturating_sub(1));
+                while scanned < count {
+                    let sample = self.sample(scanned);
+                    if trigger.fires(previous, sample) {
+                        fired = Some(scanned);
+                        break;
+                    }
+                    previous = sample;
+                    scanned += 1;
+                }
+            }
+        };
+        self.stop();
+
+        let end = result?;
+        // the DMA stopped a little after the end, check it did not lap it
+        if self.update() - (end - before - after) > SAMPLES as u64 {
+            return Err(Aborted::Overrun);
+        }
+        let samples = Samples {
+            buffer: self.buffer,
+            start: end - before - after,
+            len: (before + after) as u32,
+        };
+        Ok((samples, rate))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec::Vec;
+
+    #[test]
+    fn samples_wrap_around_the_ring() {
+        let mut buffer = [0; SAMPLES];
+        for (i, sample) in buffer.iter_mut().enumerate() {
+            *sample = i as u8;
+        }
+        // the samples started in a lap before, and run over the end
+        let samples = Samples {
+            buffer: &buffer,
+            start: 3 * SAMPLES as u64 - 2,
+            len: 4,
+        };
+        assert_eq!(samples.len(), 4);
+        assert_eq!(samples.get(0), (SAMPLES - 2) as u8);
+        assert_eq!(samples.get(2), 0);
+        let newest: Vec<u8> = samples.newest_first().collect();
+        assert_eq!(newest, [1, 0, (SAMPLES - 1) as u8, (SAMPLES - 2) as u8]);
+    }
+}
@@ -0,0 +1,149 @@
+//! Clock tree profiles, checked while compiling
+//!
+//! ```ignore
+//! let clocks = Profile::FULL.freeze(radio_clock.cfgr, &mut flash.acr);
+//! ```
+//!
+//! A custom profile is checked as well when it is a `const`:
+//!
+//! ```ignore
+//! const SLOW: Profile = Profile { sysclk: 16_000_000, ..Profile::FULL }.checked();
+//! ```
+
+use stm32f1xx_hal::flash::ACR;
+use stm32f1xx_hal::prelude::*;
+use stm32f1xx_hal::rcc::{Clocks, CFGR};
+
+/// The crystal on the blue pill
+pub const HSE_HZ: u32 = 8_000_000;
+/// The internal oscillator
+pub const HSI_HZ: u32 = 8_000_000;
+
+const MAX_SYSCLK: u32 = 72_000_000;
+const MAX_PCLK1: u32 = 36_000_000;
+const MAX_ADCCLK: u32 = 14_000_000;
+
+/// The frequencies of the clock tree
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Profile {
+    /// Run from the crystal instead of the internal oscillator
+    pub hse: bool,
+    pub sysclk: u32,
+    /// AHB, which also drives the core and SysTick
+    pub hclk: u32,
+    /// APB1, at most 36 MHz: TIM2-4, I2C, USART2-3, USB
+    pub pclk1: u32,
+    /// APB2: GPIO, TIM1, USART1, ADC
+    pub pclk2: u32,
+    /// ADC, at most 14 MHz
+    pub adcclk: u32,
+}
+
+impl Profile {
+    /// The fastest the chip can go
+    pub const FULL: Profile = Profile {
+        hse: true,
+        sysclk: 72_000_000,
+        hclk: 72_000_000,
+        pclk1: 36_000_000,
+        pclk2: 72_000_000,
+        adcclk: 12_000_000,
+    }
+    .checked();
+
+    /// The fastest with a valid 48 MHz USB clock, without dividing it by 1.5
+    pub const USB: Profile = Profile {
+        hse: true,
+        sysclk: 48_000_000,
+        hclk: 48_000_000,
+        pclk1: 24_000_000,
+        pclk2: 48_000_000,
+        adcclk: 12_000_000,
+    }
+    .checked();
+
+    /// The crystal without the PLL
+    pub const LOW_POWER: Profile = Profile {
+        hse: true,
+        sysclk: 8_000_000,
+        hclk: 8_000_000,
+        pclk1: 8_000_000,
+        pclk2: 8_000_000,
+        adcclk: 4_000_000,
+    }
+    .checked();
+
+    /// Panic, at compile time for a `const`, unless the hardware can make exactly these frequencies
+    pub const fn checked(self) -> Self {
+        let source = if self.hse { HSE_HZ } else { HSI_HZ };
+
+        // either the oscillator itself, or the PLL from it (HSI is halved first)
+        if self.sysclk != source {
+            let input = if self.hse { HSE_HZ } else { HSI_HZ / 2 };
+            assert!(
+                self.sysclk.is_multiple_of(input),
+                "sysclk is not a multiple of the PLL input"
+            );
+            let multiplier = self.sysclk / input;
+            assert!(
+                multiplier >= 2 && multiplier <= 16,
+                "PLL multiplier out of range"
+            );
+        }
+        assert!(self.sysclk <= MAX_SYSCLK, "sysclk above 72 MHz");
+
+        assert!(
+            divides(self.sysclk, self.hclk, &[1, 2, 4, 8, 16, 64, 128, 256, 512]),
+            "hclk is not sysclk divided by a power of 2"
+        );
+        assert!(
+            divides(self.hclk, self.pclk1, &[1, 2, 4, 8, 16]),
+            "pclk1 is not hclk divided by 1 to 16"
+        );
+        assert!(self.pclk1 <= MAX_PCLK1, "pclk1 above 36 MHz");
+        assert!(
+            divides(self.hclk, self.pclk2, &[1, 2, 4, 8, 16]),
+            "pclk2 is not hclk divided by 1 to 16"
+        );
+        assert!(
+            divides(self.pclk2, self.adcclk, &[2, 4, 6, 8]),
+            "adcclk is not pclk2 divided by 2, 4, 6 or 8"
+        );
+        assert!(self.adcclk <= MAX_ADCCLK, "adcclk above 14 MHz");
+
+        self
+    }
+
+    /// Check if USB gets its 48 MHz
+    pub const fn usb(&self) -> bool {
+        self.hse && (self.sysclk == 48_000_000 || self.sysclk == 72_000_000)
+    }
+
+    /// Configure the clock tree
+    pub fn freeze(self, cfgr: CFGR, acr: &mut ACR) -> Clocks {
+        let cfgr = if self.hse {
+            cfgr.use_hse(HSE_HZ.hz())
+        } else {
+            cfgr
+        };
+
+        cfgr.sysclk(self.sysclk.hz())
+            .hclk(self.hclk.hz())
+            .pclk1(self.pclk1.hz())
+            .pclk2(self.pclk2.hz())
+            .adcclk(self.adcclk.hz())
+            .freeze(acr)
+    }
+}
+
+/// Check if `to` is `from` divided by one of the divisors
+const fn divides(from: u32, to: u32, divisors: &[u32]) -> bool {
+    let mut i = 0;
+    while i < divisors.len() {
+        if from == to * divisors[i] {
+            return true;
+        }
+        i += 1;
+    }
+    false
+}
@@ -0,0 +1,206 @@
+//! A crash log which survives a reset
+//!
+//! The log lives in its own RAM region (see `memory.x`), which the runtime
+//! does not initialise. Unless the `semi` feature asks for panics over
+//! semihosting, the panic handler records the panic there and resets the
+//! chip, and the next boot reports it with [`report`].
+
+use core::fmt::{self, Write};
+use core::mem::MaybeUninit;
+use core::ptr;
+
+use crate::error;
+
+/// Marks a complete record, anything else is left over from power-on
+const MAGIC: u32 = 0xdead_c0de;
+/// How many bytes of text fit in the region
+pub const CAPACITY: usize = 500;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Record {
+    magic: u32,
+    len: u32,
+    checksum: u32,
+    text: [u8; CAPACITY],
+}
+
+#[link_section = ".uninit.crashlog"]
+static mut RECORD: MaybeUninit<Record> = MaybeUninit::uninit();
+
+fn checksum(text: &[u8]) -> u32 {
+    // FNV-1a
+    text.iter().fold(0x811c_9dc5, |hash, &byte| {
+        (hash ^ byte as u32).wrapping_mul(0x0100_0193)
+    })
+}
+
+/// The text of a crash from a previous run
+#[derive(Clone, Copy)]
+pub struct CrashLog {
+    len: usize,
+    text: [u8; CAPACITY],
+}
+
+impl CrashLog {
+    pub fn as_str(&self) -> &str {
+        let text = &self.text[..self.len];
+        match core::str::from_utf8(text) {
+            Ok(text) => text,
+            // the record is checked, but keep what is valid anyway
+            Err(e) => core::str::from_utf8(&text[..e.valid_up_to()]).unwrap_or_default(),
+        }
+    }
+}
+
+impl fmt::Display for CrashLog {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for CrashLog {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), fmt)
+    }
+}
+
+/// Writes into a fixed buffer, silently cutting off what does not fit
+struct Truncate<'a> {
+    buffer: &'a mut [u8],
+    len: usize,
+}
+
+impl Write for Truncate<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let space = self.buffer.len() - self.len;
+        let mut end = s.len().min(space);
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        self.buffer[self.len..self.len + end].copy_from_slice(&s.as_bytes()[..end]);
+        self.len += end;
+        Ok(())
+    }
+}
+
+/// Record a crash, replacing the previous one
+///
+/// Text beyond [`CAPACITY`] bytes is dropped. This should only be called
+/// with interrupts disabled, right before resetting.
+pub fn record<F>(f: F)
+where
+    F: FnOnce(&mut dyn Write) -> fmt::Result,
+{
+    let mut text = [0; CAPACITY];
+    let mut out = Truncate {
+        buffer: &mut text,
+        len: 0,
+    };
+    // whatever was written before an error is still worth keeping
+    f(&mut out).ok();
+    let len = out.len;
+
+    let record = Record {
+        magic: MAGIC,
+        len: len as u32,
+        checksum: checksum(&text[..len]),
+        text,
+    };
+
+    // SAFETY: only the crash handlers write the record, with interrupts disabled
+    unsafe { ptr::write_volatile(ptr::addr_of_mut!(RECORD), MaybeUninit::new(record)) };
+}
+
+/// Take the crash log of the previous run, if it crashed
+pub fn take() -> Option<CrashLog> {
+    let record = ptr::addr_of_mut!(RECORD).cast::<Record>();
+
+    // SAFETY: the fields are read one by one as plain words and bytes, and
+    // only the checked ones go into the log; the region is never read as a
+    // whole `Record`, which may not be initialised
+    unsafe {
+        let magic = ptr::read_volatile(ptr::addr_of!((*record).magic));
+        // clear the magic, so the crash is only reported once
+        ptr::write_volatile(ptr::addr_of_mut!((*record).magic), 0);
+        if magic != MAGIC {
+            return None;
+        }
+
+        let len = ptr::read_volatile(ptr::addr_of!((*record).len)) as usize;
+        if len > CAPACITY {
+            return None;
+        }
+
+        let stored = ptr::addr_of!((*record).text).cast::<u8>();
+        let mut text = [0; CAPACITY];
+        for (i, byte) in text[..len].iter_mut().enumerate() {
+            *byte = ptr::read_volatile(stored.add(i));
+        }
+        if ptr::read_volatile(ptr::addr_of!((*record).checksum)) != checksum(&text[..len]) {
+            return None;
+        }
+
+        Some(CrashLog { len, text })
+    }
+}
+
+/// Take the crash log of the previous run and log it
+pub fn report() -> Option<CrashLog> {
+    let crash = take();
+    if let Some(ref crash) = crash {
+        error!("previous run crashed: {}", crash);
+    }
+    crash
+}
+
+/// Record the panic and start over
+#[cfg(all(target_os = "none", not(feature = "semi")))]
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    cortex_m::interrupt::disable();
+
+    record(|out| {
+        out.write_str("panicked")?;
+        if let Some(location) = info.location() {
+            write!(
+                out,
+                " at {}:{}:{}",
+                location.file(),
+                location.line(),
+                location.column()
+            )?;
+        }
+        write!(out, ": {}", info.message())
+    });
+
+    cortex_m::peripheral::SCB::sys_reset()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the record is a single static, so one test goes through all of it
+    #[test]
+    fn a_record_is_taken_once_and_only_when_intact() {
+        record(|out| write!(out, "panicked at {}", 42));
+        assert_eq!(
+            take().map(|crash| format!("{}", crash)).as_deref(),
+            Some("panicked at 42")
+        );
+        assert!(take().is_none());
+
+        record(|out| out.write_str(&"x".repeat(CAPACITY + 10)));
+        assert_eq!(take().map(|crash| crash.as_str().len()), Some(CAPACITY));
+
+        record(|out| out.write_str("flipped"));
+        // SAFETY: no other test touches the record
+        unsafe {
+            let stored = ptr::addr_of_mut!(RECORD).cast::<Record>();
+            ptr::addr_of_mut!((*stored).text).cast::<u8>().write(b'F');
+        }
+        assert!(take().is_none());
+    }
+}
@@ -0,0 +1,43 @@
+use cortex_m::peripheral::{DCB, DWT};
+use stm32f1xx_hal::rcc::Clocks;
+
+/// A free-running counter of core clock cycles, for measuring short pulses
+pub trait CycleCounter {
+    /// The current count, wrapping around at `u32::MAX`
+    fn cycles(&self) -> u32;
+
+    /// How many cycles there are in a microsecond
+    fn cycles_per_us(&self) -> u32;
+
+    /// The number of whole microseconds since the given count
+    fn us_since(&self, start: u32) -> u32 {
+        self.cycles().wrapping_sub(start) / self.cycles_per_us()
+    }
+}
+
+/// The cycle counter of the Data Watchpoint and Trace unit
+pub struct Dwt {
+    cycles_per_us: u32,
+}
+
+impl Dwt {
+    /// Enable the cycle counter
+    pub fn new(dcb: &mut DCB, dwt: &mut DWT, clocks: &Clocks) -> Self {
+        dcb.enable_trace();
+        dwt.enable_cycle_counter();
+
+        Self {
+            cycles_per_us: clocks.sysclk().0 / 1_000_000,
+        }
+    }
+}
+
+impl CycleCounter for Dwt {
+    fn cycles(&self) -> u32 {
+        DWT::cycle_count()
+    }
+
+    fn cycles_per_us(&self) -> u32 {
+        self.cycles_per_us
+    }
+}
@@ -0,0 +1,388 @@
+use core::fmt;
+use embedded_hal::blocking::delay::DelayMs;
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+
+use crate::cycles::CycleCounter;
+
+/// How many times a read is attempted before giving up
+pub const RETRIES: u8 = 3;
+/// The sensor needs about a second between two reads
+pub const RETRY_DELAY_MS: u16 = 1000;
+/// How long the start signal has to be held low
+const START_MS: u16 = 18;
+/// No phase of the protocol takes longer than this
+const TIMEOUT_US: u32 = 100;
+/// High pulses longer than this are a 1 (26-28 µs for a 0, 70 µs for a 1)
+const ONE_THRESHOLD_US: u32 = 48;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error<E> {
+    /// Reading or writing the pin failed
+    Pin(E),
+    /// The sensor did not respond or stopped halfway
+    Timeout,
+    /// The checksum did not match the data
+    Checksum,
+}
+
+impl<E> From<E> for Error<E> {
+    fn from(item: E) -> Self {
+        Self::Pin(item)
+    }
+}
+
+/// A single measurement
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reading {
+    /// Relative humidity in tenths of a percent
+    pub humidity: u16,
+    /// Temperature in tenths of a degree Celsius
+    pub temperature: i16,
+}
+
+impl fmt::Display for Reading {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let sign = if self.temperature < 0 { "-" } else { "" };
+        let temp = self.temperature.unsigned_abs();
+        write!(
+            fmt,
+            "{}.{} %RH {}{}.{} °C",
+            self.humidity / 10,
+            self.humidity % 10,
+            sign,
+            temp / 10,
+            temp % 10
+        )
+    }
+}
+
+/// Decode the widths of the 40 high pulses (in microseconds) the sensor sends
+pub fn decode<E>(high_us: &[u32; 40]) -> Result<Reading, Error<E>> {
+    let mut bytes = [0u8; 5];
+    for (i, &width) in high_us.iter().enumerate() {
+        bytes[i / 8] <<= 1;
+        bytes[i / 8] |= (width > ONE_THRESHOLD_US) as u8;
+    }
+
+    let sum = bytes[..4].iter().fold(0u8, |acc, &b| acc.wrapping_add(b));
+    if sum != bytes[4] {
+        return Err(Error::Checksum);
+    }
+
+    // the top bit of the temperature decimals is the sign
+    let temperature = bytes[2] as i16 * 10 + (bytes[3] & 0x7f) as i16;
+    Ok(Reading {
+        humidity: bytes[0] as u16 * 10 + bytes[1] as u16,
+        temperature: if bytes[3] & 0x80 != 0 {
+            -temperature
+        } else {
+            temperature
+        },
+    })
+}
+
+/// A DHT11 on an open-drain pin with a pull-up
+pub struct Dht11<P, C> {
+    pin: P,
+    counter: C,
+}
+
+impl<P, C, E> Dht11<P, C>
+where
+    P: InputPin<Error = E> + OutputPin<Error = E>,
+    C: CycleCounter,
+{
+    /// Create a new Dht11, releasing the bus
+    pub fn new(mut pin: P, counter: C) -> Result<Self, E> {
+        pin.set_high()?;
+
+        Ok(Self { pin, counter })
+    }
+
+    /// Release the pin and cycle counter again
+    pub fn release(self) -> (P, C) {
+        (self.pin, self.counter)
+    }
+
+    /// Read the sensor, retrying after timeouts and checksum errors
+    pub fn read<D: DelayMs<u16>>(&mut self, delay: &mut D) -> Result<Reading, Error<E>> {
+        let mut attempt = 1;
+        loop {
+            match self.read_once(delay) {
+                Err(Error::Timeout | Error::Checksum) if attempt < RETRIES => {
+                    attempt += 1;
+                    delay.delay_ms(RETRY_DELAY_MS);
+                }
+
+                result => return result,
+            }
+        }
+    }
+
+    /// Read the sensor once
+    ///
+    /// The pulses are timed by busy-waiting, so interrupts should be kept short
+    /// or disabled while this runs.
+    pub fn read_once<D: DelayMs<u16>>(&mut self, delay: &mut D) -> Result<Reading, Error<E>> {
+        // start signal
+        self.pin.set_low()?;
+        delay.delay_ms(START_MS);
+        self.pin.set_high()?;
+
+        // response: the sensor pulls low for 80 µs, then releases for 80 µs
+        self.wait_while(true)?;
+        self.wait_while(false)?;
+        self.wait_while(true)?;
+
+        // every bit is 50 µs low followed by a high pulse whose width is the value
+        let mut high_us = [0; 40];
+        for width in high_us.iter_mut() {
+            self.wait_while(false)?;
+            *width = self.wait_while(true)?;
+        }
+
+        decode(&high_us)
+    }
+
+    /// Wait until the pin leaves the given level, returning how long that took
+    fn wait_while(&self, high: bool) -> Result<u32, Error<E>> {
+        let start = self.counter.cycles();
+        loop {
+            let elapsed = self.counter.us_since(start);
+            if self.pin.is_high()? != high {
+                return Ok(elapsed);
+            }
+            if elapsed > TIMEOUT_US {
+                return Err(Error::Timeout);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::{Cell, RefCell};
+    use core::convert::Infallible;
+    use std::vec::Vec;
+
+    /// The high pulses of humidity 45.0 %, 23.6 °C and checksum 0x4a, with
+    /// the few microseconds of jitter of a real sensor
+    const CAPTURED: [u32; 40] = [
+        26, 27, 70, 26, 71, 70, 27, 69, // 0x2d
+        27, 26, 26, 27, 26, 26, 27, 26, // 0x00
+        26, 27, 26, 72, 27, 70, 70, 71, // 0x17
+        27, 26, 27, 26, 26, 70, 71, 27, // 0x06
+        26, 70, 27, 26, 71, 26, 70, 27, // 0x4a
+    ];
+
+    /// The high pulses of the bytes, with the checksum after them
+    fn pulses(data: [u8; 4]) -> [u32; 40] {
+        let sum = data.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+        let mut high_us = [0; 40];
+        for (i, width) in high_us.iter_mut().enumerate() {
+            let byte = if i < 32 { data[i / 8] } else { sum };
+            *width = if byte & (0x80 >> (i % 8)) != 0 {
+                70
+            } else {
+                27
+            };
+        }
+        high_us
+    }
+
+    #[test]
+    fn decodes_a_captured_reading() {
+        let reading = decode::<Infallible>(&CAPTURED).unwrap();
+        assert_eq!(
+            reading,
+            Reading {
+                humidity: 450,
+                temperature: 236
+            }
+        );
+        assert_eq!(format!("{}", reading), "45.0 %RH 23.6 °C");
+    }
+
+    #[test]
+    fn the_top_bit_of_the_decimals_is_the_sign() {
+        let reading = decode::<Infallible>(&pulses([50, 0, 1, 0x85])).unwrap();
+        assert_eq!(reading.temperature, -15);
+        assert_eq!(format!("{}", reading), "50.0 %RH -1.5 °C");
+    }
+
+    #[test]
+    fn a_wrong_checksum_is_an_error() {
+        let mut high_us = CAPTURED;
+        // the last bit of the checksum
+        high_us[39] = 70;
+        assert_eq!(decode::<Infallible>(&high_us), Err(Error::Checksum));
+    }
+
+    #[test]
+    fn a_short_or_missing_pulse_reads_as_a_zero() {
+        // a one cut short fails the checksum
+        let mut high_us = CAPTURED;
+        high_us[2] = ONE_THRESHOLD_US;
+        assert_eq!(decode::<Infallible>(&high_us), Err(Error::Checksum));
+
+        let mut high_us = CAPTURED;
+        high_us[4] = 0;
+        assert_eq!(decode::<Infallible>(&high_us), Err(Error::Checksum));
+    }
+
+    /// A sensor which answers every start signal with the high pulses of the
+    /// next frame, or not at all for `None`
+    ///
+    /// Every look at the cycle counter takes a microsecond.
+    struct Sensor<'a> {
+        frames: Vec<Option<&'a [u32]>>,
+        /// The microsecond count
+        now: Cell<u32>,
+        /// When the start signal ended
+        released: Cell<Option<u32>>,
+        starts: Cell<usize>,
+    }
+
+    impl<'a> Sensor<'a> {
+        fn new(frames: Vec<Option<&'a [u32]>>) -> Self {
+            Self {
+                frames,
+                now: Cell::new(0),
+                released: Cell::new(None),
+                starts: Cell::new(0),
+            }
+        }
+
+        fn level(&self) -> bool {
+            let (released, frame) = match (self.released.get(), self.starts.get()) {
+                (Some(released), starts) if starts > 0 => (released, self.frames[starts - 1]),
+                _ => return true,
+            };
+            let high_us = match frame {
+                Some(high_us) => high_us,
+                None => return true,
+            };
+            // the pull-up for a little, then the response of 80 µs low and
+            // 80 µs high, then the bits
+            let phases = [(20, true), (80, false), (80, true)]
+                .into_iter()
+                .chain(
+                    high_us
+                        .iter()
+                        .flat_map(|&width| [(50, false), (width, true)]),
+                )
+                .chain([(50, false)]);
+            let mut elapsed = self.now.get().wrapping_sub(released);
+            for (length, high) in phases {
+                if elapsed < length {
+                    return high;
+                }
+                elapsed -= length;
+            }
+            true
+        }
+    }
+
+    impl OutputPin for &Sensor<'_> {
+        type Error = Infallible;
+
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            self.starts.set(self.starts.get() + 1);
+            self.released.set(None);
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            self.released.set(Some(self.now.get()));
+            Ok(())
+        }
+    }
+
+    impl InputPin for &Sensor<'_> {
+        type Error = Infallible;
+
+        fn is_high(&self) -> Result<bool, Infallible> {
+            Ok(self.level())
+        }
+
+        fn is_low(&self) -> Result<bool, Infallible> {
+            Ok(!self.level())
+        }
+    }
+
+    impl CycleCounter for &Sensor<'_> {
+        fn cycles(&self) -> u32 {
+            let now = self.now.get();
+            self.now.set(now.wrapping_add(1));
+            now
+        }
+
+        fn cycles_per_us(&self) -> u32 {
+            1
+        }
+    }
+
+    /// Notes every delay
+    #[derive(Default)]
+    struct Delays(RefCell<Vec<u16>>);
+
+    impl DelayMs<u16> for &Delays {
+        fn delay_ms(&mut self, ms: u16) {
+            self.0.borrow_mut().push(ms);
+        }
+    }
+
+    #[test]
+    fn reads_the_sensor() {
+        let sensor = Sensor::new(vec![Some(&CAPTURED)]);
+        let delays = Delays::default();
+        let mut dht11 = Dht11::new(&sensor, &sensor).unwrap();
+        assert_eq!(
+            dht11.read(&mut &delays),
+            Ok(Reading {
+                humidity: 450,
+                temperature: 236
+            })
+        );
+        assert_eq!(sensor.starts.get(), 1);
+        assert_eq!(*delays.0.borrow(), [START_MS]);
+    }
+
+    #[test]
+    fn retries_after_an_error() {
+        let mut corrupt = CAPTURED;
+        corrupt[0] = 70;
+        let good = pulses([60, 0, 20, 0]);
+        let sensor = Sensor::new(vec![None, Some(&corrupt), Some(&good)]);
+        let delays = Delays::default();
+        let mut dht11 = Dht11::new(&sensor, &sensor).unwrap();
+        assert_eq!(
+            dht11.read(&mut &delays),
+            Ok(Reading {
+                humidity: 600,
+                temperature: 200
+            })
+        );
+        assert_eq!(sensor.starts.get(), 3);
+    }
+
+    #[test]
+    fn gives_up_after_the_retries() {
+        let sensor = Sensor::new(vec![None; RETRIES as usize + 1]);
+        let delays = Delays::default();
+        let mut dht11 = Dht11::new(&sensor, &sensor).unwrap();
+        assert_eq!(dht11.read(&mut &delays), Err(Error::Timeout));
+        assert_eq!(sensor.starts.get(), RETRIES as usize);
+        assert_eq!(
+            *delays.0.borrow(),
+            [START_MS, RETRY_DELAY_MS, START_MS, RETRY_DELAY_MS, START_MS]
+        );
+
+        // a missing pulse leaves the line high until the timeout
+        let sensor = Sensor::new(vec![Some(&CAPTURED[1..]); RETRIES as usize + 1]);
+        let mut dht11 = Dht11::new(&sensor, &sensor).unwrap();
+        assert_eq!(dht11.read(&mut &delays), Err(Error::Timeout));
+        assert_eq!(sensor.starts.get(), RETRIES as usize);
+    }
+}
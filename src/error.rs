@@ -0,0 +1,170 @@
+use core::convert::Infallible;
+use core::fmt;
+use one_wire_bus::OneWireError;
+use stm32f1xx_hal::i2c;
+
+use crate::dht11;
+use crate::soft_uart::RxError;
+
+/// The maximum number of contexts an error remembers
+const MAX_CONTEXT: usize = 4;
+
+/// What went wrong
+#[derive(Debug)]
+pub enum Kind {
+    /// Something returned an empty error, like semihosting
+    Unit,
+    /// A value which should have been there was `None`
+    Missing,
+    /// A plain message
+    Str(&'static str),
+    /// Writing formatted output failed
+    Fmt,
+    /// A non-blocking operation would block
+    WouldBlock,
+    I2c(i2c::Error),
+    Max7219(max7219::DataError),
+    OneWire(OneWireError<Infallible>),
+    Dht11(dht11::Error<Infallible>),
+    SoftUart(RxError),
+}
+
+/// An error with the steps that led to it
+///
+/// ```ignore
+/// let mut bus = BlockingI2c::i2c2(...);
+/// bus.write(0x27, &[0x0c]).context("display on")?;
+/// ```
+pub struct Error {
+    kind: Kind,
+    context: [&'static str; MAX_CONTEXT],
+    depth: usize,
+}
+
+pub type Result<T, E = Error> = core::result::Result<T, E>;
+
+impl Error {
+    pub const fn new(kind: Kind) -> Self {
+        Self {
+            kind,
+            context: [""; MAX_CONTEXT],
+            depth: 0,
+        }
+    }
+
+    /// What went wrong
+    pub fn kind(&self) -> &Kind {
+        &self.kind
+    }
+
+    /// The contexts of the error, outermost first
+    pub fn context(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.context[..self.depth].iter().rev().copied()
+    }
+
+    /// Add a context, dropping it if the error already has too many
+    pub fn with_context(mut self, context: &'static str) -> Self {
+        if self.depth < MAX_CONTEXT {
+            self.context[self.depth] = context;
+            self.depth += 1;
+        }
+        self
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        for context in self.context() {
+            write!(fmt, "{}: ", context)?;
+        }
+        write!(fmt, "{:?}", self.kind)
+    }
+}
+
+impl fmt::Debug for Error {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, fmt)
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for Kind {
+    fn format(&self, fmt: defmt::Formatter) {
+        match self {
+            Self::Unit => defmt::write!(fmt, "Unit"),
+            Self::Missing => defmt::write!(fmt, "Missing"),
+            Self::Str(s) => defmt::write!(fmt, "Str({=str})", s),
+            Self::Fmt => defmt::write!(fmt, "Fmt"),
+            Self::WouldBlock => defmt::write!(fmt, "WouldBlock"),
+            Self::I2c(e) => defmt::write!(fmt, "I2c({})", defmt::Debug2Format(e)),
+            Self::Max7219(e) => defmt::write!(fmt, "Max7219({})", defmt::Debug2Format(e)),
+            Self::OneWire(e) => defmt::write!(fmt, "OneWire({})", defmt::Debug2Format(e)),
+            Self::Dht11(e) => defmt::write!(fmt, "Dht11({})", defmt::Debug2Format(e)),
+            Self::SoftUart(e) => defmt::write!(fmt, "SoftUart({})", defmt::Debug2Format(e)),
+        }
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for Error {
+    fn format(&self, fmt: defmt::Formatter) {
+        for context in self.context() {
+            defmt::write!(fmt, "{=str}: ", context);
+        }
+        defmt::write!(fmt, "{}", self.kind);
+    }
+}
+
+/// Attach a context to the error of a `Result` or to a missing `Option`
+pub trait Context<T> {
+    fn context(self, context: &'static str) -> Result<T>;
+}
+
+impl<T, E: Into<Error>> Context<T> for core::result::Result<T, E> {
+    fn context(self, context: &'static str) -> Result<T> {
+        self.map_err(|e| e.into().with_context(context))
+    }
+}
+
+impl<T> Context<T> for Option<T> {
+    fn context(self, context: &'static str) -> Result<T> {
+        self.ok_or_else(|| Error::new(Kind::Missing).with_context(context))
+    }
+}
+
+// Convert all errors of the drivers we use
+
+macro_rules! impl_from {
+    ( $(($y:ty, $x:expr)),* $(,)? ) => {
+        $(
+            impl From<$y> for Error {
+                fn from(item: $y) -> Self {
+                    #[allow(clippy::redundant_closure_call)]
+                    Self::new(($x)(item))
+                }
+            }
+        )*
+    }
+}
+
+impl_from!(
+    (Kind, |kind| kind),
+    ((), |_| Kind::Unit),
+    (&'static str, Kind::Str),
+    (fmt::Error, |_| Kind::Fmt),
+    (void::Void, |v| void::unreachable(v)),
+    (Infallible, |i| match i {}),
+    (i2c::Error, Kind::I2c),
+    (max7219::DataError, Kind::Max7219),
+    (OneWireError<Infallible>, Kind::OneWire),
+    (dht11::Error<Infallible>, Kind::Dht11),
+);
+
+impl<E: Into<Error>> From<nb::Error<E>> for Error {
+    fn from(item: nb::Error<E>) -> Self {
+        match item {
+            nb::Error::WouldBlock => Self::new(Kind::WouldBlock),
+            nb::Error::Other(e) => e.into(),
+        }
+    }
+}
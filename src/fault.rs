@@ -0,0 +1,363 @@
+//! HardFault handler which leaves a report in the crash log
+//!
+//! The report holds the stacked registers, the decoded fault status
+//! registers and a guess at the callers, found by scanning the stack for
+//! return addresses. With the `fault-blink` feature, the cause is also blinked
+//! on the PC13 LED before the chip resets.
+
+use core::fmt;
+use cortex_m_rt::ExceptionFrame;
+
+/// How many probable return addresses are taken from the stack
+const MAX_CALLERS: usize = 4;
+
+/// Configurable Fault Status Register bits, with what they mean
+const CFSR: [(u32, &str); 15] = [
+    // MemManage
+    (1 << 0, "instruction access violation"),
+    (1 << 1, "data access violation"),
+    (1 << 3, "memory fault while unstacking"),
+    (1 << 4, "memory fault while stacking"),
+    // BusFault
+    (1 << 8, "instruction bus error"),
+    (1 << 9, "precise data bus error"),
+    (1 << 10, "imprecise data bus error"),
+    (1 << 11, "bus fault while unstacking"),
+    (1 << 12, "bus fault while stacking"),
+    // UsageFault
+    (1 << 16, "undefined instruction"),
+    (1 << 17, "invalid state, probably an even branch target"),
+    (1 << 18, "invalid exception return"),
+    (1 << 19, "no coprocessor"),
+    (1 << 24, "unaligned access"),
+    (1 << 25, "division by zero"),
+];
+
+/// Hard Fault Status Register bits, with what they mean
+const HFSR: [(u32, &str); 3] = [
+    (1 << 1, "vector table read"),
+    (1 << 30, "escalated from a configurable fault"),
+    (1 << 31, "debug event"),
+];
+
+const VECTTBL: u32 = 1 << 1;
+const MMARVALID: u32 = 1 << 7;
+const BFARVALID: u32 = 1 << 15;
+const MMFSR: u32 = 0x0000_00ff;
+const BFSR: u32 = 0x0000_ff00;
+const UFSR: u32 = 0xffff_0000;
+
+/// Everything known about a fault
+#[derive(Debug, Clone, Copy)]
+pub struct Report {
+    pub r0: u32,
+    pub r1: u32,
+    pub r2: u32,
+    pub r3: u32,
+    pub r12: u32,
+    pub lr: u32,
+    pub pc: u32,
+    pub xpsr: u32,
+    pub cfsr: u32,
+    pub hfsr: u32,
+    pub mmfar: u32,
+    pub bfar: u32,
+    /// Probable return addresses on the stack, innermost first; 0 is unused
+    pub callers: [u32; MAX_CALLERS],
+}
+
+impl Report {
+    /// Combine the stacked registers with the fault status registers
+    pub fn new(frame: &ExceptionFrame, cfsr: u32, hfsr: u32, mmfar: u32, bfar: u32) -> Self {
+        Self {
+            r0: frame.r0,
+            r1: frame.r1,
+            r2: frame.r2,
+            r3: frame.r3,
+            r12: frame.r12,
+            lr: frame.lr,
+            pc: frame.pc,
+            xpsr: frame.xpsr,
+            cfsr,
+            hfsr,
+            mmfar,
+            bfar,
+            callers: [0; MAX_CALLERS],
+        }
+    }
+
+    /// Collect probable return addresses from the stack words
+    ///
+    /// Any odd word pointing into the code could be the return address of
+    /// a Thumb call, so this can include stale values.
+    pub fn scan_stack<I>(mut self, words: I, code: core::ops::Range<u32>) -> Self
+    where
+        I: IntoIterator<Item = u32>,
+    {
+        let callers = words
+            .into_iter()
+            .filter(|&word| word & 1 == 1 && code.contains(&(word & !1)))
+            .filter(|&word| word != self.lr);
+        for (slot, caller) in self.callers.iter_mut().zip(callers) {
+            *slot = caller;
+        }
+        self
+    }
+
+    /// The number of blinks for the cause of the fault
+    ///
+    /// 1 for a usage fault, 2 for a bus fault, 3 for a memory management
+    /// fault, 4 for a vector table read and 5 for anything else.
+    pub fn blink_code(&self) -> u8 {
+        if self.cfsr & UFSR != 0 {
+            1
+        } else if self.cfsr & BFSR != 0 {
+            2
+        } else if self.cfsr & MMFSR != 0 {
+            3
+        } else if self.hfsr & VECTTBL != 0 {
+            4
+        } else {
+            5
+        }
+    }
+}
+
+/// Write the causes of the set bits, separated by commas
+fn causes(fmt: &mut fmt::Formatter, bits: u32, table: &[(u32, &str)]) -> fmt::Result {
+    let mut separator = "";
+    for &(bit, cause) in table {
+        if bits & bit != 0 {
+            write!(fmt, "{}{}", separator, cause)?;
+            separator = ", ";
+        }
+    }
+    Ok(())
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            fmt,
+            "HardFault at pc {:#010x}, lr {:#010x}, xpsr {:#010x}",
+            self.pc, self.lr, self.xpsr
+        )?;
+        writeln!(
+            fmt,
+            "r0 {:#010x} r1 {:#010x} r2 {:#010x} r3 {:#010x} r12 {:#010x}",
+            self.r0, self.r1, self.r2, self.r3, self.r12
+        )?;
+
+        write!(fmt, "HFSR {:#010x}: ", self.hfsr)?;
+        causes(fmt, self.hfsr, &HFSR)?;
+        write!(fmt, "\nCFSR {:#010x}: ", self.cfsr)?;
+        causes(fmt, self.cfsr, &CFSR)?;
+
+        if self.cfsr & MMARVALID != 0 {
+            write!(fmt, "\nMMFAR {:#010x}", self.mmfar)?;
+        }
+        if self.cfsr & BFARVALID != 0 {
+            write!(fmt, "\nBFAR {:#010x}", self.bfar)?;
+        }
+
+        write!(fmt, "\ncallers:")?;
+        for caller in self.callers.iter().take_while(|&&caller| caller != 0) {
+            write!(fmt, " {:#010x}", caller)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "none")]
+mod handler {
+    use cortex_m::{asm, peripheral::SCB};
+    use cortex_m_rt::{exception, ExceptionFrame};
+    use stm32f1xx_hal::pac;
+
+    use super::Report;
+    use crate::clocks::{HSE_HZ, HSI_HZ};
+    use crate::crash;
+
+    /// How deep the stack is scanned for return addresses, in words
+    const SCAN_DEPTH: usize = 256;
+
+    extern "C" {
+        static _stext: u32;
+        static __etext: u32;
+        static _stack_start: u32;
+    }
+
+    #[exception]
+    fn HardFault(frame: &ExceptionFrame) -> ! {
+        // SAFETY: nothing else runs anymore, and the registers are only read
+        let scb = unsafe { &*SCB::PTR };
+        let report = Report::new(
+            frame,
+            scb.cfsr.read(),
+            scb.hfsr.read(),
+            scb.mmfar.read(),
+            scb.bfar.read(),
+        );
+
+        // the stack continues right after the frame, unless the frame itself is broken
+        let above =
+            frame as *const ExceptionFrame as usize + core::mem::size_of::<ExceptionFrame>();
+        // SAFETY: these are linker symbols, only their addresses are used
+        let (code, top) = unsafe {
+            (
+                &_stext as *const u32 as u32..&__etext as *const u32 as u32,
+                &_stack_start as *const u32 as usize,
+            )
+        };
+        let report = if (0x2000_0000..top).contains(&above) {
+            let end = top.min(above + SCAN_DEPTH * 4);
+            // SAFETY: the words lie between the frame and the top of the stack
+            let words = (above..end)
+                .step_by(4)
+                .map(|address| unsafe { (address as *const u32).read_volatile() });
+            report.scan_stack(words, code)
+        } else {
+            report
+        };
+
+        crash::record(|out| write!(out, "{}", report));
+
+        if cfg!(feature = "fault-blink") {
+            blink(report.blink_code());
+        }
+
+        SCB::sys_reset()
+    }
+
+    /// The current core clock, worked out from the RCC registers
+    fn sysclk() -> u32 {
+        // SAFETY: only reads
+        let cfgr = unsafe { &*pac::RCC::ptr() }.cfgr.read();
+
+        match cfgr.sws().bits() {
+            0b01 => HSE_HZ,
+            0b10 => {
+                let input = match (cfgr.pllsrc().bit(), cfgr.pllxtpre().bit()) {
+                    (false, _) => HSI_HZ / 2,
+                    (true, false) => HSE_HZ,
+                    (true, true) => HSE_HZ / 2,
+                };
+                input * (cfgr.pllmul().bits() as u32 + 2).min(16)
+            }
+            _ => HSI_HZ,
+        }
+    }
+
+    /// Blink the code on the (active low) PC13 LED three times
+    fn blink(code: u8) {
+        // SAFETY: the program is over, so PC13 is ours now
+        let rcc = unsafe { &*pac::RCC::ptr() };
+        let gpioc = unsafe { &*pac::GPIOC::ptr() };
+
+        rcc.apb2enr.modify(|_, w| w.iopcen().set_bit());
+        gpioc
+            .crh
+            .modify(|_, w| w.mode13().output2().cnf13().push_pull());
+
+        let ms = sysclk() / 1000;
+        for _ in 0..3 {
+            for _ in 0..code {
+                gpioc.bsrr.write(|w| w.br13().set_bit());
+                asm::delay(200 * ms);
+                gpioc.bsrr.write(|w| w.bs13().set_bit());
+                asm::delay(300 * ms);
+            }
+            asm::delay(1000 * ms);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CODE: core::ops::Range<u32> = 0x0800_0000..0x0801_0000;
+
+    fn report(cfsr: u32, hfsr: u32) -> Report {
+        let frame = ExceptionFrame {
+            r0: 1,
+            r1: 2,
+            r2: 3,
+            r3: 4,
+            r12: 12,
+            lr: 0x0800_1235,
+            pc: 0x0800_2000,
+            xpsr: 0x0100_0000,
+        };
+        Report::new(&frame, cfsr, hfsr, 0x2000_1000, 0x4000_0000)
+    }
+
+    #[test]
+    fn callers_are_odd_words_in_the_code() {
+        let words = [
+            0x0800_0101, // a return address
+            0x0800_0100, // even, so data
+            0x2000_0001, // in RAM
+            0x0801_0001, // just past the code
+            0x0800_1235, // the stacked LR, which the report has already
+            0x0800_fff1,
+        ];
+        let report = report(0, 0).scan_stack(words, CODE);
+        assert_eq!(report.callers, [0x0800_0101, 0x0800_fff1, 0, 0]);
+    }
+
+    #[test]
+    fn callers_stop_at_the_first_few() {
+        let words = (0..10).map(|i| 0x0800_0001 + i * 0x10);
+        let report = report(0, 0).scan_stack(words, CODE);
+        assert_eq!(
+            report.callers,
+            [0x0800_0001, 0x0800_0011, 0x0800_0021, 0x0800_0031]
+        );
+    }
+
+    #[test]
+    fn blink_codes_take_the_first_fault_kind() {
+        assert_eq!(report(1 << 25 | 1 << 9, 1 << 30).blink_code(), 1);
+        assert_eq!(report(1 << 9 | 1 << 1, 1 << 30).blink_code(), 2);
+        assert_eq!(report(1 << 1, 1 << 30).blink_code(), 3);
+        assert_eq!(report(0, VECTTBL).blink_code(), 4);
+        assert_eq!(report(0, 1 << 31).blink_code(), 5);
+    }
+
+    #[test]
+    fn reports_decode_the_status_registers() {
+        let report = report(1 << 16 | 1 << 25, 1 << 30).scan_stack([0x0800_0101], CODE);
+        assert_eq!(
+            format!("{}", report),
+            "HardFault at pc 0x08002000, lr 0x08001235, xpsr 0x01000000\n\
+             r0 0x00000001 r1 0x00000002 r2 0x00000003 r3 0x00000004 r12 0x0000000c\n\
+             HFSR 0x40000000: escalated from a configurable fault\n\
+             CFSR 0x02010000: undefined instruction, division by zero\n\
+             callers: 0x08000101"
+        );
+    }
+
+    #[test]
+    fn fault_addresses_only_show_when_valid() {
+        let text = format!("{}", report(1 << 1, 0));
+        assert!(!text.contains("MMFAR") && !text.contains("BFAR"));
+
+        let text = format!("{}", report(1 << 1 | MMARVALID, 0));
+        assert!(text.contains("data access violation\nMMFAR 0x20001000\n"));
+        assert!(!text.contains("BFAR"));
+
+        let text = format!("{}", report(1 << 9 | BFARVALID, 0));
+        assert!(text.contains("precise data bus error\nBFAR 0x40000000\n"));
+        assert!(!text.contains("MMFAR"));
+    }
+
+    #[test]
+    fn every_cause_is_named() {
+        let text = format!("{}", report(u32::MAX, u32::MAX));
+        for (_, cause) in CFSR.iter().chain(HFSR.iter()) {
+            assert!(text.contains(cause), "{}", cause);
+        }
+        assert!(text.ends_with("callers:"));
+    }
+}
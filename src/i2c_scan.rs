@@ -0,0 +1,229 @@
+use core::fmt;
+use embedded_hal::blocking::i2c::{Read, WriteRead};
+
+/// The first address which is not reserved
+pub const FIRST: u8 = 0x08;
+/// The last address which is not reserved
+pub const LAST: u8 = 0x77;
+
+/// Devices we know how to recognise
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Device {
+    /// I/O expander, as on the I2C-LCD interface
+    Pcf8574,
+    /// I/O expander with the alternative address range
+    Pcf8574A,
+    /// Light sensor
+    Bh1750,
+    /// ADC/DAC
+    Pcf8591,
+    /// Temperature sensor
+    Lm75,
+    /// OLED display controller
+    Ssd1306,
+    /// EEPROM
+    At24,
+    /// Accelerometer and gyroscope
+    Mpu6050,
+    /// Real-time clock
+    Ds3231,
+    /// Pressure sensor
+    Bmp180,
+    /// Pressure and temperature sensor
+    Bmp280,
+    /// Pressure, temperature and humidity sensor
+    Bme280,
+}
+
+impl fmt::Display for Device {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str(match self {
+            Self::Pcf8574 => "PCF8574 (LCD backpack?)",
+            Self::Pcf8574A => "PCF8574A (LCD backpack?)",
+            Self::Bh1750 => "BH1750 light sensor",
+            Self::Pcf8591 => "PCF8591 ADC/DAC",
+            Self::Lm75 => "LM75 temperature sensor",
+            Self::Ssd1306 => "SSD1306 OLED",
+            Self::At24 => "AT24 EEPROM",
+            Self::Mpu6050 => "MPU-6050 IMU",
+            Self::Ds3231 => "DS3231 RTC",
+            Self::Bmp180 => "BMP180 pressure sensor",
+            Self::Bmp280 => "BMP280 pressure sensor",
+            Self::Bme280 => "BME280 environment sensor",
+        })
+    }
+}
+
+/// The devices which can live on the given address, most likely first
+pub fn candidates(address: u8) -> &'static [Device] {
+    use Device::*;
+
+    match address {
+        0x23 => &[Bh1750, Pcf8574],
+        0x5c => &[Bh1750],
+        0x20..=0x27 => &[Pcf8574],
+        0x38..=0x3b | 0x3e | 0x3f => &[Pcf8574A],
+        0x3c | 0x3d => &[Ssd1306, Pcf8574A],
+        0x48..=0x4f => &[Pcf8591, Lm75],
+        0x50..=0x57 => &[At24],
+        0x68 => &[Ds3231, Mpu6050],
+        0x69 => &[Mpu6050],
+        0x76 => &[Bmp280, Bme280],
+        0x77 => &[Bmp280, Bme280, Bmp180],
+        _ => &[],
+    }
+}
+
+/// Check if a device acknowledges the address
+///
+/// This reads a single byte, which is harmless for all devices above. Some
+/// HALs need to be reset after a NACK before they can talk again.
+pub fn probe<I: Read>(i2c: &mut I, address: u8) -> bool {
+    i2c.read(address, &mut [0]).is_ok()
+}
+
+/// Guess which device is on the address, using register probes to choose between candidates
+///
+/// Falls back to the most likely candidate when a probe fails.
+pub fn identify<I, E>(i2c: &mut I, address: u8) -> Option<Device>
+where
+    I: Read<Error = E> + WriteRead<Error = E>,
+{
+    let candidates = candidates(address);
+    let first = *candidates.first()?;
+
+    let guess = match first {
+        Device::Bh1750 if candidates.contains(&Device::Pcf8574) => {
+            // an expander returns its port twice, the sensor a 16-bit measurement
+            let mut buffer = [0; 2];
+            i2c.read(address, &mut buffer).map(|_| {
+                if buffer[0] == buffer[1] {
+                    Device::Pcf8574
+                } else {
+                    Device::Bh1750
+                }
+            })
+        }
+
+        Device::Pcf8591 => {
+            // the LM75 has nine bits of temperature, so the low bits of the second byte are 0
+            let mut buffer = [0; 2];
+            i2c.read(address, &mut buffer).map(|_| {
+                if buffer[1] & 0x7f == 0 && buffer[0] < 0x7d {
+                    Device::Lm75
+                } else {
+                    Device::Pcf8591
+                }
+            })
+        }
+
+        Device::Ds3231 => {
+            // WHO_AM_I of the MPU-6050
+            let mut buffer = [0];
+            i2c.write_read(address, &[0x75], &mut buffer).map(|_| {
+                if buffer[0] == 0x68 {
+                    Device::Mpu6050
+                } else {
+                    Device::Ds3231
+                }
+            })
+        }
+
+        Device::Bmp280 => {
+            // chip id register of the Bosch sensors
+            let mut buffer = [0];
+            i2c.write_read(address, &[0xd0], &mut buffer)
+                .map(|_| match buffer[0] {
+                    0x55 => Device::Bmp180,
+                    0x60 => Device::Bme280,
+                    _ => Device::Bmp280,
+                })
+        }
+
+        device => Ok(device),
+    };
+
+    Some(guess.unwrap_or(first))
+}
+
+#[cfg(all(test, feature = "sim"))]
+mod tests {
+    use super::*;
+    use crate::sim::i2c::{Transaction, Transcript};
+
+    /// Identify the device with the transactions, and check that all happened
+    fn identify_with(address: u8, transactions: &[Transaction]) -> Option<Device> {
+        let mut i2c = Transcript::new(transactions);
+        let device = identify(&mut i2c, address);
+        i2c.finish();
+        device
+    }
+
+    #[test]
+    fn candidates_put_the_most_likely_first() {
+        assert_eq!(candidates(0x23), [Device::Bh1750, Device::Pcf8574]);
+        assert_eq!(candidates(0x27), [Device::Pcf8574]);
+        assert_eq!(candidates(0x3c), [Device::Ssd1306, Device::Pcf8574A]);
+        assert_eq!(candidates(0x3f), [Device::Pcf8574A]);
+        assert_eq!(candidates(0x4f), [Device::Pcf8591, Device::Lm75]);
+        assert_eq!(
+            candidates(0x77),
+            [Device::Bmp280, Device::Bme280, Device::Bmp180]
+        );
+        assert!(candidates(0x10).is_empty());
+        assert!(candidates(0x78).is_empty());
+    }
+
+    #[test]
+    fn lone_candidates_need_no_probe() {
+        assert_eq!(identify_with(0x10, &[]), None);
+        assert_eq!(identify_with(0x27, &[]), Some(Device::Pcf8574));
+        assert_eq!(identify_with(0x3c, &[]), Some(Device::Ssd1306));
+        assert_eq!(identify_with(0x50, &[]), Some(Device::At24));
+    }
+
+    #[test]
+    fn an_expander_repeats_its_port() {
+        let port = [Transaction::Read(0x23, &[0xf7, 0xf7])];
+        assert_eq!(identify_with(0x23, &port), Some(Device::Pcf8574));
+        let measurement = [Transaction::Read(0x23, &[0x01, 0x90])];
+        assert_eq!(identify_with(0x23, &measurement), Some(Device::Bh1750));
+    }
+
+    #[test]
+    fn an_lm75_has_nine_bits_of_temperature() {
+        let lm75 = [Transaction::Read(0x48, &[0x19, 0x80])];
+        assert_eq!(identify_with(0x48, &lm75), Some(Device::Lm75));
+        let adc = [Transaction::Read(0x48, &[0x80, 0x3c])];
+        assert_eq!(identify_with(0x48, &adc), Some(Device::Pcf8591));
+    }
+
+    #[test]
+    fn ids_tell_the_registers_apart() {
+        let mpu = [Transaction::WriteRead(0x68, &[0x75], &[0x68])];
+        assert_eq!(identify_with(0x68, &mpu), Some(Device::Mpu6050));
+        let rtc = [Transaction::WriteRead(0x68, &[0x75], &[0x00])];
+        assert_eq!(identify_with(0x68, &rtc), Some(Device::Ds3231));
+
+        for (id, device) in [
+            (0x55, Device::Bmp180),
+            (0x58, Device::Bmp280),
+            (0x60, Device::Bme280),
+        ] {
+            let bosch = [Transaction::WriteRead(0x77, &[0xd0], &[id])];
+            assert_eq!(identify_with(0x77, &bosch), Some(device));
+        }
+    }
+
+    #[test]
+    fn a_refused_probe_falls_back_to_the_first_candidate() {
+        assert_eq!(
+            identify_with(0x68, &[Transaction::Nack(0x68)]),
+            Some(Device::Ds3231)
+        );
+        assert_eq!(
+            identify_with(0x23, &[Transaction::Nack(0x23)]),
+            Some(Device::Bh1750)
+        );
+    }
+}
@@ -0,0 +1,58 @@
+//! The 16x2 character LCD on the I2C bus, driven by single command bytes
+
+use embedded_hal::blocking::delay::DelayMs;
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+
+use crate::debug;
+
+/// The address of the LCD backpack
+pub const ADDRESS: u8 = 0x27;
+
+/// 8-bit, 2-line, 5x8 pixels
+pub(crate) const FUNCTION_SET: u8 = 0b0011_1000;
+/// Display on, cursor and blinking off
+pub(crate) const DISPLAY_ON: u8 = 0b0000_1100;
+const CLEAR: u8 = 0b0000_0001;
+/// Move the cursor right and shift the display
+const ENTRY_MODE: u8 = 0b0000_0111;
+
+/// The commands after waking up, each followed by 100 ms
+pub(crate) const SETUP: [(&str, u8); 5] = [
+    ("display on", DISPLAY_ON),
+    ("clear display", CLEAR),
+    ("display on", DISPLAY_ON),
+    ("entry mode", ENTRY_MODE),
+    ("display on", DISPLAY_ON),
+];
+
+/// Wake up and clear the display, which takes over a second
+pub fn init<I, D, E>(bus: &mut I, delay: &mut D) -> Result<(), E>
+where
+    I: Write<Error = E> + WriteRead<Error = E>,
+    D: DelayMs<u16>,
+{
+    for _ in 0..=3 {
+        debug!("function set");
+        bus.write(ADDRESS, &[FUNCTION_SET])?;
+        delay.delay_ms(100u16);
+    }
+
+    debug!("busy flag");
+    let mut buffer = [0u8];
+    bus.write_read(ADDRESS, &[], &mut buffer)?;
+    debug!("ret: {:08b}", buffer[0]);
+
+    for (name, command) in SETUP {
+        debug!("{}", name);
+        bus.write(ADDRESS, &[command])?;
+        delay.delay_ms(100u16);
+    }
+
+    Ok(())
+}
+
+/// Write a character, without waiting for it
+pub fn write_char<I: Write>(bus: &mut I, character: u8) -> Result<(), I::Error> {
+    bus.write(ADDRESS, &[character])?;
+    bus.write(ADDRESS, &[DISPLAY_ON])
+}
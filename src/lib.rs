@@ -1,4 +1,34 @@
 #![no_std]
 
+// the host tests use the standard library
+#[cfg(test)]
+#[macro_use]
+extern crate std;
+
+pub mod asynch;
+pub mod bench;
+pub mod bitstring;
+pub mod board;
+pub mod capture;
+pub mod clocks;
+pub mod crash;
+pub mod cycles;
+pub mod dht11;
+pub mod error;
+pub mod fault;
+pub mod i2c_scan;
+pub mod lcd;
+pub mod log;
+pub mod manchester;
 pub mod numpad;
 pub mod patterns;
+pub mod pcf8591;
+pub mod pins;
+pub mod probe;
+pub mod scheduler;
+#[cfg(feature = "sim")]
+pub mod sim;
+pub mod soft_uart;
+pub mod sump;
+pub mod temperature;
+pub mod watchdog;
@@ -0,0 +1,380 @@
+//! Logging with levels, going to the backend chosen by cargo feature
+//!
+//! | feature           | output                                             |
+//! |-------------------|----------------------------------------------------|
+//! | `log-usart`       | USART1 TX on PA9, 115200 8N1                       |
+//! | `log-rtt`         | RTT channel 0, e.g. `probe-rs` or `openocd rtt`    |
+//! | `log-itm`         | ITM port 0 over SWO, see `.gdbinit`                |
+//! | `log-semihosting` | the debugger's console, skipped without a debugger |
+//! | none of them      | nothing                                            |
+//!
+//! If several are enabled, the first one in the table wins, so
+//! `--features log-rtt` works without disabling the default semihosting.
+//! Only the target logs; on other architectures the log is discarded.
+//! The USART takes input on PA10 as well, and RTT on down channel 0, which
+//! [`read`] returns.
+//!
+//! ```ignore
+//! embedded_pg::log::init(&clocks);
+//! embedded_pg::info!("temp {}", temp);
+//! ```
+
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicU8, Ordering};
+use stm32f1xx_hal::rcc::Clocks;
+
+/// How important a message is, from most to least
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum Level {
+    /// Nothing is logged, as a level for [`set_level`] only
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    fn from_u8(value: u8) -> Self {
+        match value {
+            0 => Self::Off,
+            1 => Self::Error,
+            2 => Self::Warn,
+            3 => Self::Info,
+            4 => Self::Debug,
+            _ => Self::Trace,
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.pad(match self {
+            Self::Off => "OFF",
+            Self::Error => "ERROR",
+            Self::Warn => "WARN",
+            Self::Info => "INFO",
+            Self::Debug => "DEBUG",
+            Self::Trace => "TRACE",
+        })
+    }
+}
+
+/// The least important level which is still logged
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+
+/// Set up the backend
+///
+/// The clocks are needed for the baud rate of the USART.
+pub fn init(clocks: &Clocks) {
+    backend::init(clocks);
+}
+
+/// Take the bytes which came in on the backend, without waiting for any
+///
+/// Backends without input return none.
+pub fn read(buf: &mut [u8]) -> usize {
+    backend::read(buf)
+}
+
+/// Whether the backend takes input, which only USART and RTT do
+pub fn has_input() -> bool {
+    backend::TAKES_INPUT
+}
+
+/// Log messages up to and including this level
+pub fn set_level(level: Level) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// The least important level which is still logged
+pub fn level() -> Level {
+    Level::from_u8(LEVEL.load(Ordering::Relaxed))
+}
+
+/// Check if messages of the level are logged
+pub fn enabled(level: Level) -> bool {
+    level != Level::Off && level <= self::level()
+}
+
+/// Log a single formatted line, as the macros do
+pub fn log(level: Level, args: fmt::Arguments) {
+    with(level, |out| out.write_fmt(args));
+}
+
+/// Log a line which is written in pieces, like a row of a table
+///
+/// Other messages can not end up in the middle of the line.
+pub fn with<F>(level: Level, f: F)
+where
+    F: FnOnce(&mut dyn Write) -> fmt::Result,
+{
+    if !enabled(level) {
+        return;
+    }
+
+    backend::with(|out| {
+        // a log line which fails halfway is not worth failing for
+        write!(out, "{:<5} ", level)
+            .and_then(|_| f(out))
+            .and_then(|_| out.write_str("\n"))
+            .ok();
+    });
+}
+
+/// Log at the given level, like `log!(Level::Info, "x = {}", x)`
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $($arg:tt)+) => {
+        $crate::log::log($level, format_args!($($arg)+))
+    };
+}
+
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)+) => { $crate::log!($crate::log::Level::Error, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)+) => { $crate::log!($crate::log::Level::Warn, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)+) => { $crate::log!($crate::log::Level::Info, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)+) => { $crate::log!($crate::log::Level::Debug, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)+) => { $crate::log!($crate::log::Level::Trace, $($arg)+) };
+}
+
+#[cfg(all(target_arch = "arm", feature = "log-usart"))]
+mod backend {
+    use core::fmt;
+    use cortex_m::interrupt;
+    use stm32f1xx_hal::{pac, rcc::Clocks};
+
+    const BAUD: u32 = 115_200;
+
+    pub fn init(clocks: &Clocks) {
+        // SAFETY: USART1 and PA9 belong to the log, nothing else may use them
+        let rcc = unsafe { &*pac::RCC::ptr() };
+        let gpioa = unsafe { &*pac::GPIOA::ptr() };
+        let usart = unsafe { &*pac::USART1::ptr() };
+
+        interrupt::free(|_| {
+            rcc.apb2enr
+                .modify(|_, w| w.iopaen().set_bit().usart1en().set_bit());
+            gpioa
+                .crh
+                .modify(|_, w| w.mode9().output50().cnf9().alt_push_pull());
+        });
+
+        let divider = (clocks.pclk2().0 + BAUD / 2) / BAUD;
+        usart.brr.write(|w| {
+            w.div_mantissa()
+                .bits((divider >> 4) as u16)
+                .div_fraction()
+                .bits((divider & 0xf) as u8)
+        });
+        usart
+            .cr1
+            .write(|w| w.ue().set_bit().te().set_bit().re().set_bit());
+    }
+
+    pub const TAKES_INPUT: bool = true;
+
+    pub fn read(buf: &mut [u8]) -> usize {
+        // SAFETY: reading the data register only takes the received byte
+        let usart = unsafe { &*pac::USART1::ptr() };
+
+        let mut len = 0;
+        // an overrun loses bytes, and clears on reading the data
+        while len < buf.len() && usart.sr.read().rxne().bit_is_set() {
+            buf[len] = usart.dr.read().dr().bits() as u8;
+            len += 1;
+        }
+        len
+    }
+
+    struct Usart;
+
+    impl fmt::Write for Usart {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            // SAFETY: only used inside a critical section, after `init`
+            let usart = unsafe { &*pac::USART1::ptr() };
+
+            for byte in s.bytes() {
+                while usart.sr.read().txe().bit_is_clear() {}
+                usart.dr.write(|w| w.dr().bits(byte as u16));
+            }
+
+            Ok(())
+        }
+    }
+
+    pub fn with(f: impl FnOnce(&mut dyn fmt::Write)) {
+        interrupt::free(|_| f(&mut Usart));
+    }
+}
+
+#[cfg(all(target_arch = "arm", feature = "log-rtt", not(feature = "log-usart")))]
+mod backend {
+    use core::cell::RefCell;
+    use core::fmt;
+    use cortex_m::interrupt::{self, Mutex};
+    use rtt_target::{DownChannel, UpChannel};
+    use stm32f1xx_hal::rcc::Clocks;
+
+    static CHANNEL: Mutex<RefCell<Option<UpChannel>>> = Mutex::new(RefCell::new(None));
+    static INPUT: Mutex<RefCell<Option<DownChannel>>> = Mutex::new(RefCell::new(None));
+
+    pub fn init(_: &Clocks) {
+        interrupt::free(|cs| {
+            let mut channel = CHANNEL.borrow(cs).borrow_mut();
+
+            // the control block can only be set up once
+            if channel.is_none() {
+                // drop messages while the buffer is full, so it runs without a probe
+                let channels = rtt_target::rtt_init! {
+                    up: { 0: { size: 1024 mode: NoBlockSkip name: "Log" } }
+                    down: { 0: { size: 64 name: "Input" } }
+                };
+                *channel = Some(channels.up.0);
+                INPUT.borrow(cs).replace(Some(channels.down.0));
+            }
+        });
+    }
+
+    pub const TAKES_INPUT: bool = true;
+
+    pub fn read(buf: &mut [u8]) -> usize {
+        interrupt::free(|cs| match INPUT.borrow(cs).borrow_mut().as_mut() {
+            Some(input) => input.read(buf),
+            None => 0,
+        })
+    }
+
+    pub fn with(f: impl FnOnce(&mut dyn fmt::Write)) {
+        interrupt::free(|cs| {
+            if let Some(channel) = CHANNEL.borrow(cs).borrow_mut().as_mut() {
+                f(channel);
+            }
+        });
+    }
+}
+
+#[cfg(all(
+    target_arch = "arm",
+    feature = "log-itm",
+    not(any(feature = "log-usart", feature = "log-rtt"))
+))]
+mod backend {
+    use core::fmt;
+    use cortex_m::{interrupt, itm, peripheral::ITM};
+    use stm32f1xx_hal::rcc::Clocks;
+
+    // the debugger sets up the ITM and TPIU, see `.gdbinit`
+    pub fn init(_: &Clocks) {}
+
+    pub const TAKES_INPUT: bool = false;
+
+    pub fn read(_: &mut [u8]) -> usize {
+        0
+    }
+
+    struct Stim0;
+
+    impl fmt::Write for Stim0 {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            // SAFETY: only used inside a critical section, and only port 0
+            let itm = unsafe { &mut *ITM::PTR };
+
+            // the port never becomes ready when it is disabled
+            if itm.tcr.read() & 1 != 0 && itm.ter[0].read() & 1 != 0 {
+                itm::write_str(&mut itm.stim[0], s);
+            }
+
+            Ok(())
+        }
+    }
+
+    pub fn with(f: impl FnOnce(&mut dyn fmt::Write)) {
+        interrupt::free(|_| f(&mut Stim0));
+    }
+}
+
+#[cfg(all(
+    target_arch = "arm",
+    feature = "log-semihosting",
+    not(any(feature = "log-usart", feature = "log-rtt", feature = "log-itm"))
+))]
+mod backend {
+    use core::cell::RefCell;
+    use core::fmt;
+    use cortex_m::interrupt::{self, Mutex};
+    use cortex_m::peripheral::DCB;
+    use cortex_m_semihosting::hio::{self, HStdout};
+    use stm32f1xx_hal::rcc::Clocks;
+
+    static STDOUT: Mutex<RefCell<Option<HStdout>>> = Mutex::new(RefCell::new(None));
+
+    pub fn init(_: &Clocks) {
+        // semihosting halts the core when there is nobody to answer
+        if !DCB::is_debugger_attached() {
+            return;
+        }
+
+        if let Ok(stdout) = hio::hstdout() {
+            interrupt::free(|cs| STDOUT.borrow(cs).replace(Some(stdout)));
+        }
+    }
+
+    pub const TAKES_INPUT: bool = false;
+
+    // reading the console of the debugger blocks until a line comes
+    pub fn read(_: &mut [u8]) -> usize {
+        0
+    }
+
+    pub fn with(f: impl FnOnce(&mut dyn fmt::Write)) {
+        interrupt::free(|cs| {
+            if let Some(stdout) = STDOUT.borrow(cs).borrow_mut().as_mut() {
+                f(stdout);
+            }
+        });
+    }
+}
+
+#[cfg(not(all(
+    target_arch = "arm",
+    any(
+        feature = "log-usart",
+        feature = "log-rtt",
+        feature = "log-itm",
+        feature = "log-semihosting"
+    )
+)))]
+mod backend {
+    use core::fmt;
+    use stm32f1xx_hal::rcc::Clocks;
+
+    pub fn init(_: &Clocks) {}
+
+    pub const TAKES_INPUT: bool = false;
+
+    pub fn read(_: &mut [u8]) -> usize {
+        0
+    }
+
+    pub fn with(_: impl FnOnce(&mut dyn fmt::Write)) {}
+}
@@ -0,0 +1,529 @@
+//! Manchester coding, which carries its clock with the data
+//!
+//! Every bit has a transition in its middle, so the receiver follows the
+//! clock of the sender from the edges, without a preamble to time it first.
+//! A frame starts with bits that only have those transitions, which the
+//! decoder locks on, and a delimiter bit with a transition at its start.
+//! The bytes follow LSB first, after which the line goes low again.
+//!
+//! ```ignore
+//! let timer = Timer::tim4(tim4, &clocks, &mut apb1).start_count_down(1.hz());
+//! let mut tx = ManchesterTx::new(pb13, timer, Coding::Manchester, 4800)?;
+//! tx.send(b"Hello World!")?;
+//!
+//! let mut decoder = Decoder::new(Coding::Manchester);
+//! if let Some(Event::Byte(byte)) = decoder.edge(cycles, pin.is_high()?) {}
+//! ```
+
+use embedded_hal::digital::v2::OutputPin;
+use embedded_hal::timer::{CountDown, Periodic};
+use nb::block;
+use stm32f1xx_hal::time::{Hertz, U32Ext};
+
+/// Bits of the preamble, of which the decoder needs [`LOCK`]
+pub const PREAMBLE: usize = 16;
+
+/// Intervals of a whole bit which lock the decoder
+pub const LOCK: u8 = 8;
+
+/// How the bits are put on the line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Coding {
+    /// As IEEE 802.3: a one rises in the middle, a zero falls
+    Manchester,
+    /// A zero has a transition at its start as well, so the polarity does
+    /// not matter
+    Differential,
+}
+
+impl Coding {
+    /// The bits before the delimiter, which only have a transition in their middle
+    fn preamble_bit(self, index: usize) -> bool {
+        match self {
+            Coding::Manchester => index.is_multiple_of(2),
+            Coding::Differential => true,
+        }
+    }
+}
+
+/// Turns bits into the levels of their halves
+#[derive(Debug, Clone, Copy)]
+pub struct Encoder {
+    coding: Coding,
+    /// The level at the end of the last bit
+    level: bool,
+}
+
+impl Encoder {
+    /// Start from an idle, low line
+    pub fn new(coding: Coding) -> Self {
+        Self {
+            coding,
+            level: false,
+        }
+    }
+
+    /// The levels of the first and second half of the bit
+    pub fn encode(&mut self, bit: bool) -> [bool; 2] {
+        let first = match self.coding {
+            Coding::Manchester => !bit,
+            Coding::Differential => self.level ^ !bit,
+        };
+        self.level = !first;
+        [first, !first]
+    }
+}
+
+/// The levels of every half bit of a frame, ending with the line low
+pub fn frame(coding: Coding, bytes: &[u8]) -> impl Iterator<Item = bool> + '_ {
+    let mut encoder = Encoder::new(coding);
+    let preamble = (0..PREAMBLE).map(move |index| coding.preamble_bit(index));
+    // the delimiter repeats the last bit of the manchester preamble, and is a
+    // zero for differential, both of which have a transition at their start
+    let delimiter = core::iter::once(false);
+    let data = bytes
+        .iter()
+        .flat_map(|byte| (0..8).map(move |bit| byte & (1 << bit) != 0));
+
+    preamble
+        .chain(delimiter)
+        .chain(data)
+        .flat_map(move |bit| encoder.encode(bit))
+        .chain(core::iter::once(false))
+}
+
+/// Sends frames on a pin, every half bit on a tick of the timer
+pub struct ManchesterTx<P, T> {
+    pin: P,
+    timer: T,
+    coding: Coding,
+    bitrate: u32,
+}
+
+impl<P, T, E> ManchesterTx<P, T>
+where
+    P: OutputPin<Error = E>,
+    T: CountDown<Time = Hertz> + Periodic,
+{
+    /// Take the pin and the timer, and set the line idle
+    pub fn new(mut pin: P, timer: T, coding: Coding, bitrate: u32) -> Result<Self, E> {
+        pin.set_low()?;
+
+        Ok(Self {
+            pin,
+            timer,
+            coding,
+            bitrate,
+        })
+    }
+
+    /// Release the pin and the timer again
+    pub fn release(self) -> (P, T) {
+        (self.pin, self.timer)
+    }
+
+    /// Send the bytes as a frame, and wait until the line is idle again
+    pub fn send(&mut self, bytes: &[u8]) -> Result<(), E> {
+        self.timer.start((self.bitrate * 2).hz());
+        for level in frame(self.coding, bytes) {
+            if level {
+                self.pin.set_high()?;
+            } else {
+                self.pin.set_low()?;
+            }
+            block!(self.timer.wait()).ok();
+        }
+        Ok(())
+    }
+}
+
+/// What an edge told the decoder
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// The delimiter came after the preamble, so a frame starts
+    Start,
+    Byte(u8),
+    /// An edge came at a time which fits no bit, which ends the frame
+    Timing,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Counting intervals of a whole bit, from the last edge
+    Locking { count: u8 },
+    /// After the transition in the middle of a bit
+    Middle,
+    /// After a transition at the start of a bit
+    Boundary,
+    /// After the transition at the start of the delimiter
+    Delimiter,
+}
+
+/// Decodes frames from the times of the edges on the line
+///
+/// The length of a bit is learned from the preamble, and follows every bit
+/// after it, so the clock of the sender may drift over a frame. An interval
+/// is half a bit up to 3/4 of one, and a whole bit up to 3/2; a longer one
+/// ends the frame.
+#[derive(Debug, Clone, Copy)]
+pub struct Decoder {
+    coding: Coding,
+    state: State,
+    /// The time of the last edge, and of the last one in the middle of a bit
+    last: u32,
+    middle: u32,
+    /// The level after the last edge
+    level: bool,
+    /// The length of a bit in 1/16 of the unit of time
+    period: u32,
+    byte: u8,
+    bits: u8,
+}
+
+impl Decoder {
+    pub fn new(coding: Coding) -> Self {
+        Self {
+            coding,
+            state: State::Locking { count: 0 },
+            last: 0,
+            middle: 0,
+            level: false,
+            period: 0,
+            byte: 0,
+            bits: 0,
+        }
+    }
+
+    pub fn coding(&self) -> Coding {
+        self.coding
+    }
+
+    /// Whether a frame started and has not ended yet
+    pub fn in_frame(&self) -> bool {
+        !matches!(self.state, State::Locking { .. })
+    }
+
+    /// The length of a bit in the unit of time, as far as it is known
+    pub fn period(&self) -> u32 {
+        self.period / 16
+    }
+
+    /// Take an edge to the level at the time, in any unit which wraps around
+    ///
+    /// Edges must come in the order of the line. An edge to the level the
+    /// line already had means one was missed, which ends the frame.
+    pub fn edge(&mut self, time: u32, level: bool) -> Option<Event> {
+        let interval = time.wrapping_sub(self.last).saturating_mul(16);
+        self.last = time;
+        let missed = level == self.level;
+        self.level = level;
+
+        let period = self.period;
+        let quarter = period / 4;
+        let pause = interval > period.saturating_add(period / 2);
+        let short = quarter <= interval && interval < quarter * 3;
+        let long = quarter * 3 <= interval && !pause;
+
+        match self.state {
+            State::Locking { count } => {
+                if count > 0 && long && !missed {
+                    // average over the preamble
+                    self.period = period - period / 8 + interval / 8;
+                    self.state = State::Locking {
+                        count: count.saturating_add(1),
+                    };
+                } else if count > LOCK && short {
+                    self.state = State::Delimiter;
+                    self.bits = 0;
+                    return Some(Event::Start);
+                } else {
+                    // this may be the first interval of a preamble
+                    self.period = interval;
+                    self.state = State::Locking { count: 1 };
+                }
+                self.middle = time;
+                None
+            }
+            State::Middle | State::Boundary | State::Delimiter if missed => {
+                self.state = State::Locking { count: 0 };
+                Some(Event::Timing)
+            }
+            State::Middle if long => {
+                let bit = match self.coding {
+                    Coding::Manchester => level,
+                    Coding::Differential => true,
+                };
+                self.bit(time, bit)
+            }
+            State::Middle if short => {
+                self.state = State::Boundary;
+                None
+            }
+            State::Boundary if short => {
+                let bit = match self.coding {
+                    Coding::Manchester => level,
+                    Coding::Differential => false,
+                };
+                self.bit(time, bit)
+            }
+            State::Delimiter if short => {
+                self.middle = time;
+                self.state = State::Middle;
+                None
+            }
+            State::Middle | State::Boundary | State::Delimiter if pause => self.idle(),
+            State::Middle | State::Boundary | State::Delimiter => {
+                self.state = State::Locking { count: 0 };
+                Some(Event::Timing)
+            }
+        }
+    }
+
+    /// End the frame, as the line has been idle for longer than a bit
+    ///
+    /// A frame is only complete when it ends on a byte.
+    pub fn idle(&mut self) -> Option<Event> {
+        let complete = !self.in_frame() || (self.state != State::Delimiter && self.bits == 0);
+        self.state = State::Locking { count: 0 };
+        (!complete).then_some(Event::Timing)
+    }
+
+    /// A bit ended in the middle of it, at the time
+    fn bit(&mut self, time: u32, bit: bool) -> Option<Event> {
+        let measured = time.wrapping_sub(self.middle).saturating_mul(16);
+        self.period = self.period - self.period / 8 + measured / 8;
+        self.middle = time;
+        self.state = State::Middle;
+
+        self.byte = (self.byte >> 1) | ((bit as u8) << 7);
+        self.bits += 1;
+        if self.bits < 8 {
+            return None;
+        }
+        self.bits = 0;
+        Some(Event::Byte(self.byte))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec::Vec;
+
+    const CODINGS: [Coding; 2] = [Coding::Manchester, Coding::Differential];
+
+    /// An edge of a frame, and whether it is in the middle of a bit
+    #[derive(Debug, Clone, Copy)]
+    struct Edge {
+        time: u32,
+        level: bool,
+        middle: bool,
+    }
+
+    /// The edges of a frame from `start`, with a half bit of `half` which
+    /// changes by `drift` (a fraction of it) by the end of the frame
+    fn edges(coding: Coding, bytes: &[u8], start: f64, half: f64, drift: f64) -> Vec<Edge> {
+        let levels: Vec<bool> = frame(coding, bytes).collect();
+        let step = half * drift / levels.len() as f64;
+        let mut time = start;
+        let mut last = false;
+        let mut edges = Vec::new();
+
+        for (i, (level, half)) in levels
+            .iter()
+            .zip((0..).map(|i| half + step * i as f64))
+            .enumerate()
+        {
+            if *level != last {
+                edges.push(Edge {
+                    time: time.round() as u32,
+                    level: *level,
+                    middle: i % 2 == 1,
+                });
+                last = *level;
+            }
+            time += half;
+        }
+        edges
+    }
+
+    /// Decode the edges, and end the last frame as the line goes idle
+    fn decode(coding: Coding, edges: impl IntoIterator<Item = Edge>) -> Vec<Event> {
+        let mut decoder = Decoder::new(coding);
+        let mut events: Vec<Event> = edges
+            .into_iter()
+            .filter_map(|edge| decoder.edge(edge.time, edge.level))
+            .collect();
+        events.extend(decoder.idle());
+        events
+    }
+
+    /// The bytes of every frame, which must all be complete
+    fn frames(events: &[Event]) -> Vec<Vec<u8>> {
+        let mut frames: Vec<Vec<u8>> = Vec::new();
+        for event in events {
+            match event {
+                Event::Start => frames.push(Vec::new()),
+                Event::Byte(byte) => frames.last_mut().unwrap().push(*byte),
+                Event::Timing => panic!("a timing error in {:?}", events),
+            }
+        }
+        frames
+    }
+
+    #[test]
+    fn encode() {
+        let mut encoder = Encoder::new(Coding::Manchester);
+        assert_eq!(encoder.encode(true), [false, true]);
+        assert_eq!(encoder.encode(true), [false, true]);
+        assert_eq!(encoder.encode(false), [true, false]);
+
+        // a zero has a transition at its start, a one does not
+        let mut encoder = Encoder::new(Coding::Differential);
+        assert_eq!(encoder.encode(true), [false, true]);
+        assert_eq!(encoder.encode(true), [true, false]);
+        assert_eq!(encoder.encode(false), [true, false]);
+        assert_eq!(encoder.encode(false), [true, false]);
+        assert_eq!(encoder.encode(true), [false, true]);
+
+        for coding in CODINGS {
+            let levels: Vec<bool> = frame(coding, b"ab").collect();
+            assert_eq!(levels.len(), (PREAMBLE + 1 + 16) * 2 + 1);
+            assert_eq!(levels.last(), Some(&false));
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let messages: [&[u8]; 4] = [b"Hello World!\r\n", b"\x00\xff\x55\xaa", b"", b"\x01"];
+
+        for coding in CODINGS {
+            for half in [5.0, 17.3, 104.0, 7500.0] {
+                let mut all = Vec::new();
+                let mut start = 1000.0;
+                for message in messages {
+                    let frame = edges(coding, message, start, half, 0.0);
+                    // the line is idle between the frames
+                    start = frame.last().unwrap().time as f64 + half * 20.0;
+                    all.extend(frame);
+                }
+
+                let expected: Vec<Vec<u8>> = messages.iter().map(|m| m.to_vec()).collect();
+                assert_eq!(
+                    frames(&decode(coding, all)),
+                    expected,
+                    "{:?}, {}",
+                    coding,
+                    half
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn follows_drift() {
+        let message = b"The quick brown fox jumps over the lazy dog";
+
+        for coding in CODINGS {
+            for drift in [-0.05, -0.03, -0.01, 0.01, 0.03, 0.05] {
+                let frame = edges(coding, message, 500.0, 100.0, drift);
+                let events = decode(coding, frame);
+                assert_eq!(
+                    frames(&events),
+                    [message.to_vec()],
+                    "{:?}, {}",
+                    coding,
+                    drift
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn learns_the_period() {
+        for coding in CODINGS {
+            let mut decoder = Decoder::new(coding);
+            for edge in edges(coding, b"\x5a", 0.0, 52.0, 0.0) {
+                decoder.edge(edge.time, edge.level);
+            }
+            assert!(
+                (103..=105).contains(&decoder.period()),
+                "{}",
+                decoder.period()
+            );
+        }
+    }
+
+    #[test]
+    fn lost_edge_in_the_data_ends_the_frame() {
+        let message = b"Hi!\x00\xff";
+
+        for coding in CODINGS {
+            let frame = edges(coding, message, 1000.0, 100.0, 0.0);
+            let delimiter = frame
+                .iter()
+                .position(|edge| edge.middle && edge.time >= 1000 + 100 * 2 * PREAMBLE as u32)
+                .unwrap();
+
+            for lost in (delimiter..frame.len()).filter(|&i| frame[i].middle) {
+                let mut damaged = frame.clone();
+                damaged.remove(lost);
+                let events = decode(coding, damaged);
+                assert!(
+                    events.contains(&Event::Timing),
+                    "{:?}, edge {}: {:?}",
+                    coding,
+                    lost,
+                    events
+                );
+            }
+
+            // the preamble only needs enough of its bits
+            let mut damaged = frame.clone();
+            damaged.remove(3);
+            assert_eq!(frames(&decode(coding, damaged)), [message.to_vec()]);
+        }
+    }
+
+    #[test]
+    fn next_frame_after_an_error() {
+        for coding in CODINGS {
+            let mut line = edges(coding, b"lost", 0.0, 100.0, 0.0);
+            line.remove(line.len() - 6);
+            let start = line.last().unwrap().time as f64 + 3000.0;
+            line.extend(edges(coding, b"found", start, 100.0, 0.0));
+
+            let events = decode(coding, line);
+            let timing = events.iter().position(|e| *e == Event::Timing).unwrap();
+            assert_eq!(frames(&events[timing + 1..]), [b"found".to_vec()]);
+        }
+    }
+
+    #[test]
+    fn differential_ignores_polarity() {
+        let inverted = edges(Coding::Differential, b"ab", 100.0, 50.0, 0.0)
+            .into_iter()
+            .map(|edge| Edge {
+                level: !edge.level,
+                ..edge
+            });
+
+        assert_eq!(
+            frames(&decode(Coding::Differential, inverted)),
+            [b"ab".to_vec()]
+        );
+    }
+
+    #[test]
+    fn time_wraps_around() {
+        for coding in CODINGS {
+            let line = edges(coding, b"wrap", 0.0, 100.0, 0.0)
+                .into_iter()
+                .map(|edge| Edge {
+                    time: edge.time.wrapping_sub(2000),
+                    ..edge
+                });
+            assert_eq!(frames(&decode(coding, line)), [b"wrap".to_vec()]);
+        }
+    }
+}
@@ -1,6 +1,8 @@
 use core::convert::Infallible;
 use embedded_hal::digital::v2::{InputPin, OutputPin};
-use stm32f1xx_hal::gpio::{Input, Output, PullDown, PushPull, Pxx};
+use stm32f1xx_hal::afio;
+use stm32f1xx_hal::gpio::{Edge, ExtiPin, Input, Output, PullDown, PushPull, Pxx};
+use stm32f1xx_hal::pac::EXTI;
 
 pub struct Buttons {
     /// make sure it can't be constructed
@@ -46,6 +48,8 @@ type In = Option<Pxx<Input<PullDown>>>;
 pub struct Numpad {
     rows: [Out; 4],
     cols: [In; 4],
+    /// Keep the rows high between reads, so a press raises its column
+    listening: bool,
 }
 
 impl Numpad {
@@ -59,7 +63,47 @@ impl Numpad {
             }
         }
 
-        Ok(Self { rows, cols })
+        Ok(Self {
+            rows,
+            cols,
+            listening: false,
+        })
+    }
+
+    /// Interrupt on the rising edge of the columns when a button is pressed
+    ///
+    /// Only presses are noticed, so [`Numpad::read`] still has to be polled to
+    /// see when a button is released.
+    pub fn listen<E: From<Infallible>>(
+        &mut self,
+        afio: &mut afio::Parts,
+        exti: &EXTI,
+    ) -> Result<(), E> {
+        for col in self.cols.iter_mut().flatten() {
+            col.make_interrupt_source(afio);
+            col.trigger_on_edge(exti, Edge::RISING);
+            col.enable_interrupt(exti);
+        }
+        self.listening = true;
+        self.set_rows(true)
+    }
+
+    /// Clear the interrupts of the columns, which reading the numpad raises too
+    pub fn clear_interrupts(&mut self) {
+        for col in self.cols.iter_mut().flatten() {
+            col.clear_interrupt_pending_bit();
+        }
+    }
+
+    fn set_rows<E: From<Infallible>>(&mut self, high: bool) -> Result<(), E> {
+        for row in self.rows.iter_mut().flatten() {
+            if high {
+                row.set_high()?;
+            } else {
+                row.set_low()?;
+            }
+        }
+        Ok(())
     }
 
     /// Get all active buttons on the given row index
@@ -96,11 +140,18 @@ impl Numpad {
 
     /// Read the entire numpad
     pub fn read<E: From<Infallible>>(&mut self) -> Result<Button, E> {
+        if self.listening {
+            self.set_rows(false)?;
+        }
+
         let mut buttons = Buttons::None;
         for (i, &layout) in LAYOUT.iter().enumerate() {
             buttons |= self.scan_row(i, layout)?;
         }
 
+        if self.listening {
+            self.set_rows(true)?;
+        }
         Ok(buttons)
     }
 }
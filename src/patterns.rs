@@ -33,6 +33,17 @@ pub const One: [u8; 8] = [
     0b_0000_0000,
 ];
 
+pub const Cross: [u8; 8] = [
+    0b_1000_0001,
+    0b_0100_0010,
+    0b_0010_0100,
+    0b_0001_1000,
+    0b_0001_1000,
+    0b_0010_0100,
+    0b_0100_0010,
+    0b_1000_0001,
+];
+
 // TODO: allow for variable widths
 
 pub const C: [u8; 4] = [0b_0000_0000, 0b_1000_0001, 0b_1000_0001, 0b_0111_1110];
@@ -82,3 +93,36 @@ pub const blank: [u8; 4] = [0b_0000_0000, 0b_0000_0000, 0b_0000_0000, 0b_0000_00
 pub const comma: [u8; 4] = [0b_0000_0000, 0b_0000_0110, 0b_0000_0001, 0b_0000_0000];
 
 pub const excl: [u8; 4] = [0b_0000_0000, 0b_1111_1011, 0b_1111_1011, 0b_0000_0000];
+
+/// The 4 rows of a character for scrolling text, if it has one
+pub fn glyph(character: u8) -> Option<&'static [u8; 4]> {
+    Some(match character {
+        b'a' => &a,
+        b'b' => &b,
+        b'c' => &c,
+        b'd' => &d,
+        b'e' => &e,
+        b'f' => &f,
+        b'h' => &h,
+        b'i' => &i,
+        b'j' => &j,
+        b'l' => &l,
+        b'n' => &n,
+        b'o' => &o,
+        b'r' => &r,
+        b'u' => &u,
+        b'v' => &v,
+        b'w' => &w,
+        b'x' => &x,
+
+        // uppercase
+        b'C' => &C,
+        b'F' => &F,
+        b'S' => &S,
+
+        // specials
+        b'!' => &excl,
+        b' ' => &blank,
+        _ => return None,
+    })
+}
@@ -0,0 +1,361 @@
+use embedded_hal::blocking::i2c::{Read, Write};
+
+use crate::temperature::Temperature;
+
+/// The address with A0, A1 and A2 tied low, as on the common module
+pub const ADDRESS: u8 = 0x48;
+
+/// The inputs of the module the on-board sensors are jumpered to
+pub mod inputs {
+    /// Photoresistor (jumper P5)
+    pub const LDR: u8 = 0;
+    /// Thermistor (jumper P4)
+    pub const THERMISTOR: u8 = 1;
+    /// Free input
+    pub const AIN2: u8 = 2;
+    /// Potentiometer (jumper P6)
+    pub const POTENTIOMETER: u8 = 3;
+}
+
+const OUTPUT_ENABLE: u8 = 1 << 6;
+const AUTO_INCREMENT: u8 = 1 << 2;
+
+/// How the four analog inputs are combined into channels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputMode {
+    /// AIN0, AIN1, AIN2 and AIN3
+    SingleEnded = 0b00,
+    /// AIN0 - AIN3, AIN1 - AIN3 and AIN2 - AIN3
+    ThreeDifferential = 0b01,
+    /// AIN0, AIN1 and AIN2 - AIN3
+    Mixed = 0b10,
+    /// AIN0 - AIN1 and AIN2 - AIN3
+    TwoDifferential = 0b11,
+}
+
+impl InputMode {
+    /// The number of channels in this mode
+    pub const fn channels(self) -> u8 {
+        match self {
+            Self::SingleEnded => 4,
+            Self::ThreeDifferential | Self::Mixed => 3,
+            Self::TwoDifferential => 2,
+        }
+    }
+
+    /// Check if the channel gives a signed (two's complement) result
+    pub const fn is_differential(self, channel: u8) -> bool {
+        match self {
+            Self::SingleEnded => false,
+            Self::Mixed => channel == 2,
+            Self::ThreeDifferential | Self::TwoDifferential => true,
+        }
+    }
+}
+
+/// A PCF8591 8-bit ADC/DAC
+pub struct Pcf8591<I2C> {
+    i2c: I2C,
+    address: u8,
+    mode: InputMode,
+    output_enabled: bool,
+}
+
+impl<I2C, E> Pcf8591<I2C>
+where
+    I2C: Write<Error = E> + Read<Error = E>,
+{
+    /// Create a new Pcf8591 with four single-ended inputs and the DAC off
+    pub fn new(i2c: I2C, address: u8) -> Self {
+        Self {
+            i2c,
+            address,
+            mode: InputMode::SingleEnded,
+            output_enabled: false,
+        }
+    }
+
+    /// Release the bus again
+    pub fn release(self) -> I2C {
+        self.i2c
+    }
+
+    /// Change how the inputs are combined, applied on the next read
+    pub fn set_mode(&mut self, mode: InputMode) {
+        self.mode = mode;
+    }
+
+    /// Get the current input mode
+    pub fn mode(&self) -> InputMode {
+        self.mode
+    }
+
+    fn control(&self, channel: u8, auto_increment: bool) -> u8 {
+        let mut control = (self.mode as u8) << 4 | (channel & 0b11);
+        if self.output_enabled {
+            control |= OUTPUT_ENABLE;
+        }
+        if auto_increment {
+            control |= AUTO_INCREMENT;
+        }
+        control
+    }
+
+    /// Read a single-ended channel, 0 is AGND and 255 is VREF
+    ///
+    /// Every read returns the previous conversion first, so two bytes are read
+    /// and the stale one is dropped.
+    pub fn read(&mut self, channel: u8) -> Result<u8, E> {
+        self.i2c
+            .write(self.address, &[self.control(channel, false)])?;
+
+        let mut buffer = [0; 2];
+        self.i2c.read(self.address, &mut buffer)?;
+        Ok(buffer[1])
+    }
+
+    /// Read a differential channel, from -128 to 127 times VREF / 256
+    pub fn read_differential(&mut self, channel: u8) -> Result<i8, E> {
+        Ok(self.read(channel)? as i8)
+    }
+
+    /// Read all channels of the current mode at once using auto-increment
+    ///
+    /// Values past the number of channels of the mode are 0. Differential
+    /// channels should be interpreted as `i8`.
+    pub fn read_all(&mut self) -> Result<[u8; 4], E> {
+        let channels = self.mode.channels() as usize;
+
+        self.i2c.write(self.address, &[self.control(0, true)])?;
+
+        let mut buffer = [0; 5];
+        self.i2c.read(self.address, &mut buffer[..=channels])?;
+
+        let mut values = [0; 4];
+        values[..channels].copy_from_slice(&buffer[1..=channels]);
+        Ok(values)
+    }
+
+    /// Enable the analog output and set it to `value * VREF / 256`
+    pub fn set_output(&mut self, value: u8) -> Result<(), E> {
+        self.output_enabled = true;
+        self.i2c
+            .write(self.address, &[self.control(0, false), value])
+    }
+
+    /// Disable the analog output
+    pub fn disable_output(&mut self) -> Result<(), E> {
+        self.output_enabled = false;
+        self.i2c.write(self.address, &[self.control(0, false)])
+    }
+}
+
+/// The resistance of a sensor at the bottom of a voltage divider, in ohms
+///
+/// The module has a 10k resistor from VCC to the input and the sensor from the
+/// input to ground. Returns `None` when the input is at VCC (open circuit).
+pub fn divider_resistance(value: u8, fixed_ohms: u32) -> Option<u32> {
+    let high = 255 - value as u32;
+    if high == 0 {
+        return None;
+    }
+    Some(fixed_ohms * value as u32 / high)
+}
+
+/// An NTC thermistor described by the Beta equation
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Thermistor {
+    /// Resistance at the reference temperature
+    pub r0_ohms: u32,
+    /// Reference temperature in °C
+    pub t0_celsius: f32,
+    /// The Beta coefficient in kelvin
+    pub beta: f32,
+    /// The other resistor of the divider
+    pub fixed_ohms: u32,
+}
+
+impl Thermistor {
+    /// The 10k thermistor on the module
+    pub const MODULE: Self = Self {
+        r0_ohms: 10_000,
+        t0_celsius: 25.,
+        beta: 3950.,
+        fixed_ohms: 10_000,
+    };
+
+    /// The temperature for the given ADC value, if the thermistor is connected
+    ///
+    /// `1 / T = 1 / T0 + ln(R / R0) / B`
+    pub fn temperature(&self, value: u8) -> Option<Temperature> {
+        let ohms = divider_resistance(value, self.fixed_ohms)?;
+        if ohms == 0 {
+            return None;
+        }
+
+        let t0 = self.t0_celsius + 273.15;
+        let inverse = 1. / t0 + libm::logf(ohms as f32 / self.r0_ohms as f32) / self.beta;
+        let celsius = 1. / inverse - 273.15;
+
+        Some(Temperature::from_raw(libm::roundf(celsius * 16.) as i16))
+    }
+}
+
+/// A photoresistor described by its resistance at 10 lux and its gamma
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Photoresistor {
+    /// Resistance at 10 lux
+    pub r10_ohms: u32,
+    /// Slope of log(R) against log(lux)
+    pub gamma: f32,
+    /// The other resistor of the divider
+    pub fixed_ohms: u32,
+}
+
+impl Photoresistor {
+    /// The GL5528 on the module
+    pub const MODULE: Self = Self {
+        r10_ohms: 15_000,
+        gamma: 0.7,
+        fixed_ohms: 10_000,
+    };
+
+    /// The illuminance in lux for the given ADC value, if the photoresistor is connected
+    ///
+    /// `lux = 10 * (R10 / R) ^ (1 / gamma)`
+    pub fn lux(&self, value: u8) -> Option<u32> {
+        let ohms = divider_resistance(value, self.fixed_ohms)?;
+        if ohms == 0 {
+            return None;
+        }
+
+        let lux = 10. * libm::powf(self.r10_ohms as f32 / ohms as f32, 1. / self.gamma);
+        Some(libm::roundf(lux) as u32)
+    }
+}
+
+#[cfg(all(test, feature = "sim"))]
+mod tests {
+    use super::*;
+    use crate::sim::i2c::{Error, Transaction, Transcript};
+
+    /// Run the driver against the transactions, and check that all happened
+    fn replay<T>(
+        transactions: &[Transaction],
+        run: impl FnOnce(&mut Pcf8591<Transcript>) -> T,
+    ) -> T {
+        let mut adc = Pcf8591::new(Transcript::new(transactions), ADDRESS);
+        let result = run(&mut adc);
+        adc.release().finish();
+        result
+    }
+
+    #[test]
+    fn a_read_drops_the_previous_conversion() {
+        let transactions = [
+            Transaction::Write(ADDRESS, &[0x01]),
+            Transaction::Read(ADDRESS, &[0x80, 0x3c]),
+            Transaction::Write(ADDRESS, &[0x03]),
+            Transaction::Read(ADDRESS, &[0x3c, 0xa5]),
+        ];
+        let values = replay(&transactions, |adc| {
+            [
+                adc.read(inputs::THERMISTOR).unwrap(),
+                adc.read(inputs::POTENTIOMETER).unwrap(),
+            ]
+        });
+        assert_eq!(values, [0x3c, 0xa5]);
+    }
+
+    #[test]
+    fn a_differential_read_is_signed() {
+        let transactions = [
+            Transaction::Write(ADDRESS, &[0x31]),
+            Transaction::Read(ADDRESS, &[0x00, 0xf6]),
+        ];
+        let value = replay(&transactions, |adc| {
+            adc.set_mode(InputMode::TwoDifferential);
+            adc.read_differential(1).unwrap()
+        });
+        assert_eq!(value, -10);
+    }
+
+    #[test]
+    fn read_all_auto_increments() {
+        let transactions = [
+            Transaction::Write(ADDRESS, &[0x04]),
+            Transaction::Read(ADDRESS, &[0x80, 0x10, 0x20, 0x30, 0x40]),
+            Transaction::Write(ADDRESS, &[0x24]),
+            Transaction::Read(ADDRESS, &[0x40, 0x11, 0x22, 0xf0]),
+        ];
+        let values = replay(&transactions, |adc| {
+            let single = adc.read_all().unwrap();
+            adc.set_mode(InputMode::Mixed);
+            [single, adc.read_all().unwrap()]
+        });
+        assert_eq!(values, [[0x10, 0x20, 0x30, 0x40], [0x11, 0x22, 0xf0, 0]]);
+    }
+
+    #[test]
+    fn the_dac_stays_on_for_reads() {
+        let transactions = [
+            Transaction::Write(ADDRESS, &[0x40, 0x80]),
+            Transaction::Write(ADDRESS, &[0x42]),
+            Transaction::Read(ADDRESS, &[0x00, 0x7f]),
+            Transaction::Write(ADDRESS, &[0x00]),
+        ];
+        replay(&transactions, |adc| {
+            adc.set_output(0x80).unwrap();
+            assert_eq!(adc.read(inputs::AIN2), Ok(0x7f));
+            adc.disable_output().unwrap();
+        });
+    }
+
+    #[test]
+    fn a_nack_is_an_error() {
+        let transactions = [Transaction::Nack(ADDRESS), Transaction::Nack(ADDRESS)];
+        replay(&transactions, |adc| {
+            assert_eq!(adc.read(inputs::LDR), Err(Error::Nack));
+            assert_eq!(adc.set_output(0xff), Err(Error::Nack));
+        });
+    }
+
+    #[test]
+    fn the_divider_gives_the_lower_resistance() {
+        assert_eq!(divider_resistance(0, 10_000), Some(0));
+        assert_eq!(divider_resistance(128, 10_000), Some(10_078));
+        assert_eq!(divider_resistance(200, 10_000), Some(36_363));
+        assert_eq!(divider_resistance(254, 10_000), Some(2_540_000));
+        assert_eq!(divider_resistance(255, 10_000), None);
+    }
+
+    #[test]
+    fn the_thermistor_follows_the_beta_equation() {
+        // in 1/16 °C, from the equation in f64
+        for (value, raw) in [(1, 3836), (64, 829), (128, 397), (200, -24), (254, -1006)] {
+            let temperature = Thermistor::MODULE.temperature(value).unwrap();
+            assert!((temperature.raw() - raw).abs() <= 1, "{}", value);
+        }
+        // the middle of the range is about the reference temperature
+        let middle = Thermistor::MODULE.temperature(128).unwrap();
+        assert!((middle.millicelsius() - 25_000).abs() < 250);
+    }
+
+    #[test]
+    fn a_shorted_or_open_thermistor_has_no_temperature() {
+        assert_eq!(Thermistor::MODULE.temperature(0), None);
+        assert_eq!(Thermistor::MODULE.temperature(255), None);
+    }
+
+    #[test]
+    fn the_photoresistor_follows_its_gamma() {
+        let lux = |value| Photoresistor::MODULE.lux(value);
+        assert_eq!(lux(1), Some(49_305));
+        assert_eq!(lux(64), Some(85));
+        assert_eq!(lux(128), Some(18));
+        assert_eq!(lux(200), Some(3));
+        assert_eq!(lux(254), Some(0));
+        assert_eq!(lux(0), None);
+        assert_eq!(lux(255), None);
+    }
+}
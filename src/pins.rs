@@ -0,0 +1,266 @@
+//! Pins chosen at run time, by their port and number
+//!
+//! The HAL gives every pin its own type, which fits firmware wired up once.
+//! A tester goes over a table of all of them instead, and changes their mode
+//! as it goes, so it takes the ports whole.
+//!
+//! ```ignore
+//! let mut gpio = Gpio::new(gpioa, gpiob, gpioc, &mut afio.mapr);
+//! let pa5 = Pin::new(Port::A, 5);
+//! gpio.set_mode(pa5, Mode::Input(Pull::Down));
+//! info!("{} is {}", pa5, gpio.is_high(pa5));
+//! ```
+
+use core::fmt;
+use embedded_hal::adc::{Channel, OneShot};
+use stm32f1xx_hal::adc::Adc;
+use stm32f1xx_hal::afio::MAPR;
+use stm32f1xx_hal::gpio::{gpioa, gpiob, gpioc};
+use stm32f1xx_hal::pac::{self, ADC1};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Port {
+    A,
+    B,
+    C,
+}
+
+impl Port {
+    /// The registers of the port
+    pub(crate) fn block(self) -> *const pac::gpioa::RegisterBlock {
+        match self {
+            Port::A => pac::GPIOA::ptr(),
+            Port::B => pac::GPIOB::ptr(),
+            Port::C => pac::GPIOC::ptr(),
+        }
+    }
+}
+
+/// A pin, which shows as its name, like PA5
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pin {
+    pub port: Port,
+    pub index: u8,
+}
+
+impl Pin {
+    pub const fn new(port: Port, index: u8) -> Self {
+        assert!(index < 16, "a port has 16 pins");
+        Self { port, index }
+    }
+
+    /// Read a name like PA5, in any case
+    pub fn parse(name: &str) -> Option<Self> {
+        let name = name.as_bytes();
+        if name.len() < 3 || !name[0].eq_ignore_ascii_case(&b'P') {
+            return None;
+        }
+        let port = match name[1].to_ascii_uppercase() {
+            b'A' => Port::A,
+            b'B' => Port::B,
+            b'C' => Port::C,
+            _ => return None,
+        };
+        // `parse` would take a sign as well, as in "PA+5"
+        let digits = &name[2..];
+        if digits.len() > 2 || !digits.iter().all(u8::is_ascii_digit) {
+            return None;
+        }
+        let index = digits
+            .iter()
+            .fold(0, |index, digit| index * 10 + (digit - b'0'));
+        (index < 16).then(|| Self::new(port, index))
+    }
+
+    /// The channel of ADC1 and ADC2 on the pin, if it has one
+    pub fn adc_channel(self) -> Option<u8> {
+        match (self.port, self.index) {
+            (Port::A, index @ 0..=7) => Some(index),
+            (Port::B, index @ 0..=1) => Some(index + 8),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Pin {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let port = match self.port {
+            Port::A => 'A',
+            Port::B => 'B',
+            Port::C => 'C',
+        };
+        write!(fmt, "P{}{}", port, self.index)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pull {
+    Up,
+    Down,
+    Floating,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Input(Pull),
+    /// Push-pull, at 2 MHz
+    PushPull,
+    /// Open-drain, at 2 MHz, which only drives low
+    OpenDrain,
+    /// The input of the ADC, with the digital input off
+    Analog,
+}
+
+/// All pins of ports A, B and C
+pub struct Gpio {
+    _ports: (),
+}
+
+impl Gpio {
+    /// Take the ports, with PA15, PB3 and PB4 released from JTAG
+    ///
+    /// Every pin stays a floating input until its mode is set.
+    pub fn new(
+        gpioa: gpioa::Parts,
+        gpiob: gpiob::Parts,
+        _gpioc: gpioc::Parts,
+        mapr: &mut MAPR,
+    ) -> Self {
+        mapr.disable_jtag(gpioa.pa15, gpiob.pb3, gpiob.pb4);
+        Self { _ports: () }
+    }
+
+    fn registers(pin: Pin) -> &'static pac::gpioa::RegisterBlock {
+        // SAFETY: the ports were taken whole, so nothing else uses them
+        unsafe { &*pin.port.block() }
+    }
+
+    pub fn set_mode(&mut self, pin: Pin, mode: Mode) {
+        let registers = Self::registers(pin);
+        // MODE in the low two bits and CNF in the high two
+        let bits = match mode {
+            Mode::Input(Pull::Floating) => 0b0100,
+            Mode::Input(_) => 0b1000,
+            Mode::PushPull => 0b0010,
+            Mode::OpenDrain => 0b0110,
+            Mode::Analog => 0b0000,
+        };
+        // the output register picks the pull
+        match mode {
+            Mode::Input(Pull::Up) => self.set(pin, true),
+            Mode::Input(Pull::Down) => self.set(pin, false),
+            _ => {}
+        }
+
+        let offset = (pin.index % 8) * 4;
+        let configure = |r: u32| (r & !(0b1111 << offset)) | (bits << offset);
+        // SAFETY: every 4 bits are a valid configuration
+        if pin.index < 8 {
+            registers
+                .crl
+                .modify(|r, w| unsafe { w.bits(configure(r.bits())) });
+        } else {
+            registers
+                .crh
+                .modify(|r, w| unsafe { w.bits(configure(r.bits())) });
+        }
+    }
+
+    pub fn is_high(&self, pin: Pin) -> bool {
+        Self::registers(pin).idr.read().bits() & (1 << pin.index) != 0
+    }
+
+    /// Drive an output, or pick the pull of an input
+    ///
+    /// An open-drain output lets go of the line when it is high.
+    pub fn set(&mut self, pin: Pin, high: bool) {
+        let bit = if high { pin.index } else { pin.index + 16 };
+        // SAFETY: the bit only sets or resets this pin
+        Self::registers(pin)
+            .bsrr
+            .write(|w| unsafe { w.bits(1 << bit) });
+    }
+}
+
+/// An ADC channel chosen at compile time, which the HAL reads
+pub struct AdcChannel<const N: u8>;
+
+impl<const N: u8> Channel<ADC1> for AdcChannel<N> {
+    type ID = u8;
+
+    fn channel() -> u8 {
+        N
+    }
+}
+
+/// Read the pin in analog mode, which has to be set first
+///
+/// Returns `None` for a pin without an ADC channel.
+pub fn read_analog(adc: &mut Adc<ADC1>, pin: Pin) -> Option<u16> {
+    fn read<const N: u8>(adc: &mut Adc<ADC1>) -> u16 {
+        // the one-shot read of the HAL converts at once and can not fail
+        nb::block!(adc.read(&mut AdcChannel::<N>)).unwrap_or(0)
+    }
+
+    Some(match pin.adc_channel()? {
+        0 => read::<0>(adc),
+        1 => read::<1>(adc),
+        2 => read::<2>(adc),
+        3 => read::<3>(adc),
+        4 => read::<4>(adc),
+        5 => read::<5>(adc),
+        6 => read::<6>(adc),
+        7 => read::<7>(adc),
+        8 => read::<8>(adc),
+        _ => read::<9>(adc),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_parse_in_any_case() {
+        assert_eq!(Pin::parse("PA5"), Some(Pin::new(Port::A, 5)));
+        assert_eq!(Pin::parse("pb12"), Some(Pin::new(Port::B, 12)));
+        assert_eq!(Pin::parse("pC13"), Some(Pin::new(Port::C, 13)));
+        assert_eq!(Pin::parse("PA05"), Some(Pin::new(Port::A, 5)));
+    }
+
+    #[test]
+    fn only_digits_name_the_index() {
+        for name in [
+            "PA+5", "PA-5", "PA 5", "PA5 ", "PA0x5", "PA", "P5", "PD5", "XA5",
+        ] {
+            assert_eq!(Pin::parse(name), None, "{}", name);
+        }
+    }
+
+    #[test]
+    fn indexes_stop_at_15() {
+        assert_eq!(Pin::parse("PA15"), Some(Pin::new(Port::A, 15)));
+        assert_eq!(Pin::parse("PA16"), None);
+        assert_eq!(Pin::parse("PA255"), None);
+        assert_eq!(Pin::parse("PA00005"), None);
+    }
+
+    #[test]
+    fn names_round_trip() {
+        for port in [Port::A, Port::B, Port::C] {
+            for index in 0..16 {
+                let pin = Pin::new(port, index);
+                assert_eq!(Pin::parse(&format!("{}", pin)), Some(pin));
+            }
+        }
+    }
+
+    #[test]
+    fn adc_channels() {
+        assert_eq!(Pin::new(Port::A, 0).adc_channel(), Some(0));
+        assert_eq!(Pin::new(Port::A, 7).adc_channel(), Some(7));
+        assert_eq!(Pin::new(Port::B, 1).adc_channel(), Some(9));
+        assert_eq!(Pin::new(Port::A, 8).adc_channel(), None);
+        assert_eq!(Pin::new(Port::C, 0).adc_channel(), None);
+    }
+}
@@ -0,0 +1,177 @@
+use ds18b20::Ds18b20;
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+use one_wire_bus::{OneWire, OneWireError};
+
+use crate::temperature::Temperature;
+use crate::{debug, warn};
+
+/// Get the temperature probe connected on the given pin, if any
+///
+/// If a probe is found, a first measurement is started and awaited.
+pub fn get_temp_probe<T, U, P, E>(pin: T, delay: &mut U) -> Result<Option<(Ds18b20, OneWire<T>)>, E>
+where
+    T: InputPin<Error = P> + OutputPin<Error = P>,
+    U: DelayMs<u16> + DelayUs<u16>,
+    P: core::fmt::Debug,
+    E: From<OneWireError<P>>,
+{
+    // initialise the OneWireBus
+    let owb = OneWire::new(pin)?;
+
+    find_temp_probe(owb, delay)
+}
+
+/// Get the temperature probe on the bus, if any
+///
+/// Like [`get_temp_probe`], for a bus which is already set up.
+pub fn find_temp_probe<T, U, P, E>(
+    mut owb: OneWire<T>,
+    delay: &mut U,
+) -> Result<Option<(Ds18b20, OneWire<T>)>, E>
+where
+    T: InputPin<Error = P> + OutputPin<Error = P>,
+    U: DelayMs<u16> + DelayUs<u16>,
+    P: core::fmt::Debug,
+    E: From<OneWireError<P>>,
+{
+    // find the device
+    let mut devs = owb.devices(false, delay);
+    let probe: Option<Ds18b20> = loop {
+        match devs.next() {
+            // found a device on the bus
+            Some(Ok(addr)) => {
+                debug!("addr: {:?}", addr);
+
+                // check if it's a temperature probe
+                match Ds18b20::new::<()>(addr) {
+                    Ok(x) => break Some(x),
+
+                    Err(e) => warn!("Ds::new     {:?}", e),
+                }
+            }
+
+            // found a device but it errored
+            Some(Err(e)) => {
+                warn!("devs.next   {:?}", e);
+            }
+
+            // no more devices
+            None => {
+                debug!("out of devices");
+                break None;
+            }
+        }
+    };
+
+    // if we found a probe
+    if let Some(probe) = probe {
+        // start measurement
+        probe.start_temp_measurement(&mut owb, delay)?;
+        ds18b20::Resolution::Bits12.delay_for_measurement_time(delay);
+
+        Ok(Some((probe, owb)))
+    } else {
+        // release the bus again
+        owb.release_bus()?;
+
+        Ok(None)
+    }
+}
+
+/// Read the last measured temperature from the probe
+///
+/// This reads the scratchpad directly instead of using `Ds18b20::read_data`,
+/// which converts to `f32`.
+pub fn read_temperature<T, U, P>(
+    probe: &Ds18b20,
+    owb: &mut OneWire<T>,
+    delay: &mut U,
+) -> Result<Temperature, OneWireError<P>>
+where
+    T: InputPin<Error = P> + OutputPin<Error = P>,
+    U: DelayUs<u16>,
+{
+    let scratchpad = ds18b20::read_scratchpad(probe.address(), owb, delay)?;
+    Ok(Temperature::from_scratchpad(&scratchpad))
+}
+
+#[cfg(all(test, feature = "sim"))]
+mod tests {
+    use super::*;
+    use crate::sim::onewire::{Bus, Delay, Ds18b20Model, Pin};
+    use core::cell::RefCell;
+    use core::convert::Infallible;
+
+    type Error = OneWireError<Infallible>;
+
+    #[test]
+    fn finds_the_probe_and_measures() {
+        let mut probes = [Ds18b20Model::with_serial(0x0000_0012_3456)];
+        probes[0].set_temperature(Temperature::from_raw(0x0191));
+        let rom = probes[0].rom();
+        let bus = RefCell::new(Bus::new(&mut probes));
+        let mut delay = Delay::new(&bus);
+
+        let (probe, mut owb) = get_temp_probe::<_, _, _, Error>(Pin::new(&bus), &mut delay)
+            .unwrap()
+            .expect("a probe");
+        assert_eq!(probe.address().0, rom);
+        // the first measurement was awaited
+        assert!(bus.borrow().now() >= 750_000);
+
+        let temp = read_temperature(&probe, &mut owb, &mut delay).unwrap();
+        assert_eq!(temp.millicelsius(), 25_062);
+    }
+
+    #[test]
+    fn follows_the_set_temperature() {
+        let mut probes = [Ds18b20Model::with_serial(7)];
+        let bus = RefCell::new(Bus::new(&mut probes));
+        let mut delay = Delay::new(&bus);
+        let owb = OneWire::new(Pin::new(&bus)).unwrap();
+        let (probe, mut owb) = find_temp_probe::<_, _, _, Error>(owb, &mut delay)
+            .unwrap()
+            .expect("a probe");
+
+        for raw in [-55 * 16, -162, 0, 85 * 16, 125 * 16] {
+            bus.borrow_mut().devices()[0].set_temperature(Temperature::from_raw(raw));
+            probe.start_temp_measurement(&mut owb, &mut delay).unwrap();
+            ds18b20::Resolution::Bits12.delay_for_measurement_time(&mut delay);
+
+            let temp = read_temperature(&probe, &mut owb, &mut delay).unwrap();
+            assert_eq!(temp.raw(), raw);
+        }
+    }
+
+    #[test]
+    fn no_device() {
+        let bus = RefCell::new(Bus::new(&mut []));
+        let mut delay = Delay::new(&bus);
+
+        let found = get_temp_probe::<_, _, _, Error>(Pin::new(&bus), &mut delay).unwrap();
+        assert!(found.is_none());
+        assert!(bus.borrow().is_high());
+    }
+
+    #[test]
+    fn corrupt_crc_is_an_error() {
+        let mut probes = [Ds18b20Model::with_serial(0x42)];
+        let bus = RefCell::new(Bus::new(&mut probes));
+        let mut delay = Delay::new(&bus);
+        let (probe, mut owb) = get_temp_probe::<_, _, _, Error>(Pin::new(&bus), &mut delay)
+            .unwrap()
+            .expect("a probe");
+
+        bus.borrow_mut().devices()[0].corrupt_crc(true);
+        let result = read_temperature(&probe, &mut owb, &mut delay);
+        assert!(
+            matches!(result, Err(OneWireError::CrcMismatch)),
+            "{:?}",
+            result
+        );
+
+        bus.borrow_mut().devices()[0].corrupt_crc(false);
+        assert!(read_temperature(&probe, &mut owb, &mut delay).is_ok());
+    }
+}
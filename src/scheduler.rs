@@ -0,0 +1,448 @@
+//! A cooperative scheduler for periodic and one-shot tasks
+//!
+//! Tasks are closures which run to completion, so none of them should block
+//! for long. They share state through `Cell`s or `RefCell`s.
+//!
+//! ```ignore
+//! let tim2 = Timer::tim2(tim2, &clocks, &mut apb1).start_count_down(10.ms());
+//! let mut scheduler = Scheduler::<_, Error, 4>::new(TimerClock::new(tim2, 10));
+//!
+//! let mut read = |_now| numpad.read::<Error>().map(|buttons| ...);
+//! scheduler.every("numpad", 100, &mut read).context("scheduler")?;
+//! scheduler.run()?;
+//! ```
+
+use embedded_hal::timer::{CountDown, Periodic};
+
+use crate::cycles::CycleCounter;
+
+/// A millisecond clock for the scheduler
+pub trait Clock {
+    /// Milliseconds since some start, wrapping around
+    fn now(&mut self) -> u32;
+
+    /// Wait until [`Clock::now`] reaches the deadline
+    fn wait_until(&mut self, deadline: u32);
+}
+
+/// Check if the wrapping time is at or past the deadline
+///
+/// Deadlines more than about 24 days ahead count as passed.
+pub fn reached(now: u32, deadline: u32) -> bool {
+    (now.wrapping_sub(deadline) as i32) >= 0
+}
+
+/// A clock which only moves when told to, for running tasks on the host
+#[derive(Debug, Default, Clone, Copy)]
+pub struct VirtualClock {
+    now: u32,
+}
+
+impl VirtualClock {
+    pub fn new(start: u32) -> Self {
+        Self { now: start }
+    }
+
+    /// Move the time forward
+    pub fn advance(&mut self, ms: u32) {
+        self.now = self.now.wrapping_add(ms);
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&mut self) -> u32 {
+        self.now
+    }
+
+    /// Jump straight to the deadline
+    fn wait_until(&mut self, deadline: u32) {
+        if !reached(self.now, deadline) {
+            self.now = deadline;
+        }
+    }
+}
+
+/// A clock which counts the periods of a hardware timer
+///
+/// The timer only remembers that a period passed, not how many, so a task
+/// running longer than a tick makes the clock fall behind. Pick a tick
+/// longer than the slowest task, or use a [`CycleClock`] where the time has
+/// to be right, like for a [`Supervisor`](crate::watchdog::Supervisor).
+pub struct TimerClock<T> {
+    timer: T,
+    tick_ms: u32,
+    now: u32,
+}
+
+impl<T: CountDown + Periodic> TimerClock<T> {
+    /// Count a timer which is already started with a period of `tick_ms`
+    pub fn new(timer: T, tick_ms: u32) -> Self {
+        Self {
+            timer,
+            tick_ms,
+            now: 0,
+        }
+    }
+
+    /// Release the timer again
+    pub fn release(self) -> T {
+        self.timer
+    }
+}
+
+impl<T: CountDown + Periodic> Clock for TimerClock<T> {
+    fn now(&mut self) -> u32 {
+        while self.timer.wait().is_ok() {
+            self.now = self.now.wrapping_add(self.tick_ms);
+        }
+        self.now
+    }
+
+    fn wait_until(&mut self, deadline: u32) {
+        while !reached(self.now(), deadline) {
+            nb::block!(self.timer.wait()).ok();
+            self.now = self.now.wrapping_add(self.tick_ms);
+        }
+    }
+}
+
+/// A clock which counts the cycles of the core, so it keeps time while a
+/// task runs long
+///
+/// The cycle counter wraps around in about a minute at 72 MHz, so the clock
+/// has to be read more often than that, as the scheduler does between tasks.
+pub struct CycleClock<C> {
+    counter: C,
+    /// The cycle count at the last whole millisecond
+    last: u32,
+    now: u32,
+}
+
+impl<C: CycleCounter> CycleClock<C> {
+    /// Start counting from 0 ms
+    pub fn new(counter: C) -> Self {
+        Self {
+            last: counter.cycles(),
+            counter,
+            now: 0,
+        }
+    }
+
+    /// Release the cycle counter again
+    pub fn release(self) -> C {
+        self.counter
+    }
+}
+
+impl<C: CycleCounter> Clock for CycleClock<C> {
+    fn now(&mut self) -> u32 {
+        let cycles_per_ms = self.counter.cycles_per_us() * 1000;
+        let ms = self.counter.cycles().wrapping_sub(self.last) / cycles_per_ms;
+        self.last = self.last.wrapping_add(ms * cycles_per_ms);
+        self.now = self.now.wrapping_add(ms);
+        self.now
+    }
+
+    fn wait_until(&mut self, deadline: u32) {
+        while !reached(self.now(), deadline) {}
+    }
+}
+
+/// The work of a task, which gets the current time
+pub type Job<'a, E> = &'a mut dyn FnMut(u32) -> Result<(), E>;
+
+/// A task added to a [`Scheduler`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Task(usize);
+
+struct Entry<'a, E> {
+    name: &'static str,
+    job: Job<'a, E>,
+    due: u32,
+    /// `None` for a one-shot task
+    period: Option<u32>,
+}
+
+/// Runs up to `N` tasks when they are due
+pub struct Scheduler<'a, C, E, const N: usize> {
+    clock: C,
+    tasks: [Option<Entry<'a, E>>; N],
+}
+
+impl<'a, C: Clock, E, const N: usize> Scheduler<'a, C, E, N> {
+    pub fn new(clock: C) -> Self {
+        Self {
+            clock,
+            tasks: core::array::from_fn(|_| None),
+        }
+    }
+
+    /// The clock, for example to advance a [`VirtualClock`]
+    pub fn clock(&mut self) -> &mut C {
+        &mut self.clock
+    }
+
+    pub fn now(&mut self) -> u32 {
+        self.clock.now()
+    }
+
+    /// Add a task which runs now and then every `period_ms`
+    ///
+    /// Returns `None` when all `N` places are taken.
+    pub fn every(&mut self, name: &'static str, period_ms: u32, job: Job<'a, E>) -> Option<Task> {
+        let now = self.clock.now();
+        self.add(name, job, now, Some(period_ms.max(1)))
+    }
+
+    /// Add a task which runs once, after `delay_ms`
+    ///
+    /// Returns `None` when all `N` places are taken.
+    pub fn after(&mut self, name: &'static str, delay_ms: u32, job: Job<'a, E>) -> Option<Task> {
+        let due = self.clock.now().wrapping_add(delay_ms);
+        self.add(name, job, due, None)
+    }
+
+    fn add(
+        &mut self,
+        name: &'static str,
+        job: Job<'a, E>,
+        due: u32,
+        period: Option<u32>,
+    ) -> Option<Task> {
+        let index = self.tasks.iter().position(Option::is_none)?;
+        self.tasks[index] = Some(Entry {
+            name,
+            job,
+            due,
+            period,
+        });
+        Some(Task(index))
+    }
+
+    /// Remove a task, which hands back its job
+    ///
+    /// A one-shot task is removed by itself once it ran, after which its
+    /// place can be taken by another task.
+    pub fn cancel(&mut self, task: Task) -> Option<Job<'a, E>> {
+        self.tasks[task.0].take().map(|entry| entry.job)
+    }
+
+    /// The name the task was added with
+    pub fn name(&self, task: Task) -> &'static str {
+        self.tasks[task.0].as_ref().map_or("", |entry| entry.name)
+    }
+
+    /// When the next task is due, if there are any left
+    pub fn next_due(&mut self) -> Option<u32> {
+        let now = self.clock.now();
+        self.tasks
+            .iter()
+            .flatten()
+            .map(|entry| entry.due)
+            .min_by_key(|&due| due.wrapping_sub(now) as i32)
+    }
+
+    /// Run every task which is due, the most overdue first
+    ///
+    /// A periodic task keeps its rhythm, but skips the periods it missed
+    /// entirely. Stops at the first error, and returns the number of tasks
+    /// which ran otherwise.
+    pub fn run_pending(&mut self) -> Result<usize, E> {
+        let mut ran = 0;
+        loop {
+            let now = self.clock.now();
+            let index = self
+                .tasks
+                .iter()
+                .enumerate()
+                .filter_map(|(index, entry)| Some((index, entry.as_ref()?.due)))
+                .filter(|&(_, due)| reached(now, due))
+                .min_by_key(|&(_, due)| due.wrapping_sub(now) as i32)
+                .map(|(index, _)| index);
+            let index = match index {
+                Some(index) => index,
+                None => return Ok(ran),
+            };
+
+            let result = match &mut self.tasks[index] {
+                Some(Entry {
+                    job,
+                    due,
+                    period: Some(period),
+                    ..
+                }) => {
+                    *due = due.wrapping_add(*period);
+                    if reached(now, *due) {
+                        *due = now.wrapping_add(*period);
+                    }
+                    job(now)
+                }
+                // a one-shot task is done after this
+                slot => slot.take().map_or(Ok(()), |entry| (entry.job)(now)),
+            };
+            result?;
+            ran += 1;
+        }
+    }
+
+    /// Run the tasks until none are left, waiting in between
+    ///
+    /// Only returns early on an error, or when all one-shot tasks are done
+    /// and there are no periodic ones.
+    pub fn run(&mut self) -> Result<(), E> {
+        loop {
+            self.run_pending()?;
+            match self.next_due() {
+                Some(due) => self.clock.wait_until(due),
+                None => return Ok(()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::{Cell, RefCell};
+    use std::vec::Vec;
+
+    type Error = ();
+
+    /// A job which notes the times it ran at
+    fn note(times: &RefCell<Vec<u32>>) -> impl FnMut(u32) -> Result<(), Error> + '_ {
+        move |now| {
+            times.borrow_mut().push(now);
+            Ok(())
+        }
+    }
+
+    /// Run the due tasks at every millisecond until `end`
+    fn run_until<const N: usize>(scheduler: &mut Scheduler<VirtualClock, Error, N>, end: u32) {
+        while !reached(scheduler.now(), end) {
+            scheduler.run_pending().unwrap();
+            scheduler.clock().advance(1);
+        }
+    }
+
+    #[test]
+    fn periodic_tasks_keep_their_cadence() {
+        let fast = RefCell::new(Vec::new());
+        let slow = RefCell::new(Vec::new());
+        let (mut fast_job, mut slow_job) = (note(&fast), note(&slow));
+        let mut scheduler = Scheduler::<_, Error, 2>::new(VirtualClock::new(1000));
+        scheduler.every("fast", 10, &mut fast_job).unwrap();
+        scheduler.every("slow", 25, &mut slow_job).unwrap();
+
+        run_until(&mut scheduler, 1100);
+        assert_eq!(*fast.borrow(), (1000..1100).step_by(10).collect::<Vec<_>>());
+        assert_eq!(*slow.borrow(), [1000, 1025, 1050, 1075]);
+        assert_eq!(scheduler.next_due(), Some(1100));
+    }
+
+    #[test]
+    fn a_one_shot_task_runs_once_and_frees_its_place() {
+        let once = RefCell::new(Vec::new());
+        let again = RefCell::new(Vec::new());
+        let (mut once_job, mut again_job) = (note(&once), note(&again));
+        let mut scheduler = Scheduler::<_, Error, 1>::new(VirtualClock::new(0));
+        let task = scheduler.after("once", 30, &mut once_job).unwrap();
+        assert_eq!(scheduler.name(task), "once");
+        assert_eq!(scheduler.next_due(), Some(30));
+
+        run_until(&mut scheduler, 100);
+        assert_eq!(*once.borrow(), [30]);
+        assert_eq!(scheduler.name(task), "");
+        assert_eq!(scheduler.next_due(), None);
+
+        // the place is free for another task
+        assert_eq!(scheduler.after("again", 5, &mut again_job), Some(task));
+        scheduler.run().unwrap();
+        assert_eq!(*again.borrow(), [105]);
+    }
+
+    #[test]
+    fn missed_periods_are_skipped() {
+        let times = RefCell::new(Vec::new());
+        let mut job = note(&times);
+        let mut scheduler = Scheduler::<_, Error, 1>::new(VirtualClock::new(0));
+        scheduler.every("late", 10, &mut job).unwrap();
+        scheduler.run_pending().unwrap();
+
+        // a little late keeps the rhythm
+        scheduler.clock().advance(13);
+        assert_eq!(scheduler.run_pending(), Ok(1));
+        assert_eq!(scheduler.next_due(), Some(20));
+
+        // more than a period late runs once, and starts over from now
+        scheduler.clock().advance(42);
+        assert_eq!(scheduler.run_pending(), Ok(1));
+        assert_eq!(scheduler.next_due(), Some(65));
+        assert_eq!(*times.borrow(), [0, 13, 55]);
+    }
+
+    #[test]
+    fn deadlines_across_the_wraparound() {
+        assert!(reached(5, u32::MAX - 5));
+        assert!(!reached(u32::MAX - 5, 5));
+        assert!(reached(u32::MAX, u32::MAX));
+        // more than half the range ahead counts as passed
+        assert!(reached(0, (1 << 31) + 1));
+        assert!(!reached(0, (1 << 31) - 1));
+
+        let times = RefCell::new(Vec::new());
+        let mut job = note(&times);
+        let mut scheduler = Scheduler::<_, Error, 1>::new(VirtualClock::new(u32::MAX - 24));
+        scheduler.every("wrap", 10, &mut job).unwrap();
+        run_until(&mut scheduler, 20);
+        assert_eq!(
+            *times.borrow(),
+            [u32::MAX - 24, u32::MAX - 14, u32::MAX - 4, 5, 15]
+        );
+        assert_eq!(scheduler.next_due(), Some(25));
+    }
+
+    /// A counter of 8 cycles per microsecond, which moves when told to
+    struct Cycles(Cell<u32>);
+
+    impl CycleCounter for &Cycles {
+        fn cycles(&self) -> u32 {
+            self.0.get()
+        }
+
+        fn cycles_per_us(&self) -> u32 {
+            8
+        }
+    }
+
+    #[test]
+    fn the_cycle_clock_keeps_the_fractions() {
+        let cycles = Cycles(Cell::new(u32::MAX - 10_000));
+        let mut clock = CycleClock::new(&cycles);
+        assert_eq!(clock.now(), 0);
+
+        // a long task, across the wraparound of the counter
+        cycles
+            .0
+            .set(cycles.0.get().wrapping_add(8_000 * 1234 + 7_999));
+        assert_eq!(clock.now(), 1234);
+        cycles.0.set(cycles.0.get().wrapping_add(1));
+        assert_eq!(clock.now(), 1235);
+        assert_eq!(clock.now(), 1235);
+    }
+
+    #[test]
+    fn no_place_when_all_are_taken() {
+        // a job is lent to the scheduler for good, even when turned away
+        let mut jobs: [_; 5] = core::array::from_fn(|_| |_| Ok(()));
+        let [first, second, third, fourth, fifth] = &mut jobs;
+        let mut scheduler = Scheduler::<_, Error, 2>::new(VirtualClock::new(0));
+        let task = scheduler.every("first", 10, first).unwrap();
+        scheduler.after("second", 10, second).unwrap();
+        assert!(scheduler.every("third", 10, third).is_none());
+        assert!(scheduler.after("fourth", 10, fourth).is_none());
+
+        assert!(scheduler.cancel(task).is_some());
+        assert_eq!(scheduler.every("fifth", 10, fifth), Some(task));
+        assert_eq!(scheduler.name(task), "fifth");
+    }
+}
@@ -0,0 +1,170 @@
+//! An I2C bus which replays a recorded transcript
+//!
+//! Every transaction the driver makes is compared to the next one in the
+//! transcript, and any difference panics, so a test fails at the first
+//! unexpected byte.
+
+use embedded_hal::blocking::i2c::{Read, Write, WriteRead};
+
+/// One transaction on the bus
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transaction<'a> {
+    /// The master writes the bytes to the address
+    Write(u8, &'a [u8]),
+    /// The master reads from the address, and the device answers with the bytes
+    Read(u8, &'a [u8]),
+    /// The master writes the first bytes, and the device answers with the second
+    WriteRead(u8, &'a [u8], &'a [u8]),
+    /// The master addresses a device which does not acknowledge
+    Nack(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The address was not acknowledged
+    Nack,
+}
+
+/// A bus which expects exactly the given transactions, in order
+pub struct Transcript<'a> {
+    expected: &'a [Transaction<'a>],
+    position: usize,
+}
+
+impl<'a> Transcript<'a> {
+    pub fn new(expected: &'a [Transaction<'a>]) -> Self {
+        Self {
+            expected,
+            position: 0,
+        }
+    }
+
+    /// Check that all transactions have happened
+    pub fn finish(&self) {
+        assert_eq!(
+            self.position,
+            self.expected.len(),
+            "transcript not finished, next is {:?}",
+            self.expected.get(self.position)
+        );
+    }
+
+    fn next(&mut self, address: u8) -> Result<Transaction<'a>, Error> {
+        let transaction = *self
+            .expected
+            .get(self.position)
+            .unwrap_or_else(|| panic!("unexpected transaction to {:#04x}", address));
+        self.position += 1;
+
+        match transaction {
+            Transaction::Nack(expected) => {
+                assert_eq!(address, expected, "transaction {}", self.position - 1);
+                Err(Error::Nack)
+            }
+            transaction => Ok(transaction),
+        }
+    }
+}
+
+impl Write for Transcript<'_> {
+    type Error = Error;
+
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Self::Error> {
+        let actual = Transaction::Write(address, bytes);
+        let expected = self.next(address)?;
+        assert_eq!(actual, expected, "transaction {}", self.position - 1);
+        Ok(())
+    }
+}
+
+impl Read for Transcript<'_> {
+    type Error = Error;
+
+    fn read(&mut self, address: u8, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        match self.next(address)? {
+            Transaction::Read(expected, response) if expected == address => {
+                buffer.copy_from_slice(response);
+                Ok(())
+            }
+            expected => panic!(
+                "transaction {}: expected {:?}, got a read of {} bytes from {:#04x}",
+                self.position - 1,
+                expected,
+                buffer.len(),
+                address
+            ),
+        }
+    }
+}
+
+impl WriteRead for Transcript<'_> {
+    type Error = Error;
+
+    fn write_read(
+        &mut self,
+        address: u8,
+        bytes: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        match self.next(address)? {
+            Transaction::WriteRead(expected, written, response)
+                if expected == address && written == bytes =>
+            {
+                buffer.copy_from_slice(response);
+                Ok(())
+            }
+            expected => panic!(
+                "transaction {}: expected {:?}, got a write of {:?} and read of {} bytes from {:#04x}",
+                self.position - 1,
+                expected,
+                bytes,
+                buffer.len(),
+                address
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replays_the_transactions() {
+        let transactions = [
+            Transaction::Write(0x27, &[0x08]),
+            Transaction::Read(0x48, &[1, 2]),
+            Transaction::WriteRead(0x68, &[0x75], &[0x68]),
+            Transaction::Nack(0x50),
+        ];
+        let mut bus = Transcript::new(&transactions);
+        let mut buffer = [0; 2];
+        assert_eq!(bus.write(0x27, &[0x08]), Ok(()));
+        assert_eq!(bus.read(0x48, &mut buffer), Ok(()));
+        assert_eq!(buffer, [1, 2]);
+        assert_eq!(bus.write_read(0x68, &[0x75], &mut buffer[..1]), Ok(()));
+        assert_eq!(buffer[0], 0x68);
+        assert_eq!(bus.write(0x50, &[0]), Err(Error::Nack));
+        bus.finish();
+    }
+
+    #[test]
+    #[should_panic(expected = "transaction 0")]
+    fn a_different_write_panics() {
+        let mut bus = Transcript::new(&[Transaction::Write(0x27, &[0x08])]);
+        bus.write(0x27, &[0x0c]).ok();
+    }
+
+    #[test]
+    #[should_panic(expected = "expected Write")]
+    fn a_read_instead_of_a_write_panics() {
+        let mut bus = Transcript::new(&[Transaction::Write(0x27, &[0x08])]);
+        bus.read(0x27, &mut [0]).ok();
+    }
+
+    #[test]
+    #[should_panic(expected = "not finished")]
+    fn missing_transactions_panic() {
+        Transcript::new(&[Transaction::Nack(0x27)]).finish();
+    }
+}
@@ -0,0 +1,6 @@
+//! Host-side models of the hardware, so drivers can be exercised without a board
+
+pub mod i2c;
+pub mod onewire;
+pub mod trace;
+pub mod uart;
@@ -0,0 +1,530 @@
+//! A simulated 1-Wire bus with virtual DS18B20 probes
+//!
+//! The bus keeps a virtual clock in microseconds which only advances through
+//! [`Delay`]. Every time the master releases the bus, the devices look at how
+//! long it was held low to tell reset pulses, write-0 and write-1/read slots
+//! apart, just like the real timing-based protocol.
+//!
+//! ```ignore
+//! let mut probes = [Ds18b20Model::new(0x0000_0012_3456_7828)];
+//! let bus = RefCell::new(Bus::new(&mut probes));
+//! let owb = OneWire::new(Pin::new(&bus))?;
+//! ```
+
+use core::cell::RefCell;
+use core::convert::Infallible;
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+use one_wire_bus::crc::crc8;
+
+use crate::temperature::Temperature;
+
+/// Pulses at least this long are a reset pulse
+const RESET_MIN_US: u64 = 480;
+/// Pulses shorter than this are a write-1 or read slot
+const SLOT_ONE_MAX_US: u64 = 15;
+/// When the presence pulse starts and stops, relative to the end of the reset pulse
+const PRESENCE_US: (u64, u64) = (15, 135);
+/// How long a device holds the bus low to send a 0
+const SEND_ZERO_US: u64 = 45;
+
+/// The shared state of the bus: the virtual clock, the master and all devices
+pub struct Bus<'a> {
+    now: u64,
+    master_low_since: Option<u64>,
+    devices: &'a mut [Ds18b20Model],
+}
+
+impl<'a> Bus<'a> {
+    /// Create a bus with the given devices connected
+    pub fn new(devices: &'a mut [Ds18b20Model]) -> Self {
+        Self {
+            now: 0,
+            master_low_since: None,
+            devices,
+        }
+    }
+
+    /// The virtual time in microseconds
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    /// Access the connected devices, e.g. to change their temperature
+    pub fn devices(&mut self) -> &mut [Ds18b20Model] {
+        self.devices
+    }
+
+    /// Check if nobody is pulling the bus low
+    pub fn is_high(&self) -> bool {
+        self.master_low_since.is_none() && !self.devices.iter().any(|d| d.pulls_low(self.now))
+    }
+
+    /// Let time pass
+    pub fn advance(&mut self, us: u64) {
+        self.now += us;
+    }
+
+    fn set_low(&mut self) {
+        if self.master_low_since.is_none() {
+            self.master_low_since = Some(self.now);
+            for device in self.devices.iter_mut() {
+                device.on_fall(self.now);
+            }
+        }
+    }
+
+    fn release(&mut self) {
+        if let Some(since) = self.master_low_since.take() {
+            for device in self.devices.iter_mut() {
+                device.on_rise(since, self.now);
+            }
+        }
+    }
+}
+
+/// The open-drain pin of the master
+///
+/// Setting it high releases the bus, reading it returns the wired-AND of all participants.
+pub struct Pin<'a, 'b> {
+    bus: &'b RefCell<Bus<'a>>,
+}
+
+impl<'a, 'b> Pin<'a, 'b> {
+    pub fn new(bus: &'b RefCell<Bus<'a>>) -> Self {
+        Self { bus }
+    }
+}
+
+impl OutputPin for Pin<'_, '_> {
+    type Error = Infallible;
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.bus.borrow_mut().set_low();
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.bus.borrow_mut().release();
+        Ok(())
+    }
+}
+
+impl InputPin for Pin<'_, '_> {
+    type Error = Infallible;
+
+    fn is_high(&self) -> Result<bool, Self::Error> {
+        Ok(self.bus.borrow().is_high())
+    }
+
+    fn is_low(&self) -> Result<bool, Self::Error> {
+        Ok(!self.bus.borrow().is_high())
+    }
+}
+
+/// A delay which advances the virtual clock of the bus instead of waiting
+pub struct Delay<'a, 'b> {
+    bus: &'b RefCell<Bus<'a>>,
+}
+
+impl<'a, 'b> Delay<'a, 'b> {
+    pub fn new(bus: &'b RefCell<Bus<'a>>) -> Self {
+        Self { bus }
+    }
+}
+
+macro_rules! impl_delay {
+    ($($t:ty),*) => {
+        $(
+            impl DelayUs<$t> for Delay<'_, '_> {
+                fn delay_us(&mut self, us: $t) {
+                    self.bus.borrow_mut().advance(us as u64);
+                }
+            }
+
+            impl DelayMs<$t> for Delay<'_, '_> {
+                fn delay_ms(&mut self, ms: $t) {
+                    self.bus.borrow_mut().advance(ms as u64 * 1000);
+                }
+            }
+        )*
+    };
+}
+
+impl_delay!(u8, u16, u32);
+
+/// What a device is doing in the current transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Waiting for a reset pulse
+    Idle,
+    /// Receiving a ROM command
+    RomCommand { byte: u8, bits: u8 },
+    /// Taking part in a search, `slots` counts the time slots started for this bit:
+    /// the bit and its complement are sent, then the chosen bit is received
+    Search { bit: u8, slots: u8 },
+    /// Receiving the address of a MATCH ROM
+    MatchRom { rom: u64, bits: u8 },
+    /// Receiving a function command
+    FunctionCommand { byte: u8, bits: u8 },
+    /// Receiving TH, TL and the config register
+    WriteScratchpad { data: [u8; 3], bits: u8 },
+    /// Sending `len` bytes, least significant bit first
+    Transmit { data: [u8; 9], len: u8, bits: u8 },
+    /// Answering read slots with 0 until the conversion is done
+    Converting,
+    /// Answering read slots with 1
+    Done,
+}
+
+/// A virtual DS18B20
+#[derive(Debug, Clone)]
+pub struct Ds18b20Model {
+    rom: u64,
+    temperature: Temperature,
+    scratchpad: [u8; 9],
+    eeprom: [u8; 3],
+    conversion_done_at: Option<u64>,
+    corrupt_crc: bool,
+    state: State,
+    pull_low: Option<(u64, u64)>,
+}
+
+impl Ds18b20Model {
+    /// Create a probe with the given ROM code, which is used as-is (including family code and CRC)
+    pub fn new(rom: u64) -> Self {
+        // factory defaults: TH = 75, TL = 70, 12 bits
+        let eeprom = [75, 70, 0b0111_1111];
+
+        let mut scratchpad = [0; 9];
+        scratchpad[..2].copy_from_slice(&Temperature::POWER_ON.raw().to_le_bytes());
+        scratchpad[2..5].copy_from_slice(&eeprom);
+        scratchpad[5] = 0xff;
+        scratchpad[7] = 0x10;
+
+        Self {
+            rom,
+            temperature: Temperature::from_raw(20 * 16),
+            scratchpad,
+            eeprom,
+            conversion_done_at: None,
+            corrupt_crc: false,
+            state: State::Idle,
+            pull_low: None,
+        }
+    }
+
+    /// Create a probe with the DS18B20 family code and a valid CRC for the given 48-bit serial
+    pub fn with_serial(serial: u64) -> Self {
+        let mut rom = [0; 8];
+        rom[0] = ds18b20::FAMILY_CODE;
+        rom[1..7].copy_from_slice(&serial.to_le_bytes()[..6]);
+        rom[7] = crc8(&rom[..7]);
+
+        Self::new(u64::from_le_bytes(rom))
+    }
+
+    /// The ROM code of the probe
+    pub fn rom(&self) -> u64 {
+        self.rom
+    }
+
+    /// Set the temperature the probe will measure on its next conversion
+    pub fn set_temperature(&mut self, temperature: Temperature) {
+        self.temperature = temperature;
+    }
+
+    /// Send a wrong CRC byte with the scratchpad
+    pub fn corrupt_crc(&mut self, corrupt: bool) {
+        self.corrupt_crc = corrupt;
+    }
+
+    /// The scratchpad as it is now, with pending conversions applied
+    pub fn scratchpad(&mut self, now: u64) -> [u8; 9] {
+        self.finish_conversion(now);
+
+        let mut scratchpad = self.scratchpad;
+        scratchpad[8] = crc8(&scratchpad[..8]);
+        if self.corrupt_crc {
+            scratchpad[8] ^= 0xff;
+        }
+        scratchpad
+    }
+
+    /// The conversion time in microseconds for the configured resolution
+    fn conversion_time(&self) -> u64 {
+        match (self.scratchpad[4] >> 5) & 0b11 {
+            0 => 93_750,
+            1 => 187_500,
+            2 => 375_000,
+            _ => 750_000,
+        }
+    }
+
+    fn finish_conversion(&mut self, now: u64) {
+        if let Some(done_at) = self.conversion_done_at {
+            if now >= done_at {
+                let undefined_bits = 3 - ((self.scratchpad[4] >> 5) & 0b11);
+                let raw = self.temperature.raw() & !((1 << undefined_bits) - 1);
+                self.scratchpad[..2].copy_from_slice(&raw.to_le_bytes());
+                self.conversion_done_at = None;
+            }
+        }
+    }
+
+    /// Check if the last measurement is outside the alarm limits
+    fn is_alarming(&self) -> bool {
+        let temp = Temperature::from_bytes(self.scratchpad[0], self.scratchpad[1]).celsius();
+        temp >= self.scratchpad[2] as i8 as i16 || temp <= self.scratchpad[3] as i8 as i16
+    }
+
+    fn pulls_low(&self, now: u64) -> bool {
+        matches!(self.pull_low, Some((start, end)) if start <= now && now < end)
+    }
+
+    /// Handle the master pulling the bus low, which starts every time slot
+    fn on_fall(&mut self, now: u64) {
+        let bit = match self.state {
+            State::Transmit { data, len, bits } => {
+                self.state = if bits + 1 == len * 8 {
+                    State::Done
+                } else {
+                    State::Transmit {
+                        data,
+                        len,
+                        bits: bits + 1,
+                    }
+                };
+                data[bits as usize / 8] >> (bits % 8) & 1 != 0
+            }
+
+            State::Search { bit, slots } => {
+                self.state = State::Search {
+                    bit,
+                    slots: slots + 1,
+                };
+                match slots {
+                    0 => self.rom >> bit & 1 != 0,
+                    1 => self.rom >> bit & 1 == 0,
+                    _ => return,
+                }
+            }
+
+            State::Converting => {
+                self.finish_conversion(now);
+                self.conversion_done_at.is_none()
+            }
+
+            _ => return,
+        };
+
+        if !bit {
+            self.pull_low = Some((now, now + SEND_ZERO_US));
+        }
+    }
+
+    /// Handle the master releasing the bus after `since`
+    fn on_rise(&mut self, since: u64, now: u64) {
+        let duration = now - since;
+
+        if duration >= RESET_MIN_US {
+            self.finish_conversion(now);
+            self.state = State::RomCommand { byte: 0, bits: 0 };
+            self.pull_low = Some((now + PRESENCE_US.0, now + PRESENCE_US.1));
+            return;
+        }
+
+        let bit = duration < SLOT_ONE_MAX_US;
+
+        self.state = match self.state {
+            State::RomCommand { byte, bits } => {
+                let byte = byte | (bit as u8) << bits;
+                if bits < 7 {
+                    State::RomCommand {
+                        byte,
+                        bits: bits + 1,
+                    }
+                } else {
+                    self.rom_command(byte)
+                }
+            }
+
+            State::Search {
+                bit: index,
+                slots: 3,
+            } => {
+                if bit != (self.rom >> index & 1 != 0) {
+                    // the master chose the other branch
+                    State::Idle
+                } else if index < 63 {
+                    State::Search {
+                        bit: index + 1,
+                        slots: 0,
+                    }
+                } else {
+                    State::Idle
+                }
+            }
+
+            State::MatchRom { rom, bits } => {
+                let rom = rom | (bit as u64) << bits;
+                if rom >> bits & 1 != self.rom >> bits & 1 {
+                    State::Idle
+                } else if bits < 63 {
+                    State::MatchRom {
+                        rom,
+                        bits: bits + 1,
+                    }
+                } else {
+                    State::FunctionCommand { byte: 0, bits: 0 }
+                }
+            }
+
+            State::FunctionCommand { byte, bits } => {
+                let byte = byte | (bit as u8) << bits;
+                if bits < 7 {
+                    State::FunctionCommand {
+                        byte,
+                        bits: bits + 1,
+                    }
+                } else {
+                    self.function_command(byte, now)
+                }
+            }
+
+            State::WriteScratchpad { mut data, bits } => {
+                data[bits as usize / 8] |= (bit as u8) << (bits % 8);
+                if bits < 23 {
+                    State::WriteScratchpad {
+                        data,
+                        bits: bits + 1,
+                    }
+                } else {
+                    // only the resolution bits of the config register can be written
+                    data[2] = data[2] & 0b0110_0000 | 0b0001_1111;
+                    self.scratchpad[2..5].copy_from_slice(&data);
+                    State::Idle
+                }
+            }
+
+            state => state,
+        };
+    }
+
+    fn rom_command(&mut self, command: u8) -> State {
+        match command {
+            one_wire_bus::commands::SEARCH_NORMAL => State::Search { bit: 0, slots: 0 },
+            one_wire_bus::commands::SEARCH_ALARM if self.is_alarming() => {
+                State::Search { bit: 0, slots: 0 }
+            }
+            one_wire_bus::commands::MATCH_ROM => State::MatchRom { rom: 0, bits: 0 },
+            one_wire_bus::commands::SKIP_ROM => State::FunctionCommand { byte: 0, bits: 0 },
+            // READ ROM
+            0x33 => {
+                let mut data = [0; 9];
+                data[..8].copy_from_slice(&self.rom.to_le_bytes());
+                State::Transmit {
+                    data,
+                    len: 8,
+                    bits: 0,
+                }
+            }
+            _ => State::Idle,
+        }
+    }
+
+    fn function_command(&mut self, command: u8, now: u64) -> State {
+        use ds18b20::commands::*;
+
+        match command {
+            CONVERT_TEMP => {
+                self.conversion_done_at = Some(now + self.conversion_time());
+                State::Converting
+            }
+            READ_SCRATCHPAD => State::Transmit {
+                data: self.scratchpad(now),
+                len: 9,
+                bits: 0,
+            },
+            WRITE_SCRATCHPAD => State::WriteScratchpad {
+                data: [0; 3],
+                bits: 0,
+            },
+            COPY_SCRATCHPAD => {
+                self.eeprom.copy_from_slice(&self.scratchpad[2..5]);
+                State::Done
+            }
+            RECALL_EEPROM => {
+                self.scratchpad[2..5].copy_from_slice(&self.eeprom);
+                State::Done
+            }
+            // READ POWER SUPPLY, externally powered
+            one_wire_bus::commands::READ_POWER_SUPPLY => State::Done,
+            _ => State::Idle,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use one_wire_bus::{OneWire, OneWireError};
+    use std::vec::Vec;
+
+    #[test]
+    fn reset_without_devices() {
+        let bus = RefCell::new(Bus::new(&mut []));
+        let mut owb = OneWire::new(Pin::new(&bus)).unwrap();
+
+        assert!(!owb.reset(&mut Delay::new(&bus)).unwrap());
+    }
+
+    #[test]
+    fn search_finds_every_probe() {
+        let mut probes = [
+            Ds18b20Model::with_serial(0x0000_0012_3456),
+            Ds18b20Model::with_serial(0x0000_0012_3457),
+            Ds18b20Model::with_serial(0x8000_0000_0001),
+            Ds18b20Model::with_serial(0x0000_00ff_0000),
+        ];
+        let mut expected: Vec<u64> = probes.iter().map(|probe| probe.rom()).collect();
+        let bus = RefCell::new(Bus::new(&mut probes));
+        let mut owb = OneWire::new(Pin::new(&bus)).unwrap();
+        let mut delay = Delay::new(&bus);
+
+        assert!(owb.reset(&mut delay).unwrap());
+        let found: Result<Vec<u64>, OneWireError<Infallible>> = owb
+            .devices(false, &mut delay)
+            .map(|address| address.map(|address| address.0))
+            .collect();
+        let mut found = found.unwrap();
+
+        // the search goes from the lowest bit up, so sort both
+        found.sort_unstable();
+        expected.sort_unstable();
+        assert_eq!(found, expected);
+    }
+
+    #[test]
+    fn rom_crc_is_valid() {
+        let rom = Ds18b20Model::with_serial(0x1234_5678_9abc)
+            .rom()
+            .to_le_bytes();
+
+        assert_eq!(rom[0], ds18b20::FAMILY_CODE);
+        assert_eq!(crc8(&rom), 0);
+    }
+
+    #[test]
+    fn conversion_takes_the_time_of_the_resolution() {
+        let mut probe = Ds18b20Model::with_serial(1);
+        probe.set_temperature(Temperature::from_raw(-162));
+        probe.function_command(ds18b20::commands::CONVERT_TEMP, 0);
+
+        assert_eq!(
+            probe.scratchpad(749_999)[..2],
+            Temperature::POWER_ON.raw().to_le_bytes()
+        );
+        assert_eq!(probe.scratchpad(750_000)[..2], (-162i16).to_le_bytes());
+    }
+}
@@ -0,0 +1,225 @@
+//! Simulated pins which record their levels, like a logic analyzer
+//!
+//! The trace keeps a virtual clock in nanoseconds. Every write to a [`Pin`]
+//! takes `step_ns`, like the instructions around a GPIO write, and [`Delay`]
+//! and [`Timer`] wait on the clock. `tools/sigrok` saves the changes as a
+//! sigrok session, to compare with real captures.
+//!
+//! ```ignore
+//! let trace = RefCell::new(Trace::<4096>::new(&["SDI", "CS", "SCL"], 1_000));
+//! let [sdi, cs, scl] = [0, 1, 2].map(|channel| Pin::new(&trace, channel));
+//! let mut matrix = MAX7219::from_pins(1, sdi, cs, scl)?;
+//! ```
+
+use core::cell::RefCell;
+use core::convert::Infallible;
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+use embedded_hal::timer::{CountDown, Periodic};
+use stm32f1xx_hal::time::Hertz;
+
+/// As many channels as the logic analyzer has
+pub const MAX_CHANNELS: usize = 8;
+
+/// The levels of all channels from a time on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Change {
+    pub time_ns: u64,
+    /// The level of channel n in bit n
+    pub levels: u8,
+}
+
+/// The recorded changes of up to `N` times, and the virtual clock
+pub struct Trace<const N: usize> {
+    now_ns: u64,
+    step_ns: u64,
+    names: [&'static str; MAX_CHANNELS],
+    channels: usize,
+    levels: u8,
+    changes: [Change; N],
+    len: usize,
+}
+
+impl<const N: usize> Trace<N> {
+    /// Create a trace of the named channels, all low, where a pin write takes `step_ns`
+    pub fn new(names: &[&'static str], step_ns: u64) -> Self {
+        assert!(names.len() <= MAX_CHANNELS, "at most 8 channels");
+        let mut all = [""; MAX_CHANNELS];
+        all[..names.len()].copy_from_slice(names);
+
+        Self {
+            now_ns: 0,
+            step_ns,
+            names: all,
+            channels: names.len(),
+            levels: 0,
+            changes: [Change {
+                time_ns: 0,
+                levels: 0,
+            }; N],
+            len: 0,
+        }
+    }
+
+    /// The virtual time in nanoseconds
+    pub fn now_ns(&self) -> u64 {
+        self.now_ns
+    }
+
+    pub fn advance_ns(&mut self, ns: u64) {
+        self.now_ns += ns;
+    }
+
+    pub fn names(&self) -> &[&'static str] {
+        &self.names[..self.channels]
+    }
+
+    /// The changes so far, oldest first; before the first all channels are low
+    pub fn changes(&self) -> &[Change] {
+        &self.changes[..self.len]
+    }
+
+    pub fn level(&self, channel: usize) -> bool {
+        self.levels & (1 << channel) != 0
+    }
+
+    /// Drive a channel, which takes a step of the clock
+    pub fn set(&mut self, channel: usize, level: bool) {
+        assert!(channel < self.channels, "no channel {}", channel);
+        let levels = (self.levels & !(1 << channel)) | ((level as u8) << channel);
+        if levels != self.levels {
+            self.levels = levels;
+            self.record();
+        }
+        self.now_ns += self.step_ns;
+    }
+
+    fn record(&mut self) {
+        let change = Change {
+            time_ns: self.now_ns,
+            levels: self.levels,
+        };
+        // only the last levels of a moment count
+        match self.changes[..self.len].last_mut() {
+            Some(last) if last.time_ns == change.time_ns => *last = change,
+            _ => {
+                assert!(self.len < N, "trace full");
+                self.changes[self.len] = change;
+                self.len += 1;
+            }
+        }
+    }
+}
+
+/// A pin driving a channel of the trace
+pub struct Pin<'a, const N: usize> {
+    trace: &'a RefCell<Trace<N>>,
+    channel: usize,
+}
+
+impl<'a, const N: usize> Pin<'a, N> {
+    pub fn new(trace: &'a RefCell<Trace<N>>, channel: usize) -> Self {
+        Self { trace, channel }
+    }
+}
+
+impl<const N: usize> OutputPin for Pin<'_, N> {
+    type Error = Infallible;
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.trace.borrow_mut().set(self.channel, false);
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.trace.borrow_mut().set(self.channel, true);
+        Ok(())
+    }
+}
+
+impl<const N: usize> InputPin for Pin<'_, N> {
+    type Error = Infallible;
+
+    fn is_high(&self) -> Result<bool, Self::Error> {
+        Ok(self.trace.borrow().level(self.channel))
+    }
+
+    fn is_low(&self) -> Result<bool, Self::Error> {
+        self.is_high().map(|high| !high)
+    }
+}
+
+/// A delay which advances the clock of the trace
+pub struct Delay<'a, const N: usize> {
+    trace: &'a RefCell<Trace<N>>,
+}
+
+impl<'a, const N: usize> Delay<'a, N> {
+    pub fn new(trace: &'a RefCell<Trace<N>>) -> Self {
+        Self { trace }
+    }
+}
+
+impl<const N: usize> DelayUs<u32> for Delay<'_, N> {
+    fn delay_us(&mut self, us: u32) {
+        self.trace.borrow_mut().advance_ns(us as u64 * 1_000);
+    }
+}
+
+impl<const N: usize> DelayUs<u16> for Delay<'_, N> {
+    fn delay_us(&mut self, us: u16) {
+        self.delay_us(us as u32);
+    }
+}
+
+impl<const N: usize> DelayMs<u32> for Delay<'_, N> {
+    fn delay_ms(&mut self, ms: u32) {
+        self.trace.borrow_mut().advance_ns(ms as u64 * 1_000_000);
+    }
+}
+
+impl<const N: usize> DelayMs<u16> for Delay<'_, N> {
+    fn delay_ms(&mut self, ms: u16) {
+        self.delay_ms(ms as u32);
+    }
+}
+
+/// A timer on the clock of the trace
+///
+/// Waiting advances the clock to the next tick, unless a tick already passed.
+/// Then it returns at once, like a hardware timer with its flag still set.
+pub struct Timer<'a, const N: usize> {
+    trace: &'a RefCell<Trace<N>>,
+    period_ns: u64,
+    next_ns: u64,
+}
+
+impl<'a, const N: usize> Timer<'a, N> {
+    pub fn new(trace: &'a RefCell<Trace<N>>) -> Self {
+        Self {
+            trace,
+            period_ns: 1,
+            next_ns: 0,
+        }
+    }
+}
+
+impl<const N: usize> CountDown for Timer<'_, N> {
+    type Time = Hertz;
+
+    fn start<T: Into<Hertz>>(&mut self, count: T) {
+        self.period_ns = (1_000_000_000 / count.into().0 as u64).max(1);
+        self.next_ns = self.trace.borrow().now_ns + self.period_ns;
+    }
+
+    fn wait(&mut self) -> nb::Result<(), void::Void> {
+        let mut trace = self.trace.borrow_mut();
+        trace.now_ns = trace.now_ns.max(self.next_ns);
+        while self.next_ns <= trace.now_ns {
+            self.next_ns += self.period_ns;
+        }
+        Ok(())
+    }
+}
+
+impl<const N: usize> Periodic for Timer<'_, N> {}
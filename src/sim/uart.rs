@@ -0,0 +1,194 @@
+//! A simulated UART line, to send with a `SoftUartTx` and receive the samples
+//!
+//! The line keeps a virtual clock in ticks of `rate` Hz, which only advances
+//! through [`Timer::wait`]. A transmitter records the levels it puts on the
+//! line, after which [`Line::rewind`] lets a receiver sample them again, or
+//! [`Line::samples`] hands them to a `Sampler` directly. A pin also stands in
+//! for an EXTI line, pending on every falling edge.
+//!
+//! ```ignore
+//! let line = RefCell::new(Line::new(9600 * 16));
+//! let mut tx = SoftUartTx::new(Pin::new(&line), Timer::new(&line), Config::default())?;
+//! write!(tx, "Hi")?;
+//! line.borrow_mut().rewind();
+//! let mut rx = SoftUartRx::<_, _, 4>::new(Pin::new(&line), Timer::new(&line), Config::default(), 16);
+//! ```
+
+use core::cell::RefCell;
+use core::convert::Infallible;
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+use embedded_hal::timer::{CountDown, Periodic};
+use stm32f1xx_hal::afio;
+use stm32f1xx_hal::gpio::{Edge, ExtiPin};
+use stm32f1xx_hal::pac::EXTI;
+use stm32f1xx_hal::time::Hertz;
+
+/// The most level changes a line remembers
+const MAX_EDGES: usize = 1024;
+
+/// The recorded levels of the line and the virtual clock
+pub struct Line {
+    now: u64,
+    rate: u32,
+    /// When the level changed, and to what
+    edges: [(u64, bool); MAX_EDGES],
+    len: usize,
+}
+
+impl Line {
+    /// Create an idle (high) line, its clock running at `rate` Hz
+    pub fn new(rate: u32) -> Self {
+        Self {
+            now: 0,
+            rate,
+            edges: [(0, true); MAX_EDGES],
+            len: 0,
+        }
+    }
+
+    /// The virtual time in ticks
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    /// Start the clock over, keeping the recorded levels
+    pub fn rewind(&mut self) {
+        self.now = 0;
+    }
+
+    /// The level at a time, high before anything was recorded
+    pub fn level_at(&self, time: u64) -> bool {
+        self.edges[..self.len]
+            .iter()
+            .rev()
+            .find(|&&(at, _)| at <= time)
+            .is_none_or(|&(_, level)| level)
+    }
+
+    /// The level of every tick after the start until `end`, as a timer would sample it
+    pub fn samples(&self, end: u64) -> impl Iterator<Item = bool> + '_ {
+        (1..=end).map(move |time| self.level_at(time))
+    }
+
+    /// Whether the level fell after `after`, up to and including `until`
+    fn fell(&self, after: u64, until: u64) -> bool {
+        self.edges[..self.len]
+            .iter()
+            .any(|&(at, level)| !level && at > after && at <= until)
+    }
+
+    fn set(&mut self, level: bool) {
+        // only the latest change of a tick counts
+        if self.len > 0 && self.edges[self.len - 1].0 == self.now {
+            self.len -= 1;
+        }
+        if self.level_at(self.now) == level {
+            return;
+        }
+        assert!(self.len < MAX_EDGES, "too many level changes");
+        self.edges[self.len] = (self.now, level);
+        self.len += 1;
+    }
+}
+
+/// A pin on the line, driving or sampling it at the current time
+pub struct Pin<'a> {
+    line: &'a RefCell<Line>,
+    /// Until when the falling edges were cleared
+    cleared: u64,
+}
+
+impl<'a> Pin<'a> {
+    pub fn new(line: &'a RefCell<Line>) -> Self {
+        Self { line, cleared: 0 }
+    }
+}
+
+impl OutputPin for Pin<'_> {
+    type Error = Infallible;
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.line.borrow_mut().set(false);
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.line.borrow_mut().set(true);
+        Ok(())
+    }
+}
+
+impl InputPin for Pin<'_> {
+    type Error = Infallible;
+
+    fn is_high(&self) -> Result<bool, Self::Error> {
+        let line = self.line.borrow();
+        Ok(line.level_at(line.now))
+    }
+
+    fn is_low(&self) -> Result<bool, Self::Error> {
+        self.is_high().map(|high| !high)
+    }
+}
+
+/// Always on the falling edge, without any registers to set up
+impl ExtiPin for Pin<'_> {
+    fn make_interrupt_source(&mut self, _: &mut afio::Parts) {}
+
+    fn trigger_on_edge(&mut self, _: &EXTI, _: Edge) {}
+
+    fn enable_interrupt(&mut self, _: &EXTI) {}
+
+    fn disable_interrupt(&mut self, _: &EXTI) {}
+
+    fn clear_interrupt_pending_bit(&mut self) {
+        self.cleared = self.line.borrow().now;
+    }
+
+    fn check_interrupt(&mut self) -> bool {
+        let line = self.line.borrow();
+        line.fell(self.cleared, line.now)
+    }
+}
+
+/// A timer on the clock of the line
+///
+/// Every call to `wait` is one tick of the line, and it ticks once every
+/// `rate / frequency` of those, like a hardware timer polled in a loop.
+pub struct Timer<'a> {
+    line: &'a RefCell<Line>,
+    period: u64,
+    next: u64,
+}
+
+impl<'a> Timer<'a> {
+    pub fn new(line: &'a RefCell<Line>) -> Self {
+        Self {
+            line,
+            period: 1,
+            next: 0,
+        }
+    }
+}
+
+impl CountDown for Timer<'_> {
+    type Time = Hertz;
+
+    fn start<T: Into<Hertz>>(&mut self, count: T) {
+        let line = self.line.borrow();
+        self.period = (line.rate / count.into().0).max(1) as u64;
+        self.next = line.now + self.period;
+    }
+
+    fn wait(&mut self) -> nb::Result<(), void::Void> {
+        let mut line = self.line.borrow_mut();
+        line.now += 1;
+        if line.now < self.next {
+            return Err(nb::Error::WouldBlock);
+        }
+        self.next += self.period;
+        Ok(())
+    }
+}
+
+impl Periodic for Timer<'_> {}
@@ -0,0 +1,715 @@
+//! A bit-banged UART, timed by a hardware timer
+//!
+//! ```ignore
+//! let timer = Timer::tim2(tim2, &clocks, &mut apb1).start_count_down(9600.hz());
+//! let mut tx = SoftUartTx::new(pb12, timer, Config::default())?;
+//! writeln!(tx, "Hello World!")?;
+//!
+//! // 16 samples a bit, with a falling edge on PB13 starting every frame
+//! let timer = Timer::tim3(tim3, &clocks, &mut apb1).start_count_down(1.hz());
+//! let mut rx = SoftUartRx::<_, _, 32>::new(pb13, timer, Config::default(), 16);
+//! rx.listen(&mut afio, &exti);
+//! ```
+
+use core::fmt;
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+use embedded_hal::serial;
+use embedded_hal::timer::{CountDown, Periodic};
+use stm32f1xx_hal::afio;
+use stm32f1xx_hal::gpio::{Edge, ExtiPin};
+use stm32f1xx_hal::pac::EXTI;
+use stm32f1xx_hal::time::{Hertz, U32Ext};
+
+use crate::error::{Error, Kind};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parity {
+    None,
+    /// The parity bit makes the number of ones even
+    Even,
+    /// The parity bit makes the number of ones odd
+    Odd,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopBits {
+    One,
+    Two,
+}
+
+/// Why a received word was dropped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RxError {
+    /// A stop bit was low
+    Framing,
+    /// The parity bit did not match the data
+    Parity,
+    /// The buffer was full
+    Overrun,
+}
+
+impl From<RxError> for Error {
+    fn from(item: RxError) -> Self {
+        Self::new(Kind::SoftUart(item))
+    }
+}
+
+/// The framing of the line, 9600 8N1 by default
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Config {
+    pub baud: u32,
+    pub data_bits: u8,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            baud: 9600,
+            data_bits: 8,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+        }
+    }
+}
+
+impl Config {
+    pub fn baud(mut self, baud: u32) -> Self {
+        self.baud = baud;
+        self
+    }
+
+    /// Set the number of data bits, from 5 to 8
+    pub fn data_bits(mut self, data_bits: u8) -> Self {
+        assert!((5..=8).contains(&data_bits), "5 to 8 data bits");
+        self.data_bits = data_bits;
+        self
+    }
+
+    pub fn parity(mut self, parity: Parity) -> Self {
+        self.parity = parity;
+        self
+    }
+
+    pub fn stop_bits(mut self, stop_bits: StopBits) -> Self {
+        self.stop_bits = stop_bits;
+        self
+    }
+
+    /// The parity bit for the data, if any
+    pub fn parity_bit(&self, data: u8) -> Option<bool> {
+        let odd_ones = data.count_ones() % 2 == 1;
+        match self.parity {
+            Parity::None => None,
+            Parity::Even => Some(odd_ones),
+            Parity::Odd => Some(!odd_ones),
+        }
+    }
+
+    /// The bits on the line for a word, first bit in bit 0, and their number
+    ///
+    /// Bits above the data bits of the word are dropped.
+    pub fn frame(&self, word: u8) -> (u16, u8) {
+        let data = (word as u16) & ((1 << self.data_bits) - 1);
+
+        // the start bit is low
+        let mut bits = data << 1;
+        let mut length = 1 + self.data_bits;
+
+        if let Some(parity) = self.parity_bit(data as u8) {
+            bits |= (parity as u16) << length;
+            length += 1;
+        }
+
+        let stop_bits = match self.stop_bits {
+            StopBits::One => 1,
+            StopBits::Two => 2,
+        };
+        bits |= ((1 << stop_bits) - 1) << length;
+        (bits, length + stop_bits)
+    }
+
+    /// The word in the bits of a received frame, first bit in bit 0
+    ///
+    /// The start bit is not checked, as it is what started the frame.
+    pub fn unframe(&self, frame: u16) -> Result<u8, RxError> {
+        let (_, length) = self.frame(0);
+        let data = ((frame >> 1) & ((1 << self.data_bits) - 1)) as u8;
+        let mut position = 1 + self.data_bits;
+
+        if let Some(parity) = self.parity_bit(data) {
+            if (frame >> position) & 1 != parity as u16 {
+                return Err(RxError::Parity);
+            }
+            position += 1;
+        }
+
+        let stop_bits = (1 << (length - position)) - 1;
+        if (frame >> position) & stop_bits != stop_bits {
+            return Err(RxError::Framing);
+        }
+        Ok(data)
+    }
+}
+
+/// The sending half of a bit-banged UART
+///
+/// Every bit starts on a tick of the timer, which runs at the baud rate.
+/// The timer is polled, so this only sends while being written to or
+/// flushed, like with `block!`, and a word must not be left half-sent.
+pub struct SoftUartTx<P, T> {
+    pin: P,
+    timer: T,
+    config: Config,
+    /// The bits left to send, first in bit 0
+    frame: u16,
+    remaining: u8,
+    /// Whether the last stop bit lasted its full time
+    idle: bool,
+}
+
+impl<P, T, E> SoftUartTx<P, T>
+where
+    P: OutputPin<Error = E>,
+    T: CountDown<Time = Hertz> + Periodic,
+{
+    /// Take the pin and the timer, and set the line idle
+    pub fn new(mut pin: P, mut timer: T, config: Config) -> Result<Self, E> {
+        pin.set_high()?;
+        timer.start(config.baud.hz());
+
+        Ok(Self {
+            pin,
+            timer,
+            config,
+            frame: 0,
+            remaining: 0,
+            idle: true,
+        })
+    }
+
+    /// Release the pin and the timer again
+    pub fn release(self) -> (P, T) {
+        (self.pin, self.timer)
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Put the next bit on the line
+    fn send_bit(&mut self) -> Result<(), E> {
+        if self.frame & 1 != 0 {
+            self.pin.set_high()?;
+        } else {
+            self.pin.set_low()?;
+        }
+        self.frame >>= 1;
+        self.remaining -= 1;
+        Ok(())
+    }
+
+    /// Wait for the next tick of the timer
+    fn tick(&mut self) -> nb::Result<(), E> {
+        self.timer
+            .wait()
+            .map_err(|e| e.map(|void| void::unreachable(void)))
+    }
+}
+
+impl<P, T, E> serial::Write<u8> for SoftUartTx<P, T>
+where
+    P: OutputPin<Error = E>,
+    T: CountDown<Time = Hertz> + Periodic,
+{
+    type Error = E;
+
+    /// Start sending the word once the previous one is out
+    fn write(&mut self, word: u8) -> nb::Result<(), E> {
+        self.flush()?;
+
+        // every frame starts a fresh period, so a pause between frames cannot
+        // shorten the start bit
+        let (frame, length) = self.config.frame(word);
+        self.frame = frame;
+        self.remaining = length;
+        self.timer.start(self.config.baud.hz());
+        self.send_bit()?;
+        self.idle = false;
+        Ok(())
+    }
+
+    /// Wait until the last stop bit is over
+    fn flush(&mut self) -> nb::Result<(), E> {
+        while self.remaining > 0 {
+            self.tick()?;
+            self.send_bit()?;
+        }
+
+        if !self.idle {
+            self.tick()?;
+            self.idle = true;
+        }
+        Ok(())
+    }
+}
+
+impl<P, T, E> fmt::Write for SoftUartTx<P, T>
+where
+    P: OutputPin<Error = E>,
+    T: CountDown<Time = Hertz> + Periodic,
+{
+    /// Send the string, and wait until it is out
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            nb::block!(serial::Write::write(self, byte)).map_err(|_| fmt::Error)?;
+        }
+        nb::block!(serial::Write::flush(self)).map_err(|_| fmt::Error)
+    }
+}
+
+/// Decodes the frames of a line sampled at a multiple of the baud rate
+///
+/// Every bit is decided by the majority of the three samples around its
+/// middle, or by the middle sample only at 3x oversampling.
+pub struct Sampler {
+    config: Config,
+    oversampling: u8,
+    /// The sample since the start of the frame, if there is one
+    position: Option<u16>,
+    /// The high samples of the current bit so far
+    highs: u8,
+    /// The bits received so far, first in bit 0
+    bits: u16,
+}
+
+impl Sampler {
+    /// Take 3 to 16 samples a bit
+    pub fn new(config: Config, oversampling: u8) -> Self {
+        assert!((3..=16).contains(&oversampling), "3x to 16x oversampling");
+        Self {
+            config,
+            oversampling,
+            position: None,
+            highs: 0,
+            bits: 0,
+        }
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn oversampling(&self) -> u8 {
+        self.oversampling
+    }
+
+    /// Check if no frame is being received
+    pub fn is_idle(&self) -> bool {
+        self.position.is_none()
+    }
+
+    /// The samples of every bit which are counted
+    fn window(&self) -> (u16, u16) {
+        let middle = (self.oversampling as u16 - 1) / 2;
+        if self.oversampling >= 4 {
+            (middle - 1, middle + 1)
+        } else {
+            (middle, middle)
+        }
+    }
+
+    /// Start a frame on a falling edge, the next sample being its first
+    ///
+    /// An edge after the start bit was decided is part of the data, so it is
+    /// ignored. Returns whether the samples were realigned.
+    pub fn start(&mut self) -> bool {
+        let (_, decided) = self.window();
+        match self.position {
+            Some(position) if position > decided => false,
+            _ => {
+                self.position = Some(0);
+                self.highs = 0;
+                self.bits = 0;
+                true
+            }
+        }
+    }
+
+    /// Take the next sample of the line, and the word once a frame is complete
+    ///
+    /// A low sample while idle starts a frame, so the line can be polled
+    /// without edge detection as well. A start bit which turns out to be high
+    /// was a glitch and is dropped without an error.
+    pub fn sample(&mut self, high: bool) -> Option<Result<u8, RxError>> {
+        let position = match self.position {
+            Some(position) => position,
+            None if high => return None,
+            None => {
+                self.start();
+                0
+            }
+        };
+        self.position = Some(position + 1);
+
+        let oversampling = self.oversampling as u16;
+        let (bit, phase) = (position / oversampling, position % oversampling);
+        let (first, last) = self.window();
+        if (first..=last).contains(&phase) {
+            self.highs += high as u8;
+        }
+        if phase != last {
+            return None;
+        }
+
+        let level = self.highs as u16 * 2 > last - first + 1;
+        self.highs = 0;
+        if bit == 0 && level {
+            self.position = None;
+            return None;
+        }
+        self.bits |= (level as u16) << bit;
+
+        // stop after the middle of the last stop bit, to be ready for the next edge
+        let (_, length) = self.config.frame(0);
+        if bit + 1 < length as u16 {
+            return None;
+        }
+        self.position = None;
+        Some(self.config.unframe(self.bits))
+    }
+}
+
+/// The received words, oldest first
+struct RingBuffer<const N: usize> {
+    words: [u8; N],
+    first: usize,
+    len: usize,
+}
+
+impl<const N: usize> RingBuffer<N> {
+    const fn new() -> Self {
+        Self {
+            words: [0; N],
+            first: 0,
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, word: u8) -> Result<(), RxError> {
+        if self.len == N {
+            return Err(RxError::Overrun);
+        }
+        self.words[(self.first + self.len) % N] = word;
+        self.len += 1;
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Option<u8> {
+        if self.len == 0 {
+            return None;
+        }
+        let word = self.words[self.first];
+        self.first = (self.first + 1) % N;
+        self.len -= 1;
+        Some(word)
+    }
+}
+
+/// The receiving half of a bit-banged UART, buffering up to `N` words
+///
+/// The timer runs at the baud rate times the oversampling, and every tick
+/// the pin is sampled by [`SoftUartRx::poll`], usually from the interrupt of
+/// the timer. With [`SoftUartRx::listen`], the falling edge of a start bit
+/// restarts the timer from [`SoftUartRx::on_edge`], so the samples line up
+/// with the bits instead of being up to a sample late.
+///
+/// At 9600 baud and 16x oversampling that is an interrupt every 6.5 µs, so
+/// take less samples for faster lines.
+pub struct SoftUartRx<P, T, const N: usize> {
+    pin: P,
+    timer: T,
+    sampler: Sampler,
+    buffer: RingBuffer<N>,
+    /// The first error since the last read
+    error: Option<RxError>,
+}
+
+impl<P, T, const N: usize> SoftUartRx<P, T, N>
+where
+    P: InputPin,
+    T: CountDown<Time = Hertz> + Periodic,
+{
+    /// Take the pin and the timer, sampling `oversampling` (3 to 16) times a bit
+    pub fn new(pin: P, mut timer: T, config: Config, oversampling: u8) -> Self {
+        let sampler = Sampler::new(config, oversampling);
+        timer.start(Self::rate(&sampler));
+
+        Self {
+            pin,
+            timer,
+            sampler,
+            buffer: RingBuffer::new(),
+            error: None,
+        }
+    }
+
+    /// Release the pin and the timer again
+    pub fn release(self) -> (P, T) {
+        (self.pin, self.timer)
+    }
+
+    pub fn config(&self) -> &Config {
+        self.sampler.config()
+    }
+
+    fn rate(sampler: &Sampler) -> Hertz {
+        (sampler.config().baud * sampler.oversampling() as u32).hz()
+    }
+
+    /// Sample the pin if the timer ticked
+    ///
+    /// Call it from the interrupt of the timer, which this clears, or at
+    /// least as often as the timer ticks.
+    pub fn poll(&mut self) {
+        if self.timer.wait().is_err() {
+            return;
+        }
+
+        // a pin which cannot be read looks idle
+        let high = self.pin.is_high().unwrap_or(true);
+        let result = match self.sampler.sample(high) {
+            Some(Ok(word)) => self.buffer.push(word),
+            Some(Err(e)) => Err(e),
+            None => Ok(()),
+        };
+        if let Err(e) = result {
+            self.error.get_or_insert(e);
+        }
+    }
+}
+
+impl<P, T, const N: usize> SoftUartRx<P, T, N>
+where
+    P: InputPin + ExtiPin,
+    T: CountDown<Time = Hertz> + Periodic,
+{
+    /// Interrupt on the falling edge of a start bit
+    pub fn listen(&mut self, afio: &mut afio::Parts, exti: &EXTI) {
+        self.pin.make_interrupt_source(afio);
+        self.pin.trigger_on_edge(exti, Edge::FALLING);
+        self.pin.enable_interrupt(exti);
+    }
+
+    /// Align the samples to a start bit; call it from the interrupt of the pin
+    pub fn on_edge(&mut self) {
+        self.pin.clear_interrupt_pending_bit();
+        if self.sampler.start() {
+            self.timer.start(Self::rate(&self.sampler));
+            // drop a tick of the old period which is still pending
+            self.timer.wait().ok();
+        }
+    }
+}
+
+impl<P, T, const N: usize> serial::Read<u8> for SoftUartRx<P, T, N> {
+    type Error = RxError;
+
+    /// Take the oldest word, after reporting what went wrong since the last read
+    fn read(&mut self) -> nb::Result<u8, RxError> {
+        if let Some(e) = self.error.take() {
+            return Err(nb::Error::Other(e));
+        }
+        self.buffer.pop().ok_or(nb::Error::WouldBlock)
+    }
+}
+
+#[cfg(all(test, feature = "sim"))]
+mod tests {
+    use super::*;
+    use crate::sim::uart::{Line, Pin, Timer};
+    use core::cell::RefCell;
+    use embedded_hal::serial::{Read, Write};
+    use std::vec::Vec;
+
+    const BAUD: u32 = 9600;
+    /// Ticks of the line a second, fine enough for any clock error
+    const RATE: u32 = BAUD * 16 * 20;
+
+    /// Send the words, and rewind the line to receive them; returns when they end
+    fn send(line: &RefCell<Line>, config: Config, words: &[u8]) -> u64 {
+        let mut tx = SoftUartTx::new(Pin::new(line), Timer::new(line), config).unwrap();
+        for &word in words {
+            nb::block!(tx.write(word)).unwrap();
+        }
+        nb::block!(tx.flush()).unwrap();
+        let end = line.borrow().now() + RATE as u64 / BAUD as u64;
+        line.borrow_mut().rewind();
+        end
+    }
+
+    /// Read everything the receiver has, errors included
+    fn received<P, T, const N: usize>(rx: &mut SoftUartRx<P, T, N>) -> Vec<Result<u8, RxError>> {
+        let mut received = Vec::new();
+        loop {
+            match rx.read() {
+                Ok(word) => received.push(Ok(word)),
+                Err(nb::Error::Other(e)) => received.push(Err(e)),
+                Err(nb::Error::WouldBlock) => return received,
+            }
+        }
+    }
+
+    /// Send the words, and receive what a receiver with its own config makes of them
+    fn loopback<const N: usize>(
+        tx_config: Config,
+        rx_config: Config,
+        oversampling: u8,
+        words: &[u8],
+    ) -> Vec<Result<u8, RxError>> {
+        let line = RefCell::new(Line::new(RATE));
+        let end = send(&line, tx_config, words);
+
+        let mut rx =
+            SoftUartRx::<_, _, N>::new(Pin::new(&line), Timer::new(&line), rx_config, oversampling);
+        while line.borrow().now() < end {
+            rx.poll();
+        }
+        received(&mut rx)
+    }
+
+    /// Like [`loopback`], but every falling edge calls `on_edge` as its interrupt would
+    fn loopback_on_edges(
+        tx_config: Config,
+        oversampling: u8,
+        words: &[u8],
+    ) -> Vec<Result<u8, RxError>> {
+        let line = RefCell::new(Line::new(RATE));
+        let end = send(&line, tx_config, words);
+
+        let mut rx = SoftUartRx::<_, _, 64>::new(
+            Pin::new(&line),
+            Timer::new(&line),
+            Config::default(),
+            oversampling,
+        );
+        let mut exti = Pin::new(&line);
+        while line.borrow().now() < end {
+            if exti.check_interrupt() {
+                exti.clear_interrupt_pending_bit();
+                rx.on_edge();
+            }
+            rx.poll();
+        }
+        received(&mut rx)
+    }
+
+    /// The baud rate of a clock which is off by the permille
+    fn off_by(permille: i32) -> u32 {
+        (BAUD as i32 * (1000 + permille) / 1000) as u32
+    }
+
+    #[test]
+    fn loopback_with_clock_error() {
+        let words: Vec<u8> = (0..=255)
+            .step_by(7)
+            .chain([0x00, 0xff, 0x55, 0xaa])
+            .collect();
+        let expected: Vec<_> = words.iter().map(|&word| Ok(word)).collect();
+
+        for (oversampling, permille) in [(3, 20), (8, 30), (16, 30)] {
+            for error in [-permille, 0, permille] {
+                let tx = Config::default().baud(off_by(error));
+                let received = loopback::<64>(tx, Config::default(), oversampling, &words);
+                assert_eq!(received, expected, "{}x, {}‰", oversampling, error);
+            }
+        }
+    }
+
+    #[test]
+    fn start_bits_align_the_samples_on_their_edge() {
+        let words: Vec<u8> = (0..=255).step_by(5).chain([0x00, 0xff, 0x55]).collect();
+        let expected: Vec<_> = words.iter().map(|&word| Ok(word)).collect();
+
+        for oversampling in [3, 8, 16] {
+            for error in [-50, -30, 0, 30] {
+                let tx = Config::default().baud(off_by(error));
+                let received = loopback_on_edges(tx, oversampling, &words);
+                assert_eq!(received, expected, "{}x, {}‰", oversampling, error);
+            }
+
+            // polled, the start bit is found up to a sample late, which a slow sender adds to
+            let tx = Config::default().baud(off_by(-50));
+            let polled = loopback::<64>(tx, Config::default(), oversampling, &words);
+            assert_ne!(polled, expected, "{}x", oversampling);
+        }
+    }
+
+    #[test]
+    fn loopback_of_other_framings() {
+        let configs = [
+            Config::default().data_bits(7).parity(Parity::Even),
+            Config::default().data_bits(5).stop_bits(StopBits::Two),
+            Config::default()
+                .parity(Parity::Odd)
+                .stop_bits(StopBits::Two),
+        ];
+        for config in configs {
+            let mask = ((1u16 << config.data_bits) - 1) as u8;
+            let words = [0x00, 0x1f, 0x15, 0x7f, 0xff];
+            let expected: Vec<_> = words.iter().map(|&word| Ok(word & mask)).collect();
+
+            for oversampling in [3, 8, 16] {
+                let tx = config.baud(off_by(-15));
+                let received = loopback::<8>(tx, config, oversampling, &words);
+                assert_eq!(received, expected, "{:?} at {}x", config, oversampling);
+            }
+        }
+    }
+
+    #[test]
+    fn parity_error() {
+        let tx = Config::default().parity(Parity::Even);
+        let rx = Config::default().parity(Parity::Odd);
+
+        for oversampling in [3, 8, 16] {
+            let received = loopback::<8>(tx, rx, oversampling, &[0x41]);
+            assert_eq!(received, [Err(RxError::Parity)]);
+        }
+    }
+
+    #[test]
+    fn broken_stop_bit_is_a_framing_error() {
+        // the even parity bit of 0x03 is low, where the receiver expects the stop bit
+        let tx = Config::default().parity(Parity::Even);
+
+        for oversampling in [3, 8, 16] {
+            let received = loopback::<8>(tx, Config::default(), oversampling, &[0x03, 0x01]);
+            assert_eq!(received, [Err(RxError::Framing), Ok(0x01)]);
+        }
+    }
+
+    #[test]
+    fn overrun_keeps_the_oldest_words() {
+        for oversampling in [3, 8, 16] {
+            let received =
+                loopback::<2>(Config::default(), Config::default(), oversampling, b"abcd");
+            assert_eq!(received, [Err(RxError::Overrun), Ok(b'a'), Ok(b'b')]);
+        }
+    }
+
+    #[test]
+    fn frame_and_unframe() {
+        let config = Config::default()
+            .parity(Parity::Even)
+            .stop_bits(StopBits::Two);
+        let (bits, length) = config.frame(0x03);
+        assert_eq!(length, 12);
+        // start, the data LSB first, the parity and two stop bits
+        assert_eq!(bits, 0b1100_0000_0110);
+        assert_eq!(config.unframe(bits), Ok(0x03));
+        assert_eq!(config.unframe(bits ^ 1 << 9), Err(RxError::Parity));
+        assert_eq!(config.unframe(bits & !(1 << 11)), Err(RxError::Framing));
+    }
+}
@@ -0,0 +1,369 @@
+//! The SUMP protocol of the Openbench Logic Sniffer, which PulseView speaks
+//!
+//! The host sends commands of one byte, or of five when the first has its top
+//! bit set: an opcode and a little-endian argument. The device answers the ID
+//! and metadata commands, and sends the samples after a run, newest first.
+//!
+//! ```ignore
+//! let mut parser = Parser::new();
+//! let mut settings = Settings::default();
+//! if let Some(command) = parser.push(byte) {
+//!     settings.apply(command);
+//! }
+//! ```
+
+/// What the device answers to [`Command::Id`]
+pub const ID: &[u8; 4] = b"1ALS";
+
+/// The clock which the divider of the host divides
+pub const CLOCK_HZ: u32 = 100_000_000;
+
+/// A command from the host
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Reset,
+    Run,
+    Id,
+    Metadata,
+    /// The sample rate is [`CLOCK_HZ`] / (divider + 1)
+    Divider(u32),
+    /// Samples to send, and of those how many after the trigger
+    Counts {
+        read: u32,
+        delay: u32,
+    },
+    Flags(u32),
+    TriggerMask(u32),
+    TriggerValue(u32),
+    TriggerConfig(u32),
+    /// Any other command, like flow control or the other trigger stages
+    Unknown(u8),
+}
+
+/// Collects the bytes from the host into commands
+#[derive(Debug, Default)]
+pub struct Parser {
+    bytes: [u8; 5],
+    len: usize,
+}
+
+impl Parser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a byte, which may complete a command
+    pub fn push(&mut self, byte: u8) -> Option<Command> {
+        self.bytes[self.len] = byte;
+        self.len += 1;
+
+        let opcode = self.bytes[0];
+        if opcode & 0x80 != 0 && self.len < 5 {
+            return None;
+        }
+        self.len = 0;
+
+        let argument =
+            u32::from_le_bytes([self.bytes[1], self.bytes[2], self.bytes[3], self.bytes[4]]);
+        Some(match opcode {
+            0x00 => Command::Reset,
+            0x01 => Command::Run,
+            0x02 => Command::Id,
+            0x04 => Command::Metadata,
+            0x80 => Command::Divider(argument & 0x00ff_ffff),
+            // both count four samples at a time, less one
+            0x81 => Command::Counts {
+                read: ((argument & 0xffff) + 1) * 4,
+                delay: ((argument >> 16) + 1) * 4,
+            },
+            0x82 => Command::Flags(argument),
+            0xc0 => Command::TriggerMask(argument),
+            0xc1 => Command::TriggerValue(argument),
+            0xc2 => Command::TriggerConfig(argument),
+            opcode => Command::Unknown(opcode),
+        })
+    }
+}
+
+/// Fires on the sample where the masked channels start to match the value
+///
+/// It only fires on a change into the pattern, so a level which already
+/// matched when the capture started waits for the next edge. Without a mask
+/// it fires at once.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Trigger {
+    pub mask: u8,
+    pub value: u8,
+}
+
+impl Trigger {
+    pub fn is_immediate(&self) -> bool {
+        self.mask == 0
+    }
+
+    pub fn matches(&self, sample: u8) -> bool {
+        (sample ^ self.value) & self.mask == 0
+    }
+
+    /// Whether it fires on `sample`, after `previous`
+    pub fn fires(&self, previous: u8, sample: u8) -> bool {
+        self.is_immediate() || (self.matches(sample) && !self.matches(previous))
+    }
+}
+
+/// How to capture, as the host set it up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Settings {
+    pub divider: u32,
+    pub read: u32,
+    pub delay: u32,
+    pub flags: u32,
+    /// Stage 0, as the others are not supported
+    pub trigger: Trigger,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            divider: 99,
+            read: 4096,
+            delay: 4096,
+            flags: 0,
+            trigger: Trigger::default(),
+        }
+    }
+}
+
+impl Settings {
+    /// Remember a command which sets something up
+    pub fn apply(&mut self, command: Command) {
+        match command {
+            Command::Divider(divider) => self.divider = divider,
+            Command::Counts { read, delay } => {
+                self.read = read;
+                self.delay = delay;
+            }
+            Command::Flags(flags) => self.flags = flags,
+            // only channels 0-7 are sampled
+            Command::TriggerMask(mask) => self.trigger.mask = mask as u8,
+            Command::TriggerValue(value) => self.trigger.value = value as u8,
+            _ => {}
+        }
+    }
+
+    pub fn rate(&self) -> u32 {
+        CLOCK_HZ / (self.divider + 1)
+    }
+
+    /// The groups of 8 channels to send, by bit, which the flags disable
+    pub fn groups(&self) -> u8 {
+        !(self.flags >> 2) as u8 & 0x0f
+    }
+
+    /// Samples before and after the trigger, of at most `samples` together
+    pub fn window(&self, samples: u32) -> (u32, u32) {
+        let read = self.read.min(samples);
+        let after = self.delay.min(read);
+        (read - after, after)
+    }
+}
+
+/// What the device tells the host about itself
+#[derive(Debug, Clone, Copy)]
+pub struct Device {
+    pub name: &'static str,
+    pub version: &'static str,
+    pub probes: u32,
+    /// Bytes of sample memory
+    pub memory: u32,
+    pub max_rate: u32,
+}
+
+impl Device {
+    /// The answer to [`Command::Metadata`], a byte at a time
+    pub fn metadata(&self, mut emit: impl FnMut(u8)) {
+        let mut string = |key: u8, text: &str| {
+            emit(key);
+            text.bytes().for_each(&mut emit);
+            emit(0);
+        };
+        string(0x01, self.name);
+        string(0x02, self.version);
+
+        for (key, value) in [
+            (0x20, self.probes),
+            (0x21, self.memory),
+            (0x23, self.max_rate),
+            (0x24, 2),
+        ] {
+            emit(key);
+            value.to_be_bytes().into_iter().for_each(&mut emit);
+        }
+        emit(0x00);
+    }
+}
+
+/// The bytes of a sample, a byte for every group which is sent
+pub fn sample_bytes(sample: u8, groups: u8, mut emit: impl FnMut(u8)) {
+    for group in 0..4 {
+        if groups & (1 << group) != 0 {
+            // only the first group has channels
+            emit(if group == 0 { sample } else { 0 });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec::Vec;
+
+    fn parse(bytes: &[u8]) -> Vec<Command> {
+        let mut parser = Parser::new();
+        bytes.iter().filter_map(|&byte| parser.push(byte)).collect()
+    }
+
+    #[test]
+    fn short_commands_are_one_byte() {
+        assert_eq!(
+            parse(&[0x00, 0x02, 0x04, 0x01, 0x11]),
+            [
+                Command::Reset,
+                Command::Id,
+                Command::Metadata,
+                Command::Run,
+                // XON, which is not supported
+                Command::Unknown(0x11),
+            ]
+        );
+    }
+
+    #[test]
+    fn long_commands_take_four_bytes_of_argument() {
+        let mut parser = Parser::new();
+        for &byte in &[0xc0, 0x01, 0x02, 0x03] {
+            assert_eq!(parser.push(byte), None);
+        }
+        assert_eq!(parser.push(0x04), Some(Command::TriggerMask(0x0403_0201)));
+
+        // a long opcode which is not known still takes its argument
+        assert_eq!(
+            parse(&[0xc4, 0x00, 0x01, 0x02, 0x03, 0x02, 0x82, 0x3c, 0, 0, 0]),
+            [Command::Unknown(0xc4), Command::Id, Command::Flags(0x3c)]
+        );
+    }
+
+    #[test]
+    fn arguments_as_the_host_sends_them() {
+        assert_eq!(
+            parse(&[
+                0x80, 0x63, 0x00, 0x00, 0xff, // divider of 99, with a stray top byte
+                0x81, 0xff, 0x03, 0x3f, 0x00, // 4096 samples, 256 after the trigger
+                0xc1, 0x05, 0x00, 0x00, 0x00, //
+                0xc2, 0x00, 0x00, 0x00, 0x08, //
+            ]),
+            [
+                Command::Divider(99),
+                Command::Counts {
+                    read: 4096,
+                    delay: 256
+                },
+                Command::TriggerValue(0x05),
+                Command::TriggerConfig(0x0800_0000),
+            ]
+        );
+        assert_eq!(
+            parse(&[0x81, 0, 0, 0, 0]),
+            [Command::Counts { read: 4, delay: 4 }]
+        );
+    }
+
+    #[test]
+    fn settings_follow_the_commands() {
+        let mut settings = Settings::default();
+        assert_eq!(settings.rate(), 1_000_000);
+        assert_eq!(settings.groups(), 0b1111);
+
+        for command in parse(&[
+            0x80, 0x31, 0x00, 0x00, 0x00, //
+            0x81, 0xff, 0x00, 0x3f, 0x00, //
+            0x82, 0x38, 0x00, 0x00, 0x00, //
+            0xc0, 0x03, 0x01, 0x00, 0x00, //
+            0xc1, 0x01, 0x00, 0x00, 0x00, //
+        ]) {
+            settings.apply(command);
+        }
+        assert_eq!(settings.rate(), 2_000_000);
+        assert_eq!((settings.read, settings.delay), (1024, 256));
+        // the flags disable groups 2 to 4
+        assert_eq!(settings.groups(), 0b0001);
+        // only the first 8 channels count
+        assert_eq!(
+            settings.trigger,
+            Trigger {
+                mask: 0x03,
+                value: 0x01
+            }
+        );
+    }
+
+    #[test]
+    fn the_window_fits_the_memory() {
+        let settings = Settings {
+            read: 4096,
+            delay: 1024,
+            ..Settings::default()
+        };
+        assert_eq!(settings.window(12_000), (3072, 1024));
+        assert_eq!(settings.window(2000), (976, 1024));
+        assert_eq!(settings.window(500), (0, 500));
+    }
+
+    #[test]
+    fn a_trigger_fires_on_a_change_into_the_pattern() {
+        let trigger = Trigger {
+            mask: 0b0000_0011,
+            value: 0b0000_0001,
+        };
+        assert!(trigger.fires(0b0000_0000, 0b1111_0101));
+        assert!(!trigger.fires(0b0000_0001, 0b0000_0001));
+        assert!(!trigger.fires(0b0000_0000, 0b0000_0011));
+        assert!(Trigger::default().fires(0x55, 0x55));
+    }
+
+    #[test]
+    fn metadata_is_tagged() {
+        let device = Device {
+            name: "ab",
+            version: "1",
+            probes: 8,
+            memory: 0x1234,
+            max_rate: 1_000_000,
+        };
+        let mut bytes = Vec::new();
+        device.metadata(|byte| bytes.push(byte));
+        assert_eq!(
+            bytes,
+            [
+                0x01, b'a', b'b', 0x00, //
+                0x02, b'1', 0x00, //
+                0x20, 0, 0, 0, 8, //
+                0x21, 0, 0, 0x12, 0x34, //
+                0x23, 0, 0x0f, 0x42, 0x40, //
+                0x24, 0, 0, 0, 2, //
+                0x00,
+            ]
+        );
+    }
+
+    #[test]
+    fn samples_have_a_byte_per_group() {
+        let mut bytes = Vec::new();
+        sample_bytes(0xa5, 0b1111, |byte| bytes.push(byte));
+        assert_eq!(bytes, [0xa5, 0, 0, 0]);
+
+        bytes.clear();
+        sample_bytes(0xa5, 0b1010, |byte| bytes.push(byte));
+        assert_eq!(bytes, [0, 0]);
+    }
+}
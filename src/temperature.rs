@@ -0,0 +1,273 @@
+use core::fmt;
+
+/// A temperature in the native DS18B20 format: a signed number of 1/16 °C
+///
+/// All conversions are done in integer arithmetic, so no soft-float code
+/// is pulled into the binary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Temperature {
+    raw: i16,
+}
+
+/// The scales a temperature can be expressed in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scale {
+    Celsius,
+    Fahrenheit,
+    Kelvin,
+}
+
+impl Temperature {
+    /// The lowest temperature the DS18B20 can measure (-55 °C)
+    pub const MIN: Self = Self::from_raw(-55 * 16);
+    /// The highest temperature the DS18B20 can measure (125 °C)
+    pub const MAX: Self = Self::from_raw(125 * 16);
+    /// The value the DS18B20 reports before its first conversion (85 °C)
+    pub const POWER_ON: Self = Self::from_raw(85 * 16);
+
+    /// Create a temperature from a number of 1/16 °C
+    pub const fn from_raw(raw: i16) -> Self {
+        Self { raw }
+    }
+
+    /// Create a temperature from the two temperature bytes of the scratchpad
+    pub const fn from_bytes(lsb: u8, msb: u8) -> Self {
+        Self::from_raw(i16::from_le_bytes([lsb, msb]))
+    }
+
+    /// Create a temperature from a full DS18B20 scratchpad
+    ///
+    /// The low bits which are undefined at the configured resolution are cleared.
+    pub const fn from_scratchpad(scratchpad: &[u8; 9]) -> Self {
+        // bits 6:5 of the config register are the resolution (0 = 9 bits, 3 = 12 bits)
+        let undefined_bits = 3 - ((scratchpad[4] >> 5) & 0b11);
+        let mask = !((1i16 << undefined_bits) - 1);
+
+        Self::from_raw(Self::from_bytes(scratchpad[0], scratchpad[1]).raw & mask)
+    }
+
+    /// The temperature as a number of 1/16 °C
+    pub const fn raw(self) -> i16 {
+        self.raw
+    }
+
+    /// The temperature in whole degrees Celsius, rounded towards negative infinity
+    pub const fn celsius(self) -> i16 {
+        self.raw >> 4
+    }
+
+    /// The temperature in thousandths of a degree Celsius, rounded towards zero
+    pub const fn millicelsius(self) -> i32 {
+        self.ten_thousandths(Scale::Celsius) / 10
+    }
+
+    /// The temperature in thousandths of a degree Fahrenheit, rounded towards zero
+    pub const fn millifahrenheit(self) -> i32 {
+        self.ten_thousandths(Scale::Fahrenheit) / 10
+    }
+
+    /// The temperature in thousandths of a kelvin, rounded towards zero
+    pub const fn millikelvin(self) -> i32 {
+        self.ten_thousandths(Scale::Kelvin) / 10
+    }
+
+    /// Check if the temperature is within the range the DS18B20 can measure
+    pub const fn in_range(self) -> bool {
+        Self::MIN.raw <= self.raw && self.raw <= Self::MAX.raw
+    }
+
+    /// Express the temperature in the given scale, for formatting
+    pub const fn in_scale(self, scale: Scale) -> Scaled {
+        Scaled { temp: self, scale }
+    }
+
+    /// The temperature in 1/10000 of a degree in the given scale
+    ///
+    /// Every 1/16 °C step is exactly representable in all scales at this precision.
+    const fn ten_thousandths(self, scale: Scale) -> i32 {
+        let raw = self.raw as i32;
+        match scale {
+            Scale::Celsius => raw * 625,
+            // 9/5 * 625 = 1125
+            Scale::Fahrenheit => raw * 1125 + 320_000,
+            Scale::Kelvin => raw * 625 + 2_731_500,
+        }
+    }
+}
+
+/// A temperature in a specific scale
+///
+/// Formats with four decimals by default, or rounds to the given precision (e.g. `{:.1}`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Scaled {
+    temp: Temperature,
+    scale: Scale,
+}
+
+impl fmt::Display for Scaled {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let value = self.temp.ten_thousandths(self.scale);
+        let precision = fmt.precision().unwrap_or(4).min(4) as u32;
+
+        // round half away from zero to the requested precision
+        let divisor = 10u32.pow(4 - precision);
+        let rounded = (value.unsigned_abs() + divisor / 2) / divisor;
+        let sign = if value < 0 && rounded != 0 { "-" } else { "" };
+
+        let unit = match self.scale {
+            Scale::Celsius => "°C",
+            Scale::Fahrenheit => "°F",
+            Scale::Kelvin => "K",
+        };
+
+        if precision == 0 {
+            write!(fmt, "{}{} {}", sign, rounded, unit)
+        } else {
+            let scale = 10u32.pow(precision);
+            write!(
+                fmt,
+                "{}{}.{:0width$} {}",
+                sign,
+                rounded / scale,
+                rounded % scale,
+                unit,
+                width = precision as usize
+            )
+        }
+    }
+}
+
+impl fmt::Display for Temperature {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.in_scale(Scale::Celsius), fmt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A scratchpad with the temperature and the resolution in bits
+    fn scratchpad(raw: u16, bits: u8) -> [u8; 9] {
+        let [lsb, msb] = raw.to_le_bytes();
+        let config = ((bits - 9) << 5) | 0b1_1111;
+        [lsb, msb, 0x4b, 0x46, config, 0xff, 0x0c, 0x10, 0x00]
+    }
+
+    #[test]
+    fn datasheet_table_at_12_bits() {
+        // table 1 of the DS18B20 datasheet
+        for (raw, millicelsius) in [
+            (0x07d0, 125_000),
+            (0x0550, 85_000),
+            (0x0191, 25_062),
+            (0x00a2, 10_125),
+            (0x0008, 500),
+            (0x0000, 0),
+            (0xfff8, -500),
+            (0xff5e, -10_125),
+            (0xfe6f, -25_062),
+            (0xfc90, -55_000),
+        ] {
+            let temp = Temperature::from_scratchpad(&scratchpad(raw, 12));
+            assert_eq!(temp.raw(), raw as i16);
+            assert_eq!(temp.millicelsius(), millicelsius, "{:#06x}", raw);
+            assert!(temp.in_range());
+        }
+    }
+
+    #[test]
+    fn undefined_bits_are_masked() {
+        // -10.125 °C, with the bits below the resolution set
+        let expected = [(9, -10_500), (10, -10_250), (11, -10_125), (12, -10_125)];
+        for (bits, millicelsius) in expected {
+            let temp = Temperature::from_scratchpad(&scratchpad(0xff5e, bits));
+            assert_eq!(temp.millicelsius(), millicelsius, "{} bits", bits);
+        }
+
+        let expected = [(9, 0x0190), (10, 0x0194), (11, 0x0196), (12, 0x0197)];
+        for (bits, raw) in expected {
+            let temp = Temperature::from_scratchpad(&scratchpad(0x0197, bits));
+            assert_eq!(temp.raw(), raw, "{} bits", bits);
+        }
+    }
+
+    #[test]
+    fn range() {
+        assert_eq!(Temperature::MIN.millicelsius(), -55_000);
+        assert_eq!(Temperature::MAX.millicelsius(), 125_000);
+        assert_eq!(Temperature::POWER_ON.millicelsius(), 85_000);
+        assert!(!Temperature::from_raw(-55 * 16 - 1).in_range());
+        assert!(!Temperature::from_raw(125 * 16 + 1).in_range());
+    }
+
+    #[test]
+    fn every_step_converts_exactly() {
+        for raw in Temperature::MIN.raw()..=Temperature::MAX.raw() {
+            let temp = Temperature::from_raw(raw);
+            let ten_thousandths = raw as i32 * 625;
+            assert_eq!(temp.millicelsius(), ten_thousandths / 10);
+            assert_eq!(
+                temp.millifahrenheit(),
+                (ten_thousandths * 9 / 5 + 320_000) / 10
+            );
+            assert_eq!(temp.millikelvin(), (ten_thousandths + 2_731_500) / 10);
+            assert_eq!(temp.celsius(), (raw as f32 / 16.0).floor() as i16);
+        }
+    }
+
+    #[test]
+    fn conversions() {
+        let temp = Temperature::from_raw(0x0191);
+        assert_eq!(temp.millifahrenheit(), 77_112);
+        assert_eq!(temp.millikelvin(), 298_212);
+        assert_eq!(Temperature::MIN.millifahrenheit(), -67_000);
+        assert_eq!(Temperature::MIN.millikelvin(), 218_150);
+        assert_eq!(Temperature::MAX.millifahrenheit(), 257_000);
+        assert_eq!(Temperature::from_raw(-8).celsius(), -1);
+        assert_eq!(Temperature::from_raw(-1).millicelsius(), -62);
+    }
+
+    #[test]
+    fn display_rounds_to_the_precision() {
+        let temp = Temperature::from_raw(0x0191);
+        assert_eq!(format!("{}", temp), "25.0625 °C");
+        assert_eq!(format!("{:.0}", temp), "25 °C");
+        assert_eq!(format!("{:.1}", temp), "25.1 °C");
+        assert_eq!(format!("{:.2}", temp), "25.06 °C");
+        assert_eq!(format!("{:.3}", temp), "25.063 °C");
+        assert_eq!(format!("{:.6}", temp), "25.0625 °C");
+
+        let temp = Temperature::from_raw(-0x0191);
+        assert_eq!(format!("{}", temp), "-25.0625 °C");
+        assert_eq!(format!("{:.0}", temp), "-25 °C");
+        assert_eq!(format!("{:.1}", temp), "-25.1 °C");
+        assert_eq!(format!("{:.2}", temp), "-25.06 °C");
+        assert_eq!(format!("{:.3}", temp), "-25.063 °C");
+
+        // half a step away from zero, and no sign on a rounded zero
+        assert_eq!(format!("{:.0}", Temperature::from_raw(8)), "1 °C");
+        assert_eq!(format!("{:.0}", Temperature::from_raw(-8)), "-1 °C");
+        assert_eq!(format!("{:.0}", Temperature::from_raw(-1)), "0 °C");
+        assert_eq!(format!("{:.1}", Temperature::from_raw(-1)), "-0.1 °C");
+        assert_eq!(format!("{:.2}", Temperature::from_raw(-1)), "-0.06 °C");
+    }
+
+    #[test]
+    fn display_in_other_scales() {
+        let temp = Temperature::from_raw(0x0191);
+        assert_eq!(
+            format!("{}", temp.in_scale(Scale::Fahrenheit)),
+            "77.1125 °F"
+        );
+        assert_eq!(format!("{:.1}", temp.in_scale(Scale::Kelvin)), "298.2 K");
+        assert_eq!(
+            format!("{:.2}", Temperature::MIN.in_scale(Scale::Fahrenheit)),
+            "-67.00 °F"
+        );
+        assert_eq!(
+            format!("{}", Temperature::from_raw(0).in_scale(Scale::Kelvin)),
+            "273.1500 K"
+        );
+    }
+}
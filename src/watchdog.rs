@@ -0,0 +1,244 @@
+//! A watchdog which is only fed while every task keeps up
+//!
+//! ```ignore
+//! let mut dog = IndependentWatchdog::new(dev_peripherals.IWDG);
+//! dog.stop_on_debug(&dev_peripherals.DBGMCU, true);
+//! dog.start(2000.ms());
+//!
+//! let mut supervisor = Supervisor::<_, 2>::new(dog);
+//! let numpad = supervisor.register("numpad", 500, now).context("watchdog")?;
+//! loop {
+//!     // ...
+//!     supervisor.check_in(numpad, now);
+//!     supervisor.feed(now);
+//! }
+//! ```
+
+use core::fmt;
+use embedded_hal::watchdog::Watchdog;
+use stm32f1xx_hal::pac;
+
+use crate::warn;
+
+/// Why the chip was last reset, from RCC_CSR
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResetCause {
+    /// Entering standby or stop while that is not allowed
+    LowPower,
+    /// The window watchdog
+    WindowWatchdog,
+    /// The independent watchdog, so a task got stuck
+    IndependentWatchdog,
+    /// `SCB::sys_reset`, as after a crash
+    Software,
+    /// Power on or brown out
+    PowerOn,
+    /// The reset pin, like the button or the debugger
+    Pin,
+    /// No flag was set, so they were already cleared
+    Unknown,
+}
+
+impl ResetCause {
+    /// Read the cause of the last reset, and clear it for the next one
+    pub fn take() -> Self {
+        // SAFETY: RCC_CSR is not used by the HAL, and RMVF only clears the flags
+        let rcc = unsafe { &*pac::RCC::ptr() };
+        let csr = rcc.csr.read();
+        rcc.csr.modify(|_, w| w.rmvf().set_bit());
+
+        // the pin flag is set by every reset, so it comes last
+        if csr.lpwrrstf().bit() {
+            Self::LowPower
+        } else if csr.wwdgrstf().bit() {
+            Self::WindowWatchdog
+        } else if csr.iwdgrstf().bit() {
+            Self::IndependentWatchdog
+        } else if csr.sftrstf().bit() {
+            Self::Software
+        } else if csr.porrstf().bit() {
+            Self::PowerOn
+        } else if csr.pinrstf().bit() {
+            Self::Pin
+        } else {
+            Self::Unknown
+        }
+    }
+}
+
+impl fmt::Display for ResetCause {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str(match self {
+            Self::LowPower => "low-power management",
+            Self::WindowWatchdog => "window watchdog",
+            Self::IndependentWatchdog => "independent watchdog",
+            Self::Software => "software",
+            Self::PowerOn => "power on",
+            Self::Pin => "reset pin",
+            Self::Unknown => "unknown",
+        })
+    }
+}
+
+/// A task registered with a [`Supervisor`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Task(usize);
+
+#[derive(Clone, Copy)]
+struct Entry {
+    name: &'static str,
+    deadline_ms: u32,
+    last_ms: u32,
+    late: bool,
+}
+
+/// Feeds the watchdog only while all of up to `N` tasks check in on time
+///
+/// Time is whatever millisecond count the caller keeps, and may wrap.
+pub struct Supervisor<W, const N: usize> {
+    dog: W,
+    tasks: [Option<Entry>; N],
+}
+
+impl<W: Watchdog, const N: usize> Supervisor<W, N> {
+    /// Supervise a watchdog which is already started
+    pub fn new(dog: W) -> Self {
+        Self {
+            dog,
+            tasks: [None; N],
+        }
+    }
+
+    /// Release the watchdog again
+    pub fn release(self) -> W {
+        self.dog
+    }
+
+    /// Add a task which has to check in at least every `deadline_ms`
+    ///
+    /// Returns `None` when all `N` places are taken.
+    pub fn register(&mut self, name: &'static str, deadline_ms: u32, now_ms: u32) -> Option<Task> {
+        let index = self.tasks.iter().position(Option::is_none)?;
+        self.tasks[index] = Some(Entry {
+            name,
+            deadline_ms,
+            last_ms: now_ms,
+            late: false,
+        });
+        Some(Task(index))
+    }
+
+    /// Report that the task made progress
+    pub fn check_in(&mut self, task: Task, now_ms: u32) {
+        if let Some(entry) = &mut self.tasks[task.0] {
+            entry.last_ms = now_ms;
+            entry.late = false;
+        }
+    }
+
+    /// Feed the watchdog if no task missed its deadline
+    ///
+    /// Returns the first task which is late, which is logged once.
+    pub fn feed(&mut self, now_ms: u32) -> Result<(), Task> {
+        let mut late = None;
+        for (index, entry) in self.tasks.iter_mut().enumerate() {
+            let entry = match entry {
+                Some(entry) => entry,
+                None => continue,
+            };
+
+            if now_ms.wrapping_sub(entry.last_ms) > entry.deadline_ms {
+                if !entry.late {
+                    warn!(
+                        "watchdog: {} did not check in for {} ms",
+                        entry.name, entry.deadline_ms
+                    );
+                    entry.late = true;
+                }
+                late = late.or(Some(Task(index)));
+            }
+        }
+
+        match late {
+            Some(task) => Err(task),
+            None => {
+                self.dog.feed();
+                Ok(())
+            }
+        }
+    }
+
+    /// The name the task was registered with
+    pub fn name(&self, task: Task) -> &'static str {
+        self.tasks[task.0].map_or("", |entry| entry.name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Counts how often it was fed
+    #[derive(Default)]
+    struct Dog(usize);
+
+    impl Watchdog for Dog {
+        fn feed(&mut self) {
+            self.0 += 1;
+        }
+    }
+
+    #[test]
+    fn feeds_while_every_task_checks_in() {
+        let mut supervisor = Supervisor::<_, 2>::new(Dog::default());
+        let a = supervisor.register("a", 100, 0).unwrap();
+        let b = supervisor.register("b", 300, 0).unwrap();
+        assert_eq!(supervisor.name(b), "b");
+
+        // the deadline itself is still on time
+        assert_eq!(supervisor.feed(100), Ok(()));
+        assert_eq!(supervisor.feed(101), Err(a));
+        supervisor.check_in(a, 101);
+        supervisor.check_in(a, 250);
+        assert_eq!(supervisor.feed(300), Ok(()));
+        // the first late task is returned, the others are logged too
+        assert_eq!(supervisor.feed(351), Err(a));
+        supervisor.check_in(a, 351);
+        assert_eq!(supervisor.feed(351), Err(b));
+
+        assert_eq!(supervisor.release().0, 2);
+    }
+
+    #[test]
+    fn a_late_task_stays_late_until_it_checks_in() {
+        let mut supervisor = Supervisor::<_, 1>::new(Dog::default());
+        let task = supervisor.register("task", 100, 0).unwrap();
+
+        assert_eq!(supervisor.feed(150), Err(task));
+        assert!(supervisor.tasks[0].unwrap().late);
+        assert_eq!(supervisor.feed(200), Err(task));
+        supervisor.check_in(task, 200);
+        assert!(!supervisor.tasks[0].unwrap().late);
+        assert_eq!(supervisor.feed(250), Ok(()));
+        assert_eq!(supervisor.release().0, 1);
+    }
+
+    #[test]
+    fn deadlines_across_the_wraparound() {
+        let mut supervisor = Supervisor::<_, 1>::new(Dog::default());
+        let task = supervisor.register("task", 100, u32::MAX - 49).unwrap();
+
+        assert_eq!(supervisor.feed(50), Ok(()));
+        assert_eq!(supervisor.feed(51), Err(task));
+        supervisor.check_in(task, 40);
+        assert_eq!(supervisor.feed(140), Ok(()));
+        assert_eq!(supervisor.release().0, 2);
+    }
+
+    #[test]
+    fn no_place_when_all_are_taken() {
+        let mut supervisor = Supervisor::<_, 1>::new(Dog::default());
+        supervisor.register("a", 100, 0).unwrap();
+        assert_eq!(supervisor.register("b", 100, 0), None);
+    }
+}
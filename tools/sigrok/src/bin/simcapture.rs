@@ -0,0 +1,120 @@
+//! Save what the firmware would send as sigrok sessions, from simulated pins
+//!
+//! Writes `writer_sim.sr` with the UART and Manchester of `writer`, and
+//! `matrix_sim.sr` with the matrix scrolling like `main_async`, both with a
+//! PulseView view.
+//!
+//! ```sh
+//! simcapture [directory]
+//! srdecode writer_sim.sr uart TX
+//! srdecode writer_sim.sr manchester MAN
+//! srdecode matrix_sim.sr max7219 CS CLK DIN
+//! ```
+
+use std::cell::RefCell;
+use std::error::Error;
+use std::fmt::{Debug, Write as _};
+use std::path::Path;
+
+use embedded_hal::blocking::delay::DelayMs;
+use max7219::MAX7219;
+
+use embedded_pg::asynch::matrix;
+use embedded_pg::manchester::{Coding, ManchesterTx};
+use embedded_pg::sim::trace::{Delay, Pin, Timer, Trace};
+use embedded_pg::soft_uart::{Config, SoftUartTx};
+use sigrok_decode::{export, Change};
+
+/// The sample rate of the logic analyzer
+const SAMPLERATE: u64 = 1_000_000;
+/// The most changes a simulation records
+const MAX_CHANGES: usize = 32 * 1024;
+
+/// An error of a driver without `std::error::Error`
+fn driver(e: impl Debug) -> String {
+    format!("{:?}", e)
+}
+
+/// Save the trace, and a view next to it
+fn save<const N: usize>(
+    trace: &Trace<N>,
+    directory: &Path,
+    name: &str,
+) -> Result<(), Box<dyn Error>> {
+    let sample = |time_ns: u64| time_ns * SAMPLERATE / 1_000_000_000;
+    let changes: Vec<Change> = trace
+        .changes()
+        .iter()
+        .map(|change| Change {
+            sample: sample(change.time_ns),
+            levels: change.levels as u64,
+        })
+        .collect();
+
+    let session = directory.join(format!("{}.sr", name));
+    export::write_session(
+        &session,
+        SAMPLERATE,
+        trace.names(),
+        &changes,
+        sample(trace.now_ns()),
+    )?;
+    export::write_view(directory.join(format!("{}.pvs", name)), trace.names())?;
+    println!("{}", session.display());
+    Ok(())
+}
+
+/// `writer`: "Hello World!" at 9600 8N1 and 4800 bit/s Manchester, twice
+/// with a second in between
+fn writer(directory: &Path) -> Result<(), Box<dyn Error>> {
+    // a write to a pin takes about a microsecond at 72 MHz
+    let trace = RefCell::new(Trace::<MAX_CHANGES>::new(&["TX", "MAN"], 1_000));
+    let config = Config::default();
+    let mut tx = SoftUartTx::new(Pin::new(&trace, 0), Timer::new(&trace), config)?;
+    let mut manchester = ManchesterTx::new(
+        Pin::new(&trace, 1),
+        Timer::new(&trace),
+        Coding::Manchester,
+        4800,
+    )?;
+    let mut delay = Delay::new(&trace);
+
+    for _ in 0..2 {
+        let message = "Hello World!\r\n";
+        tx.write_str(message)?;
+        manchester.send(message.as_bytes())?;
+        delay.delay_ms(1000u32);
+    }
+    let trace = trace.borrow();
+    save(&trace, directory, "writer_sim")
+}
+
+/// `main_async`: "Feroxide! " scrolling over the matrix, a row every 150 ms
+fn matrix(directory: &Path) -> Result<(), Box<dyn Error>> {
+    let trace = RefCell::new(Trace::<MAX_CHANGES>::new(&["DIN", "CS", "CLK"], 1_000));
+    let [din, cs, clk] = [0, 1, 2].map(|channel| Pin::new(&trace, channel));
+    // CS idles high, which the connector leaves to the board
+    trace.borrow_mut().set(1, true);
+    let mut display = MAX7219::from_pins(1, din, cs, clk).map_err(driver)?;
+    let mut delay = Delay::new(&trace);
+    display.power_on().map_err(driver)?;
+
+    let text = b"Feroxide! ";
+    for position in 0..text.len() * 4 {
+        display
+            .write_raw(0, &matrix::frame(text, position))
+            .map_err(driver)?;
+        delay.delay_ms(150u32);
+    }
+    let trace = trace.borrow();
+    save(&trace, directory, "matrix_sim")
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let directory = std::env::args().nth(1).unwrap_or_else(|| ".".to_string());
+    let directory = Path::new(&directory);
+
+    writer(directory)?;
+    matrix(directory)?;
+    Ok(())
+}
@@ -0,0 +1,101 @@
+//! Writing sigrok session files, like the ones PulseView saves
+//!
+//! The session holds one sample a byte, so at most 8 channels, in chunks as
+//! big as the ones in `pulseview/`. The view file names and enables the
+//! channels, which PulseView reads when it is next to the session.
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::session::{Change, Error};
+
+/// The bytes in every chunk of samples
+const CHUNK: usize = 10 * 1024 * 1024;
+
+/// The colours PulseView gives the first 8 channels
+const COLORS: [u32; 8] = [
+    4279638298, 4287582722, 4291559424, 4294277376, 4293776384, 4285780502, 4281623972, 4285878395,
+];
+
+/// Write the changes of the probes as a session of `samples` samples
+pub fn write_session(
+    path: impl AsRef<Path>,
+    samplerate: u64,
+    probes: &[&str],
+    changes: &[Change],
+    samples: u64,
+) -> Result<(), Error> {
+    assert!(probes.len() <= 8, "at most 8 probes");
+    let mut zip = ZipWriter::new(File::create(path)?);
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    zip.start_file("version", options)?;
+    zip.write_all(b"2")?;
+
+    zip.start_file("metadata", options)?;
+    writeln!(zip, "[global]\nsigrok version=0.5.2\n")?;
+    writeln!(zip, "[device 1]\ncapturefile=logic-1")?;
+    writeln!(zip, "total probes={}", probes.len())?;
+    writeln!(
+        zip,
+        "samplerate={}\ntotal analog=0",
+        format_rate(samplerate)
+    )?;
+    for (i, probe) in probes.iter().enumerate() {
+        writeln!(zip, "probe{}={}", i + 1, probe)?;
+    }
+    writeln!(zip, "unitsize=1")?;
+
+    // every sample holds the levels of the last change before or at it
+    let mut next = 0;
+    let mut levels = 0;
+    let mut chunk = Vec::with_capacity(CHUNK);
+    for sample in 0..samples {
+        while next < changes.len() && changes[next].sample <= sample {
+            levels = changes[next].levels as u8;
+            next += 1;
+        }
+        chunk.push(levels);
+
+        if chunk.len() == CHUNK || sample + 1 == samples {
+            zip.start_file(format!("logic-1-{}", sample as usize / CHUNK + 1), options)?;
+            zip.write_all(&chunk)?;
+            chunk.clear();
+        }
+    }
+
+    zip.finish()?;
+    Ok(())
+}
+
+/// Write a PulseView view of the probes, showing all of them
+pub fn write_view(path: impl AsRef<Path>, probes: &[&str]) -> io::Result<()> {
+    let mut view = File::create(path)?;
+    writeln!(view, "[General]\ndecode_signals=0\nmeta_objs=0\nviews=1")?;
+    for (i, probe) in probes.iter().enumerate() {
+        writeln!(view, "\n[D{}]", i)?;
+        writeln!(view, "color={}", COLORS[i % COLORS.len()])?;
+        writeln!(view, "conv_options=0\nconversion_type=0\nenabled=true")?;
+        writeln!(view, "name={}", probe)?;
+    }
+
+    writeln!(view, "\n[view0]")?;
+    for i in 0..probes.len() {
+        writeln!(view, "D{}\\trace_height=38", i)?;
+    }
+    Ok(())
+}
+
+/// A rate like "24 MHz"
+fn format_rate(rate: u64) -> String {
+    match rate {
+        rate if rate % 1_000_000_000 == 0 => format!("{} GHz", rate / 1_000_000_000),
+        rate if rate % 1_000_000 == 0 => format!("{} MHz", rate / 1_000_000),
+        rate if rate % 1_000 == 0 => format!("{} kHz", rate / 1_000),
+        rate => format!("{} Hz", rate),
+    }
+}
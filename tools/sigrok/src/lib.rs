@@ -0,0 +1,10 @@
+//! Decoding sigrok captures of the firmware, to check its output on the host
+
+pub mod export;
+pub mod manchester;
+pub mod max7219;
+pub mod session;
+pub mod uart;
+pub mod writer;
+
+pub use session::{Change, Error, Session};
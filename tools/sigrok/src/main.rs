@@ -0,0 +1,127 @@
+//! Print what the firmware sent in a sigrok capture
+//!
+//! ```sh
+//! srdecode pulseview/hello_world.sr writer
+//! srdecode pulseview/led_matrix.sr max7219 cs clk do
+//! srdecode writer_sim.sr manchester MAN
+//! ```
+
+use std::error::Error;
+use std::process::ExitCode;
+
+use sigrok_decode::manchester::{self, Coding};
+use sigrok_decode::{max7219, uart, writer, Change, Session};
+
+const USAGE: &str = "\
+usage: srdecode <capture.sr> <decoder> [channels...]
+
+decoders:
+  info                    the sample rate, length and probes
+  writer [data]           the protocol writer used to send, on D0
+  uart [rx] [baud]        8N1 words, on D0 at 9600 baud
+  manchester [data] [differential]
+                          Manchester frames, on D0
+  max7219 [load clk din]  the commands to the matrix, on cs, clk and do
+
+A channel is the name of a probe or its index.";
+
+fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let (path, decoder, options) = match args {
+        [path, decoder, options @ ..] => (path, decoder.as_str(), options),
+        _ => return Err(USAGE.into()),
+    };
+    let mut session = Session::open(path)?;
+    // the channels by their options, or by their defaults
+    let mut channels = |defaults: &[&str]| -> Result<Vec<Change>, Box<dyn Error>> {
+        let names = defaults
+            .iter()
+            .enumerate()
+            .map(|(i, default)| options.get(i).map_or(*default, String::as_str));
+        let channels = names
+            .map(|name| session.channel(name))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(session.changes(&channels)?)
+    };
+
+    match decoder {
+        "info" => {
+            let samples = session.len()?;
+            println!("{} Hz, {} samples", session.samplerate, samples);
+            println!("{:.3} s", session.seconds(samples));
+            for (channel, probe) in session.probes.iter().enumerate() {
+                println!("{}: {}", channel, probe);
+            }
+        }
+        "writer" => {
+            let changes = channels(&["D0"])?;
+            for message in writer::decode(&changes) {
+                println!(
+                    "{:.6} s: {:?} ({:.1} ms a bit)",
+                    session.seconds(message.start),
+                    String::from_utf8_lossy(&message.bytes),
+                    session.seconds(message.period as u64) * 1000.0
+                );
+            }
+        }
+        "uart" => {
+            let changes = channels(&["D0"])?;
+            let baud = options.get(1).map_or(Ok(9600), |baud| baud.parse())?;
+            for word in uart::decode(&changes, session.samplerate, baud) {
+                let framing = if word.framed { "" } else { " (framing error)" };
+                println!(
+                    "{:.6} s: {:#04x} {:?}{}",
+                    session.seconds(word.sample),
+                    word.value,
+                    word.value as char,
+                    framing
+                );
+            }
+        }
+        "manchester" => {
+            let changes = channels(&["D0"])?;
+            let coding = match options.get(1).map(String::as_str) {
+                None => Coding::Manchester,
+                Some("differential") => Coding::Differential,
+                Some(coding) => return Err(format!("unknown coding {}\n\n{}", coding, USAGE).into()),
+            };
+            for frame in manchester::decode(&changes, coding) {
+                let complete = if frame.complete { "" } else { " (incomplete)" };
+                println!(
+                    "{:.6} s: {:?} ({:.3} ms a bit){}",
+                    session.seconds(frame.sample),
+                    String::from_utf8_lossy(&frame.bytes),
+                    session.seconds(frame.period as u64) * 1000.0,
+                    complete
+                );
+            }
+        }
+        "max7219" => {
+            let changes = channels(&["cs", "clk", "do"])?;
+            for latch in max7219::decode(&changes) {
+                let commands: Vec<String> = latch.commands().map(|c| c.to_string()).collect();
+                print!(
+                    "{:.6} s: {}",
+                    session.seconds(latch.sample),
+                    commands.join(", ")
+                );
+                if latch.leftover > 0 {
+                    print!(" ({} bits left over)", latch.leftover);
+                }
+                println!();
+            }
+        }
+        _ => return Err(USAGE.into()),
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
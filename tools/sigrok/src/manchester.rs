@@ -0,0 +1,139 @@
+//! Manchester frames, with the decoder of the firmware
+//!
+//! The decoder learns the bit rate from every preamble, so it needs none.
+
+use embedded_pg::manchester::{Decoder, Event};
+
+pub use embedded_pg::manchester::Coding;
+
+use crate::session::Change;
+
+/// A received frame
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    /// The sample of the delimiter
+    pub sample: u64,
+    /// The length of a bit at the end, in samples
+    pub period: u32,
+    pub bytes: Vec<u8>,
+    /// Whether it ended on a byte, without an edge out of time
+    pub complete: bool,
+}
+
+/// Decode every frame on the channel, from its changes
+pub fn decode(changes: &[Change], coding: Coding) -> Vec<Frame> {
+    let mut decoder = Decoder::new(coding);
+    let mut frames: Vec<Frame> = Vec::new();
+
+    // the changes of a single channel are its edges, after its initial level
+    for change in changes.iter().skip(1) {
+        let event = decoder.edge(change.sample as u32, change.level(0));
+        match (event, frames.last_mut()) {
+            (Some(Event::Start), _) => frames.push(Frame {
+                sample: change.sample,
+                period: 0,
+                bytes: Vec::new(),
+                complete: true,
+            }),
+            (Some(Event::Byte(byte)), Some(frame)) => frame.bytes.push(byte),
+            (Some(Event::Timing), Some(frame)) => frame.complete = false,
+            _ => {}
+        }
+        if let (true, Some(frame)) = (decoder.in_frame(), frames.last_mut()) {
+            frame.period = decoder.period();
+        }
+    }
+
+    // the line may stop before the frame ends
+    if let (Some(Event::Timing), Some(frame)) = (decoder.idle(), frames.last_mut()) {
+        frame.complete = false;
+    }
+    frames
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_pg::manchester::frame;
+
+    /// The changes of a line sending the frames, a half bit lasting `half`
+    /// samples and growing by `drift` of it over every frame
+    fn line(coding: Coding, messages: &[&[u8]], half: f64, drift: f64) -> Vec<Change> {
+        let mut changes = vec![Change {
+            sample: 0,
+            levels: 0,
+        }];
+        let mut time: f64 = 1000.0;
+        for message in messages {
+            let levels: Vec<bool> = frame(coding, message).collect();
+            let step = half * drift / levels.len() as f64;
+            for (i, level) in levels.iter().enumerate() {
+                if *level != changes.last().unwrap().level(0) {
+                    changes.push(Change {
+                        sample: time.round() as u64,
+                        levels: *level as u64,
+                    });
+                }
+                time += half + step * i as f64;
+            }
+            time += half * 40.0;
+        }
+        changes
+    }
+
+    #[test]
+    fn frames_with_drift() {
+        let messages: [&[u8]; 2] = [b"Hello World!\r\n", b"\x00\xff"];
+        for coding in [Coding::Manchester, Coding::Differential] {
+            for drift in [-0.04, 0.0, 0.04] {
+                let frames = decode(&line(coding, &messages, 10.0, drift), coding);
+                let bytes: Vec<&[u8]> = frames.iter().map(|f| f.bytes.as_slice()).collect();
+                assert_eq!(bytes, messages, "{:?}, {}", coding, drift);
+                assert!(frames.iter().all(|f| f.complete));
+
+                // the bit at the end of the frame, after the drift
+                let period = 20.0 * (1.0 + drift);
+                assert!(
+                    (frames[0].period as f64 - period).abs() <= 1.5,
+                    "{:?}",
+                    frames[0]
+                );
+                // the delimiter comes after the preamble
+                assert!(frames[0].sample > 1000 + 16 * 20 - 40, "{:?}", frames[0]);
+            }
+        }
+    }
+
+    #[test]
+    fn glitched_and_cut_frames_are_incomplete() {
+        for coding in [Coding::Manchester, Coding::Differential] {
+            let mut changes = line(coding, &[b"glitch"], 10.0, 0.0);
+            // a short pulse in the middle of the data
+            let at = changes.len() - 12;
+            let Change { sample, levels } = changes[at];
+            changes.insert(
+                at + 1,
+                Change {
+                    sample: sample + 3,
+                    levels: levels ^ 1,
+                },
+            );
+            changes.insert(
+                at + 2,
+                Change {
+                    sample: sample + 5,
+                    levels,
+                },
+            );
+            let frames = decode(&changes, coding);
+            assert_eq!(frames.len(), 1);
+            assert!(!frames[0].complete, "{:?}", frames[0]);
+
+            let changes = line(coding, &[b"cut"], 10.0, 0.0);
+            let frames = decode(&changes[..changes.len() - 5], coding);
+            assert_eq!(frames.len(), 1);
+            assert!(!frames[0].complete, "{:?}", frames[0]);
+            assert_eq!(frames[0].bytes, b"cu");
+        }
+    }
+}
@@ -0,0 +1,126 @@
+//! The 3-wire stream of the MAX7219 LED driver, as in `led_matrix.sr`
+//!
+//! DIN is shifted in MSB first on every rising edge of CLK, and latched on
+//! the rising edge of LOAD (CS). Every driver takes 16 bits, so with drivers
+//! daisy-chained a latch holds a word for each, the first for the last one.
+
+use std::fmt;
+
+use crate::session::Change;
+
+/// The order of the channels in the changes
+pub const LOAD: usize = 0;
+pub const CLK: usize = 1;
+pub const DIN: usize = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    NoOp,
+    /// A row of the matrix, from 0
+    Digit(u8),
+    DecodeMode,
+    Intensity,
+    ScanLimit,
+    Shutdown,
+    DisplayTest,
+    Unknown(u8),
+}
+
+/// A word sent to a driver
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Command {
+    pub register: Register,
+    pub data: u8,
+}
+
+impl From<u16> for Command {
+    fn from(word: u16) -> Self {
+        let register = match (word >> 8) as u8 & 0x0f {
+            0x0 => Register::NoOp,
+            digit @ 0x1..=0x8 => Register::Digit(digit - 1),
+            0x9 => Register::DecodeMode,
+            0xa => Register::Intensity,
+            0xb => Register::ScanLimit,
+            0xc => Register::Shutdown,
+            0xf => Register::DisplayTest,
+            address => Register::Unknown(address),
+        };
+        Self {
+            register,
+            data: word as u8,
+        }
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let data = self.data;
+        match self.register {
+            Register::NoOp => write!(fmt, "no-op"),
+            Register::Digit(digit) => write!(fmt, "digit {} = {:08b}", digit, data),
+            Register::DecodeMode => write!(fmt, "decode mode {:#04x}", data),
+            Register::Intensity => write!(fmt, "intensity {}/32", (data & 0x0f) * 2 + 1),
+            Register::ScanLimit => write!(fmt, "scan limit {} digits", (data & 0x07) + 1),
+            Register::Shutdown if data & 1 == 0 => write!(fmt, "shutdown"),
+            Register::Shutdown => write!(fmt, "normal operation"),
+            Register::DisplayTest if data & 1 == 0 => write!(fmt, "display test off"),
+            Register::DisplayTest => write!(fmt, "display test on"),
+            Register::Unknown(address) => write!(fmt, "register {:#x} = {:#04x}", address, data),
+        }
+    }
+}
+
+/// The bits shifted in while LOAD was low
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Latch {
+    /// The sample where LOAD went high
+    pub sample: u64,
+    /// The last bits in words of 16, in the order they were sent
+    pub words: Vec<u16>,
+    /// How many bits came before the first whole word, and were shifted out
+    pub leftover: u8,
+}
+
+impl Latch {
+    pub fn commands(&self) -> impl Iterator<Item = Command> + '_ {
+        self.words.iter().map(|&word| Command::from(word))
+    }
+}
+
+/// Decode every latch, from the changes of LOAD, CLK and DIN in that order
+pub fn decode(changes: &[Change]) -> Vec<Latch> {
+    let mut latches = Vec::new();
+    let mut bits: Vec<bool> = Vec::new();
+    let mut shifting = false;
+
+    for pair in changes.windows(2) {
+        let (before, after) = (pair[0], pair[1]);
+
+        if after.level(CLK) && !before.level(CLK) && shifting {
+            // DIN was set before the clock rose
+            bits.push(before.level(DIN));
+        }
+
+        match (before.level(LOAD), after.level(LOAD)) {
+            (true, false) => {
+                shifting = true;
+                bits.clear();
+            }
+            (false, true) if shifting => {
+                shifting = false;
+                let leftover = bits.len() % 16;
+                let words = bits[leftover..]
+                    .chunks_exact(16)
+                    .map(|word| word.iter().fold(0, |word, &bit| word << 1 | bit as u16))
+                    .collect();
+                latches.push(Latch {
+                    sample: after.sample,
+                    words,
+                    leftover: leftover as u8,
+                });
+            }
+            _ => {}
+        }
+    }
+    latches
+}
@@ -0,0 +1,233 @@
+//! Reading sigrok session files
+//!
+//! A `.sr` file is a zip with a `metadata` file describing the capture, and
+//! the samples in chunks called `logic-1-1`, `logic-1-2`, ... Every sample is
+//! `unitsize` bytes, little endian, with probe 1 in bit 0.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use zip::result::ZipError;
+use zip::ZipArchive;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Zip(ZipError),
+    /// The metadata is missing something or doesn't make sense
+    Metadata(String),
+    /// There is no probe by that name
+    UnknownChannel(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(fmt, "{}", e),
+            Self::Zip(e) => write!(fmt, "{}", e),
+            Self::Metadata(e) => write!(fmt, "metadata: {}", e),
+            Self::UnknownChannel(name) => write!(fmt, "no channel {:?}", name),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(item: io::Error) -> Self {
+        Self::Io(item)
+    }
+}
+
+impl From<ZipError> for Error {
+    fn from(item: ZipError) -> Self {
+        Self::Zip(item)
+    }
+}
+
+/// The levels of some channels from a sample on, until the next change
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Change {
+    pub sample: u64,
+    /// The level of the n-th requested channel in bit n
+    pub levels: u64,
+}
+
+impl Change {
+    pub fn level(&self, index: usize) -> bool {
+        self.levels & (1 << index) != 0
+    }
+}
+
+/// The level of a channel at a sample, from the changes of its channels
+pub fn level_at(changes: &[Change], index: usize, sample: u64) -> bool {
+    match changes.partition_point(|change| change.sample <= sample) {
+        0 => false,
+        after => changes[after - 1].level(index),
+    }
+}
+
+/// An opened capture
+pub struct Session {
+    archive: ZipArchive<File>,
+    capturefile: String,
+    unitsize: usize,
+    /// Samples per second
+    pub samplerate: u64,
+    /// The names of the probes, by channel
+    pub probes: Vec<String>,
+}
+
+impl Session {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let mut archive = ZipArchive::new(File::open(path)?)?;
+        let mut metadata = String::new();
+        archive.by_name("metadata")?.read_to_string(&mut metadata)?;
+
+        let mut capturefile = None;
+        let mut unitsize = 1;
+        let mut samplerate = None;
+        let mut probes = Vec::new();
+        // only the first device is read, which is all PulseView saves
+        for line in metadata
+            .lines()
+            .skip_while(|line| *line != "[device 1]")
+            .skip(1)
+            .take_while(|line| !line.starts_with('['))
+        {
+            let (key, value) = match line.split_once('=') {
+                Some(pair) => pair,
+                None => continue,
+            };
+            match key {
+                "capturefile" => capturefile = Some(value.to_string()),
+                "unitsize" => unitsize = parse(key, value)?,
+                "samplerate" => samplerate = Some(parse_rate(value)?),
+                _ => {
+                    if let Some(probe) = key.strip_prefix("probe") {
+                        let index: usize = parse(key, probe)?;
+                        if index == 0 || index > 64 {
+                            return Err(Error::Metadata(format!("probe {}", index)));
+                        }
+                        if probes.len() < index {
+                            probes.resize(index, String::new());
+                        }
+                        probes[index - 1] = value.to_string();
+                    }
+                }
+            }
+        }
+
+        if !(1..=8).contains(&unitsize) {
+            return Err(Error::Metadata(format!("unitsize {}", unitsize)));
+        }
+        Ok(Self {
+            archive,
+            capturefile: capturefile.ok_or_else(|| missing("capturefile"))?,
+            unitsize,
+            samplerate: samplerate.ok_or_else(|| missing("samplerate"))?,
+            probes,
+        })
+    }
+
+    /// Find a channel by the name of its probe, or by its index
+    pub fn channel(&self, name: &str) -> Result<usize, Error> {
+        self.probes
+            .iter()
+            .position(|probe| probe == name)
+            .or_else(|| name.parse().ok().filter(|&i| i < self.probes.len()))
+            .ok_or_else(|| Error::UnknownChannel(name.to_string()))
+    }
+
+    /// The sample in seconds
+    pub fn seconds(&self, sample: u64) -> f64 {
+        sample as f64 / self.samplerate as f64
+    }
+
+    /// The names of the chunks of samples, in order
+    fn chunks(&self) -> Vec<String> {
+        // older captures have a single file without a number
+        if self.archive.index_for_name(&self.capturefile).is_some() {
+            return vec![self.capturefile.clone()];
+        }
+        (1..)
+            .map(|n| format!("{}-{}", self.capturefile, n))
+            .take_while(|name| self.archive.index_for_name(name).is_some())
+            .collect()
+    }
+
+    /// The number of samples in the capture
+    pub fn len(&mut self) -> Result<u64, Error> {
+        let mut bytes = 0;
+        for name in self.chunks() {
+            bytes += self.archive.by_name(&name)?.size();
+        }
+        Ok(bytes / self.unitsize as u64)
+    }
+
+    pub fn is_empty(&mut self) -> Result<bool, Error> {
+        Ok(self.len()? == 0)
+    }
+
+    /// Every change in the levels of the channels, starting with their levels at the first sample
+    pub fn changes(&mut self, channels: &[usize]) -> Result<Vec<Change>, Error> {
+        assert!(channels.len() <= 64, "at most 64 channels");
+        let mut changes: Vec<Change> = Vec::new();
+        let mut sample = 0;
+        let mut buffer = Vec::new();
+        // a sample may be split over two chunks
+        let mut partial = Vec::new();
+
+        for name in self.chunks() {
+            buffer.clear();
+            buffer.append(&mut partial);
+            self.archive.by_name(&name)?.read_to_end(&mut buffer)?;
+
+            let whole = buffer.len() - buffer.len() % self.unitsize;
+            partial.extend_from_slice(&buffer[whole..]);
+            for unit in buffer[..whole].chunks_exact(self.unitsize) {
+                let mut bytes = [0; 8];
+                bytes[..self.unitsize].copy_from_slice(unit);
+                let probes = u64::from_le_bytes(bytes);
+
+                let levels = channels
+                    .iter()
+                    .enumerate()
+                    .fold(0, |levels, (i, &channel)| {
+                        levels | ((probes >> channel) & 1) << i
+                    });
+                if changes.last().is_none_or(|last| last.levels != levels) {
+                    changes.push(Change { sample, levels });
+                }
+                sample += 1;
+            }
+        }
+        Ok(changes)
+    }
+}
+
+fn missing(key: &str) -> Error {
+    Error::Metadata(format!("no {}", key))
+}
+
+fn parse<T: std::str::FromStr>(key: &str, value: &str) -> Result<T, Error> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| Error::Metadata(format!("{} {:?}", key, value)))
+}
+
+/// A rate like "24 MHz"
+fn parse_rate(value: &str) -> Result<u64, Error> {
+    let (number, unit) = value.split_once(' ').unwrap_or((value, "Hz"));
+    let multiplier = match unit {
+        "Hz" => 1,
+        "kHz" => 1_000,
+        "MHz" => 1_000_000,
+        "GHz" => 1_000_000_000,
+        _ => return Err(Error::Metadata(format!("samplerate {:?}", value))),
+    };
+    Ok(parse::<u64>("samplerate", number)? * multiplier)
+}
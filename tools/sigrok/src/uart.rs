@@ -0,0 +1,44 @@
+//! 8N1 UART words, which `writer` sends now
+//!
+//! Every word starts on a falling edge, after which the bits are sampled in
+//! their middle, LSB first.
+
+use crate::session::{level_at, Change};
+
+/// A received word
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Word {
+    /// The sample where the start bit begins
+    pub sample: u64,
+    pub value: u8,
+    /// Whether the stop bit was high
+    pub framed: bool,
+}
+
+/// Decode every word on the channel, from its changes
+pub fn decode(changes: &[Change], samplerate: u64, baud: u32) -> Vec<Word> {
+    let bit = samplerate as f64 / baud as f64;
+    let level = |start: u64, bits: f64| level_at(changes, 0, start + (bits * bit) as u64);
+    let mut words = Vec::new();
+    // the line may only fall after the middle of the last stop bit
+    let mut idle_from = 0;
+
+    for pair in changes.windows(2) {
+        let start = pair[1].sample;
+        let falling = pair[0].level(0) && !pair[1].level(0);
+        if !falling || start < idle_from {
+            continue;
+        }
+
+        let value = (0..8).fold(0, |value, i| {
+            value | (level(start, 1.5 + i as f64) as u8) << i
+        });
+        words.push(Word {
+            sample: start,
+            value,
+            framed: level(start, 9.5),
+        });
+        idle_from = start + (9.5 * bit) as u64;
+    }
+    words
+}
@@ -0,0 +1,119 @@
+//! The protocol `writer` used before it sent a UART, as in `hello_world.sr`
+//!
+//! Every message starts with the line low for 32 periods of the timer, and 4
+//! clock pulses of a period high and a period low. Then every byte follows
+//! MSB first, a period a bit, with a low period after it.
+//!
+//! The firmware printed every byte over semihosting before sending it, which
+//! takes a few periods. The bits set right after the print hold only until
+//! the next tick, and bit 7 does not show at all, as the timer already
+//! expired during the print. Only printable ASCII was sent, so a byte starts
+//! with bit 6 when it rises off the ticks, and with bit 5 when it rises on a
+//! tick, because bit 6 is clear. A print which ends within 2% of a tick
+//! looks like the latter.
+
+use crate::session::{level_at, Change};
+
+/// How many periods the line must have been low before the clock pulses
+const MIN_WAIT: f64 = 16.0;
+/// How far an edge may be from a tick, in periods, to be on the tick
+const TOLERANCE: f64 = 0.02;
+
+/// A decoded message
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    /// The sample where the clock pulses start
+    pub start: u64,
+    /// The period of the clock, in samples
+    pub period: f64,
+    pub bytes: Vec<u8>,
+}
+
+/// Decode every message on the channel, from its changes
+pub fn decode(changes: &[Change]) -> Vec<Message> {
+    // the changes of a single channel are its edges, after its initial level
+    let edges: Vec<u64> = changes.iter().skip(1).map(|change| change.sample).collect();
+    let rising = |i: usize| level_at(changes, 0, edges[i]);
+    let level = |sample: f64| level_at(changes, 0, sample as u64);
+    let mut messages = Vec::new();
+
+    let mut i = 0;
+    while i + 8 <= edges.len() {
+        let period = match clock(&edges, i) {
+            Some(period) if rising(i) => period,
+            _ => {
+                i += 1;
+                continue;
+            }
+        };
+        let start = edges[i];
+
+        // the last clock pulse ends on a tick, just like every bit after it
+        let mut tick = edges[i + 7] as f64;
+        let mut bytes = Vec::new();
+        i += 8;
+
+        while i < edges.len() && rising(i) {
+            let rise = edges[i] as f64;
+            let phase = (rise - tick) / period;
+            // a long wait is the start of the next message
+            if phase >= MIN_WAIT {
+                break;
+            }
+
+            let mut byte = 0;
+            if (phase - phase.round()).abs() <= TOLERANCE {
+                tick += phase.round() * period;
+            } else {
+                tick += phase.ceil() * period;
+                byte |= (level((rise + tick) / 2.0) as u8) << 6;
+            }
+            for bit in 0..6 {
+                let middle = tick + (bit as f64 + 0.5) * period;
+                byte |= (level(middle) as u8) << (5 - bit);
+            }
+            bytes.push(byte);
+
+            // follow the ticks of the firmware through the edges of the byte
+            let last = tick + 6.0 * period;
+            i += 1;
+            while i < edges.len() && (edges[i] as f64) < last + period / 2.0 {
+                let phase = (edges[i] as f64 - tick) / period;
+                if (phase - phase.round()).abs() <= TOLERANCE {
+                    tick += phase.round() * period;
+                }
+                i += 1;
+            }
+            // the separator starts at the end of the last bit
+            tick += ((last - tick) / period).round() * period;
+        }
+
+        messages.push(Message {
+            start,
+            period,
+            bytes,
+        });
+    }
+    messages
+}
+
+/// The period of the clock pulses starting at edge `i`, if they are
+fn clock(edges: &[u64], i: usize) -> Option<f64> {
+    let pulses = &edges[i..i + 8];
+    // the first pulse may be cut short by the print before it
+    let period = (pulses[7] - pulses[1]) as f64 / 6.0;
+    let regular = pulses[1..].windows(2).all(|pair| {
+        let length = (pair[1] - pair[0]) as f64;
+        (length - period).abs() <= period * TOLERANCE
+    });
+    let first = (pulses[1] - pulses[0]) as f64;
+    if !regular || period < 2.0 || first > period * (1.0 + TOLERANCE) {
+        return None;
+    }
+
+    // the wait before it, unless the capture started during it
+    if i > 0 && ((pulses[0] - edges[i - 1]) as f64) < period * MIN_WAIT {
+        return None;
+    }
+    Some(period)
+}
@@ -0,0 +1,146 @@
+//! The decoders against the captures in `pulseview`, and against what
+//! `simcapture` records of the drivers
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use embedded_pg::asynch::matrix;
+use sigrok_decode::max7219::{self, Latch, Register};
+use sigrok_decode::{manchester, uart, writer, Change, Session};
+
+fn open(path: impl AsRef<Path>) -> Session {
+    let path = path.as_ref();
+    Session::open(path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e))
+}
+
+fn capture(name: &str) -> Session {
+    open(
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("../../pulseview")
+            .join(name),
+    )
+}
+
+fn changes(session: &mut Session, names: &[&str]) -> Vec<Change> {
+    let channels: Vec<usize> = names
+        .iter()
+        .map(|name| session.channel(name).unwrap())
+        .collect();
+    session.changes(&channels).unwrap()
+}
+
+/// The rows of the matrix after every full update, from the commands
+fn frames(latches: &[Latch]) -> Vec<[u8; 8]> {
+    let mut rows = [0; 8];
+    let mut frames = Vec::new();
+    for command in latches.iter().flat_map(Latch::commands) {
+        if let Register::Digit(digit) = command.register {
+            rows[digit as usize] = command.data;
+            if digit == 7 {
+                frames.push(rows);
+            }
+        }
+    }
+    frames
+}
+
+#[test]
+fn hello_world() {
+    let mut session = capture("hello_world.sr");
+    let changes = changes(&mut session, &["D0"]);
+
+    let messages = writer::decode(&changes);
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].bytes, b"Hello World!");
+    assert!((session.seconds(messages[0].start) - 2.637).abs() < 0.001);
+    // a tick of 100 ms, at 1 MHz
+    assert!(
+        (messages[0].period - 100_000.0).abs() < 1_000.0,
+        "{}",
+        messages[0].period
+    );
+}
+
+#[test]
+fn led_matrix() {
+    let mut session = capture("led_matrix.sr");
+    let latches = max7219::decode(&changes(&mut session, &["cs", "clk", "do"]));
+
+    assert_eq!(latches.len(), 510);
+    assert!(latches
+        .iter()
+        .all(|latch| latch.leftover == 0 && latch.words.len() == 1));
+
+    // every update sets the decode mode around the rows
+    let registers: Vec<Register> = latches
+        .iter()
+        .flat_map(Latch::commands)
+        .map(|command| command.register)
+        .collect();
+    for update in registers.chunks(10) {
+        let rows: Vec<Register> = (0..8).map(Register::Digit).collect();
+        assert_eq!(update[0], Register::DecodeMode);
+        assert_eq!(update[1..9], rows[..]);
+        assert_eq!(update[9], Register::DecodeMode);
+    }
+
+    let shown = frames(&latches);
+    assert_eq!(shown.len(), 51);
+    assert_eq!(
+        shown[..4],
+        [
+            [0x00, 0xfd, 0x00, 0x00, 0xfe, 0x09, 0x09, 0x06],
+            [0x00, 0x00, 0x00, 0x00, 0x00, 0xfd, 0x00, 0x00],
+            [0x00; 8],
+            [0x07, 0x08, 0x08, 0xff, 0x00, 0x00, 0x00, 0x00],
+        ]
+    );
+}
+
+/// Run `simcapture` into a directory of its own
+fn simcapture() -> PathBuf {
+    let directory = std::env::temp_dir().join(format!("simcapture-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    let status = Command::new(env!("CARGO_BIN_EXE_simcapture"))
+        .arg(&directory)
+        .output()
+        .unwrap();
+    assert!(status.status.success(), "{:?}", status);
+    directory
+}
+
+#[test]
+fn simcapture_round_trip() {
+    let directory = simcapture();
+    let message = b"Hello World!\r\n";
+
+    let mut session = open(directory.join("writer_sim.sr"));
+    let words = uart::decode(&changes(&mut session, &["TX"]), session.samplerate, 9600);
+    assert!(words.iter().all(|word| word.framed));
+    let bytes: Vec<u8> = words.iter().map(|word| word.value).collect();
+    assert_eq!(bytes, [&message[..], &message[..]].concat());
+
+    let received = manchester::decode(
+        &changes(&mut session, &["MAN"]),
+        manchester::Coding::Manchester,
+    );
+    assert_eq!(received.len(), 2);
+    for frame in received {
+        assert!(frame.complete);
+        assert_eq!(frame.bytes, message);
+        // 4800 bit/s at 1 MHz
+        assert!((207..=210).contains(&frame.period), "{}", frame.period);
+    }
+
+    let mut session = open(directory.join("matrix_sim.sr"));
+    let latches = max7219::decode(&changes(&mut session, &["CS", "CLK", "DIN"]));
+    assert!(latches.iter().all(|latch| latch.leftover == 0));
+    let text = b"Feroxide! ";
+    // powering on clears the matrix first
+    let expected: Vec<[u8; 8]> = std::iter::once([0; 8])
+        .chain((0..text.len() * 4).map(|position| matrix::frame(text, position)))
+        .collect();
+    assert_eq!(frames(&latches), expected);
+
+    std::fs::remove_dir_all(directory).ok();
+}